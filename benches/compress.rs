@@ -0,0 +1,99 @@
+use bar::ar::Bar;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::{io::Cursor, path::Path};
+
+/// The directory of sample files that every compression method is benchmarked on
+const CORPUS: &str = "./benches/test-in";
+
+/// Every compression method and quality that files can be packed with
+const METHODS: &[&str] = &[
+    "none",
+    "fast-deflate",
+    "medium-deflate",
+    "high-deflate",
+    "fast-gzip",
+    "medium-gzip",
+    "high-gzip",
+];
+
+/// Get the total size of all files in a directory, recursively
+fn corpus_size(dir: &Path) -> u64 {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            match entry.metadata().unwrap().is_dir() {
+                true => corpus_size(&entry.path()),
+                false => entry.metadata().unwrap().len(),
+            }
+        })
+        .sum()
+}
+
+/// Pack and save the corpus using a compression method, returning the saved archive
+fn pack(method: &str) -> Vec<u8> {
+    let mut bar = Bar::pack(
+        CORPUS,
+        Cursor::new(Vec::new()),
+        method.parse().unwrap(),
+        false,
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    bar.save(&mut out, false).unwrap();
+    out
+}
+
+fn compress_corpus(c: &mut Criterion) {
+    let size = corpus_size(Path::new(CORPUS));
+    let mut group = c.benchmark_group("Barchive compression");
+    group.throughput(Throughput::Bytes(size));
+
+    for method in METHODS {
+        let packed = pack(method).len();
+        println!(
+            "{}: {} bytes -> {} bytes ({:.1}% of original)",
+            method,
+            size,
+            packed,
+            packed as f64 / size as f64 * 100.
+        );
+        group.bench_with_input(BenchmarkId::from_parameter(method), method, |b, method| {
+            b.iter(|| black_box(pack(method)))
+        });
+    }
+    group.finish();
+}
+
+/// Read and decompress every file of a saved archive, returning the number of decompressed bytes
+fn unpack(archive: &[u8]) -> u64 {
+    let mut bar = Bar::unpack_bytes(archive).unwrap();
+    let paths = bar
+        .files_sorted_by_size()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    paths
+        .iter()
+        .map(|path| std::io::copy(&mut bar.open(path).unwrap(), &mut std::io::sink()).unwrap())
+        .sum()
+}
+
+fn decompress_corpus(c: &mut Criterion) {
+    let size = corpus_size(Path::new(CORPUS));
+    let mut group = c.benchmark_group("Barchive decompression");
+    group.throughput(Throughput::Bytes(size));
+
+    //Archives are packed before measuring so only reading file data is timed
+    for method in METHODS {
+        let packed = pack(method);
+        assert_eq!(unpack(&packed), size);
+        group.bench_with_input(BenchmarkId::from_parameter(method), &packed, |b, packed| {
+            b.iter(|| black_box(unpack(packed)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(compress, compress_corpus, decompress_corpus);
+criterion_main!(compress);
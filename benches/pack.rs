@@ -1,16 +1,20 @@
 use bar::ar::Bar;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
 use std::io::Cursor;
 
-fn pack_nocompress(c: &mut Criterion) {
-    c.bench_function("Barchive pack (no compression)", move |b| {
+/// Benchmark packing the test directory with a compression method and saving it to `out`, then unpacking
+/// the saved archive
+fn pack_with(c: &mut Criterion, name: &str, compression: &str, out: &'static str) {
+    c.bench_function(&format!("Barchive pack ({})", name), move |b| {
         b.iter_with_setup(
-            || std::io::BufWriter::new(std::fs::File::create("./benches/test-out.bar").unwrap()),
+            || std::io::BufWriter::new(std::fs::File::create(out).unwrap()),
             |mut file| {
                 let mut bar = black_box(Bar::pack(
                     "./benches/test-in",
                     Cursor::new(vec![0u8; 2048]),
-                    "none".parse().unwrap(),
+                    compression.parse().unwrap(),
                     false,
                 ))
                 .unwrap();
@@ -19,10 +23,129 @@ fn pack_nocompress(c: &mut Criterion) {
         )
     });
 
-    c.bench_function("Barchive unpack (no compression)", move |b| {
-        b.iter(|| black_box(Bar::unpack("./benches/test-out.bar").unwrap()))
+    c.bench_function(&format!("Barchive unpack ({})", name), move |b| {
+        b.iter(|| black_box(Bar::unpack(out).unwrap()))
+    });
+}
+
+fn pack_nocompress(c: &mut Criterion) {
+    pack_with(c, "no compression", "none", "./benches/test-out.bar");
+}
+
+fn pack_gzip(c: &mut Criterion) {
+    pack_with(c, "gzip", "medium-gzip", "./benches/test-out-gzip.bar");
+}
+
+fn extract_buffer_sizes(c: &mut Criterion) {
+    let mut bar = Bar::pack(
+        "./benches/test-in",
+        Cursor::new(Vec::new()),
+        "none".parse().unwrap(),
+        false,
+    )
+    .unwrap();
+    let mut saved = Vec::new();
+    bar.save(&mut saved, false).unwrap();
+
+    let mut group = c.benchmark_group("Barchive extract buffer size");
+    group.throughput(Throughput::Bytes(saved.len() as u64));
+    for (name, size) in [("8KB", 8 * 1024), ("1MB", 1024 * 1024)] {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &size, |b, &size| {
+            b.iter_with_setup(
+                || tempfile::tempdir().unwrap(),
+                |out| {
+                    let mut bar = Bar::unpack_reader(Cursor::new(saved.as_slice()))
+                        .unwrap()
+                        .with_io_buffer_size(size);
+                    black_box(bar.save_unpacked(out.path(), false)).unwrap();
+                },
+            )
+        });
+    }
+    group.finish();
+}
+
+/// The number of files in the directory of tiny files
+const TINY_FILES: usize = 5000;
+
+/// Create a directory of many files that are each smaller than 1KB, split into subdirectories
+fn tiny_files() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..TINY_FILES {
+        let sub = dir.path().join(format!("dir{}", i % 50));
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(
+            sub.join(format!("file{}.txt", i)),
+            format!("Tiny file number {} ", i).repeat(1 + i % 40),
+        )
+        .unwrap();
+    }
+    dir
+}
+
+fn pack_tiny_files(c: &mut Criterion) {
+    let dir = tiny_files();
+    let mut group = c.benchmark_group("Barchive pack tiny files");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(TINY_FILES as u64));
+    group.bench_function("memory", |b| {
+        b.iter(|| {
+            black_box(Bar::pack(
+                dir.path(),
+                Cursor::new(Vec::new()),
+                "none".parse().unwrap(),
+                false,
+            ))
+            .unwrap()
+        })
+    });
+    group.bench_function("tempfile", |b| {
+        b.iter_with_setup(
+            || tempfile::tempfile().unwrap(),
+            |back| black_box(Bar::pack(dir.path(), back, "none".parse().unwrap(), false)).unwrap(),
+        )
+    });
+    group.finish();
+}
+
+/// The size of the file in the large file benchmark
+const LARGE_FILE_SIZE: usize = 256 * 1024 * 1024;
+
+fn save_large_file(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let data = (0..LARGE_FILE_SIZE)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write(dir.path().join("large.bin"), data).unwrap();
+
+    //Packing only copies the file into the backing storage, saving is what copies or compresses file data
+    let mut group = c.benchmark_group("Barchive save large file");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(LARGE_FILE_SIZE as u64));
+    group.bench_function("none", |b| {
+        b.iter_batched(
+            || {
+                Bar::pack(
+                    dir.path(),
+                    tempfile::tempfile().unwrap(),
+                    "none".parse().unwrap(),
+                    false,
+                )
+                .unwrap()
+            },
+            |mut bar| black_box(bar.save(&mut std::io::sink(), false)).unwrap(),
+            BatchSize::PerIteration,
+        )
     });
+    group.finish();
 }
 
-criterion_group!(pack, pack_nocompress);
+criterion_group!(
+    pack,
+    pack_nocompress,
+    pack_gzip,
+    extract_buffer_sizes,
+    pack_tiny_files,
+    save_large_file
+);
 criterion_main!(pack);
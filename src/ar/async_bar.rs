@@ -0,0 +1,114 @@
+//! The `async_bar` module contains the [AsyncBar] struct, an async wrapper over [Bar] for use with tokio.
+//! Reading and writing archives is blocking and compression is CPU bound, so every operation is run on
+//! tokio's blocking thread pool
+
+use super::{Bar, BarResult};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// An `AsyncBar` wraps a file-backed [Bar] so that it can be saved and read from async code without
+/// blocking the async runtime's worker threads. Clones of an `AsyncBar` share the same archive
+#[derive(Clone)]
+pub struct AsyncBar {
+    /// The archive that operations are run on, locked by one blocking task at a time
+    bar: Arc<Mutex<Bar<std::fs::File>>>,
+}
+
+impl AsyncBar {
+    /// Wrap an archive that was already packed or unpacked
+    pub fn new(bar: Bar<std::fs::File>) -> Self {
+        Self {
+            bar: Arc::new(Mutex::new(bar)),
+        }
+    }
+
+    /// Run `op` with the archive on the blocking thread pool
+    async fn blocking<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&mut Bar<std::fs::File>) -> BarResult<T> + Send + 'static,
+    ) -> BarResult<T> {
+        let bar = self.bar.clone();
+        tokio::task::spawn_blocking(move || op(&mut bar.lock().unwrap()))
+            .await
+            .map_err(io::Error::from)?
+    }
+
+    /// Unpack the archive file at `path`, see [unpack](fn@Bar::unpack)
+    pub async fn unpack(path: impl AsRef<Path>) -> BarResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let bar = tokio::task::spawn_blocking(move || Bar::unpack(path))
+            .await
+            .map_err(io::Error::from)??;
+        Ok(Self::new(bar))
+    }
+
+    /// Save the archive to `writer`, compressing files as needed. The archive is written to memory on the
+    /// blocking thread pool and then written to `writer` asynchronously
+    pub async fn save<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> BarResult<()> {
+        let saved = self
+            .blocking(|bar| {
+                let mut saved = Vec::new();
+                bar.save(&mut saved, false)?;
+                Ok(saved)
+            })
+            .await?;
+        writer.write_all(&saved).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Read and decompress the data of the file at `path` in the archive
+    pub async fn read_file(&self, path: impl AsRef<Path>) -> BarResult<Vec<u8>> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        self.blocking(move |bar| {
+            let mut data = Vec::new();
+            io::copy(&mut bar.open(path)?, &mut data)?;
+            Ok(data)
+        })
+        .await
+    }
+
+    /// Get the wrapped archive back, returning `None` if the archive is shared with another `AsyncBar`
+    pub fn into_inner(self) -> Option<Bar<std::fs::File>> {
+        Arc::try_unwrap(self.bar)
+            .ok()
+            .map(|bar| bar.into_inner().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_async_save_unpack() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Async data ".repeat(100)).unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "Nested async data").unwrap();
+
+        let back = tempfile::tempfile().unwrap();
+        let bar = Bar::pack(dir.path(), back, "high-gzip".parse().unwrap(), false).unwrap();
+        let bar = AsyncBar::new(bar);
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("async.bar");
+        let mut file = tokio::fs::File::create(&path).await.unwrap();
+        bar.save(&mut file).await.unwrap();
+        drop(file);
+
+        let bar = AsyncBar::unpack(&path).await.unwrap();
+        assert_eq!(
+            bar.read_file("a.txt").await.unwrap(),
+            "Async data ".repeat(100).into_bytes()
+        );
+        assert_eq!(
+            bar.read_file("sub/b.txt").await.unwrap(),
+            b"Nested async data"
+        );
+        assert!(bar.read_file("missing.txt").await.is_err());
+        assert!(bar.into_inner().is_some());
+    }
+}
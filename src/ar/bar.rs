@@ -3,14 +3,16 @@
 
 use super::entry;
 use super::entry::Entry;
-use byteorder::{LittleEndian, ReadBytesExt};
-use flate2::read::{DeflateDecoder, GzDecoder};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
 use rmpv::Value;
 use std::{
     cell::RefCell,
     collections::HashMap,
+    convert::TryFrom,
     fmt,
     io::{self, Read, Seek, SeekFrom, Write},
     path,
@@ -28,6 +30,29 @@ pub struct Bar<S: Read + Seek> {
 
     /// The header data
     pub(super) header: Header,
+
+    /// If the header should be compressed with DEFLATE when it is written
+    pub(super) compress_header: bool,
+
+    /// If a redundant copy of the header should be written at the front of the archive when it is saved
+    pub(super) redundant_header: bool,
+
+    /// The size of the buffers used when writing saved archives and unpacked files
+    pub(super) io_buffer_size: usize,
+
+    /// Paths of special files like FIFOs, sockets, and devices that were skipped when packing
+    pub(super) skipped: Vec<std::path::PathBuf>,
+
+    /// The largest number of bytes that the data of a file may decompress to when it is read, or `None`
+    /// for no limit
+    pub(super) decompression_limit: Option<u64>,
+
+    /// If the hash trailer should be written again when the archive file is updated in place
+    pub(super) hash_updates: bool,
+
+    /// The directory that temporary files are created in while saving, or `None` for the system's temporary
+    /// directory
+    pub(super) temp_dir: Option<std::path::PathBuf>,
 }
 
 impl<S: Read + Seek> fmt::Debug for Bar<S> {
@@ -42,8 +67,90 @@ pub struct Header {
     /// Metadata about the entire archive
     pub meta: Meta,
 
+    /// A free-form description of the archive, stored with the archive's metadata
+    pub description: Option<String>,
+
+    /// The number of files and directories in the archive when it was last saved, or `None` if the archive
+    /// was read from a header without counts or has not been saved
+    pub counts: Option<EntryCounts>,
+
+    /// The absolute path of the directory that the archive was packed from, or `None` if the archive was not
+    /// packed from a directory or was packed before the path was recorded
+    pub source_path: Option<String>,
+
+    /// When the archive was packed, in seconds since the unix epoch
+    pub created: Option<i64>,
+
     /// The root directory of the header
     pub root: Dir,
+
+    /// The location of the compressed data section if this is a solid archive, or `None` if every file is
+    /// compressed individually
+    pub solid: Option<Solid>,
+}
+
+/// The byte ranges of each section of an archive, see [raw_layout](fn@Bar::raw_layout)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawLayout {
+    /// The redundant copy of the header at the start of the archive including its magic bytes and size, if
+    /// the archive has one
+    pub front_header: Option<std::ops::Range<u64>>,
+
+    /// The data of every file
+    pub data: std::ops::Range<u64>,
+
+    /// The header, which may be compressed
+    pub header: std::ops::Range<u64>,
+
+    /// The size of everything before the header, stored after the header
+    pub data_size: std::ops::Range<u64>,
+
+    /// The SHA-256 hash of everything before it followed by its magic bytes, if the archive has one
+    pub hash: Option<std::ops::Range<u64>>,
+}
+
+/// The number of files and directories in an archive, see [entry_counts](fn@Bar::entry_counts)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EntryCounts {
+    /// The number of files in every directory
+    pub files: u64,
+
+    /// The number of directories, not counting the root directory
+    pub dirs: u64,
+}
+
+impl EntryCounts {
+    /// Count the files and directories in `dir` and all of its subdirectories
+    pub fn count(dir: &entry::Dir) -> Self {
+        dir.entries()
+            .fold(Self::default(), |counts, entry| match entry {
+                Entry::File(_) => Self {
+                    files: counts.files + 1,
+                    ..counts
+                },
+                Entry::Dir(dir) => {
+                    let nested = Self::count(dir);
+                    Self {
+                        files: counts.files + nested.files,
+                        dirs: counts.dirs + nested.dirs + 1,
+                    }
+                }
+            })
+    }
+}
+
+/// The location of the single compressed stream that holds the data of every file in a solid archive.
+/// File offsets and sizes in a solid archive refer to the decompressed stream
+#[derive(Debug, Clone, Copy)]
+pub struct Solid {
+    /// The compression method used for the whole data section
+    pub compression: CompressType,
+
+    /// The offset of the compressed stream in the archive
+    pub off: u64,
+
+    /// The size of the compressed stream in bytes
+    pub size: u64,
 }
 
 /// The `BarErr` enum enumerates all possible errors that can occur when reading from or writing to a
@@ -61,6 +168,9 @@ pub enum BarErr {
     #[error("An error occurred when encoding header of bar file: {0}")]
     InvalidMsgPackEncode(#[from] rmpv::encode::Error),
 
+    #[error("An error occurred when reading or writing a zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
     #[error("The archive header format is invalid: {0}")]
     InvalidHeaderFormat(String),
 
@@ -72,6 +182,45 @@ pub enum BarErr {
 
     #[error("The specified entry at path {0} does not exist")]
     NoEntry(String),
+
+    #[error("The entry at path {0} is not a directory")]
+    NotADirectory(String),
+
+    #[error("An entry already exists at path {0}")]
+    EntryExists(String),
+
+    #[error("The archive does not end with a hash to verify")]
+    NoArchiveHash,
+
+    #[error("The archive's hash does not match its contents, the archive is corrupted")]
+    ArchiveHashMismatch,
+
+    #[error("The name of the file at path {} is not valid UTF-8", .0.display())]
+    NonUtf8Name(path::PathBuf),
+
+    #[error("The data of file {0} is stored in the base archive, which is needed to read it")]
+    InheritedFile(String),
+
+    #[error("Invalid entry name: {0}")]
+    InvalidName(#[from] entry::NameError),
+
+    #[error("Files can't be added to a solid archive without saving the whole archive again")]
+    SolidArchive,
+
+    #[error("The data of file {0} decompresses to more than the limit of {1} bytes")]
+    DecompressionLimitExceeded(String, u64),
+
+    #[error("Packing was cancelled")]
+    Cancelled,
+
+    #[error("Invalid pattern {0} in ignore file: {1}")]
+    InvalidIgnorePattern(String, String),
+
+    #[error("The archive could not be decrypted with the given key")]
+    WrongKey,
+
+    #[error("The data of file {0} is too large to store, file data can be at most 4 GiB")]
+    FileTooLarge(String),
 }
 
 /// The `BarResult<T>` type is a result with an Err variant of [BarErr]
@@ -86,6 +235,44 @@ const OFFSET: u8 = 5;
 const SIZE: u8 = 6;
 const USED: u8 = 8;
 const COMPRESSMETHOD: u8 = 9;
+const ACCESSCOUNT: u8 = 10;
+const LASTACCESS: u8 = 11;
+const MODIFIED: u8 = 12;
+const THUMBNAIL: u8 = 13;
+const INHERITED: u8 = 14;
+const DESCRIPTION: u8 = 15;
+const FILECOUNT: u8 = 16;
+const DIRCOUNT: u8 = 17;
+const NORMALIZED: u8 = 18;
+const SOURCEPATH: u8 = 19;
+const CREATED: u8 = 20;
+
+/// A flag byte written before a DEFLATE compressed header. MessagePack never uses this byte, so it can't be
+/// mistaken for the start of an uncompressed header
+pub(super) const COMPRESSED_HEADER: u8 = 0xc1;
+
+/// The name of a file listing glob patterns of entries to leave out when packing the directory that contains
+/// it, see [read_ignore_file]
+pub const IGNORE_FILE: &str = ".barignore";
+
+/// The default size of the buffers used when writing archives and unpacked files, see
+/// [with_io_buffer_size](fn@Bar::with_io_buffer_size)
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The default largest number of bytes that the data of a single file may decompress to, see
+/// [set_decompression_limit](fn@Bar::set_decompression_limit)
+pub const DEFAULT_DECOMPRESSION_LIMIT: u64 = 16 * 1024 * 1024 * 1024;
+
+/// The byte order of the integers written around the header: the size of the file data after the header and
+/// the size of the front header. The header itself is MessagePack, which is always big endian
+pub(super) type Endian = LittleEndian;
+
+/// Magic bytes at the end of an archive, written after a SHA-256 hash of everything before the hash
+pub(super) const HASH_TRAILER_MAGIC: &[u8; 8] = b"BARHASH\0";
+
+/// Magic bytes at the start of an archive that has a redundant copy of its header written before the file
+/// data, followed by the size of the front header as a little endian `u64`
+pub(super) const FRONT_HEADER_MAGIC: &[u8; 8] = b"BARFRONT";
 
 pub(super) fn ser_meta(meta: &Meta) -> Value {
     use rmpv::{Integer, Utf8String};
@@ -105,6 +292,30 @@ pub(super) fn ser_meta(meta: &Meta) -> Value {
             Value::String(Utf8String::from(meta.note.clone().unwrap())),
         ))
     }
+    if meta.access_count > 0 {
+        map.push((
+            Value::Integer(Integer::from(ACCESSCOUNT)),
+            Value::Integer(Integer::from(meta.access_count)),
+        ))
+    }
+    if let Some(last_access) = meta.last_access {
+        map.push((
+            Value::Integer(Integer::from(LASTACCESS)),
+            Value::Integer(Integer::from(last_access)),
+        ))
+    }
+    if let Some(modified) = meta.modified {
+        map.push((
+            Value::Integer(Integer::from(MODIFIED)),
+            Value::Integer(Integer::from(modified)),
+        ))
+    }
+    if let Some(ref thumbnail) = meta.thumbnail {
+        map.push((
+            Value::Integer(Integer::from(THUMBNAIL)),
+            Value::Binary(thumbnail.clone()),
+        ))
+    }
 
     Value::Map(map)
 }
@@ -119,23 +330,148 @@ pub(super) fn ser_entry(entry: &Entry) -> Value {
 pub(super) fn ser_direntry(dir: &entry::Dir) -> Value {
     Value::Array(vec![
         ser_meta(&dir.meta.borrow()),
-        Value::Array(
-            dir.data
-                .iter()
-                .map(|(_, val)| ser_entry(val))
-                .collect::<Vec<Value>>(),
-        ),
+        Value::Array(dir.entries_sorted().map(ser_entry).collect::<Vec<Value>>()),
     ])
 }
 
 pub(super) fn ser_header(header: &Header) -> Value {
-    Value::Array(vec![ser_meta(&header.meta), ser_direntry(&header.root)])
+    let mut meta = ser_meta(&header.meta);
+    if let Value::Map(map) = &mut meta {
+        if let Some(description) = &header.description {
+            map.push((
+                Value::Integer(rmpv::Integer::from(DESCRIPTION)),
+                Value::String(rmpv::Utf8String::from(description.clone())),
+            ));
+        }
+        if let Some(source_path) = &header.source_path {
+            map.push((
+                Value::Integer(rmpv::Integer::from(SOURCEPATH)),
+                Value::String(rmpv::Utf8String::from(source_path.clone())),
+            ));
+        }
+        if let Some(created) = header.created {
+            map.push((
+                Value::Integer(rmpv::Integer::from(CREATED)),
+                Value::Integer(rmpv::Integer::from(created)),
+            ));
+        }
+        let counts = EntryCounts::count(&header.root);
+        map.push((
+            Value::Integer(rmpv::Integer::from(FILECOUNT)),
+            Value::Integer(rmpv::Integer::from(counts.files)),
+        ));
+        map.push((
+            Value::Integer(rmpv::Integer::from(DIRCOUNT)),
+            Value::Integer(rmpv::Integer::from(counts.dirs)),
+        ));
+    }
+    let mut vals = vec![meta, ser_direntry(&header.root)];
+    if let Some(ref solid) = header.solid {
+        vals.push(ser_solid(solid));
+    }
+    Value::Array(vals)
+}
+
+/// Create a map value from the location of a solid archive's compressed stream
+pub(super) fn ser_solid(solid: &Solid) -> Value {
+    use rmpv::{Integer, Utf8String};
+    Value::Map(vec![
+        (
+            Value::Integer(Integer::from(OFFSET)),
+            Value::Integer(Integer::from(solid.off)),
+        ),
+        (
+            Value::Integer(Integer::from(SIZE)),
+            Value::Integer(Integer::from(solid.size)),
+        ),
+        (
+            Value::Integer(Integer::from(COMPRESSMETHOD)),
+            Value::String(Utf8String::from(solid.compression.to_string())),
+        ),
+    ])
+}
+
+/// Serialize a header and write it to a writer, prefixing it with a flag byte and compressing it if
+/// `compress` is `true`
+pub(super) fn write_header(
+    header: &Header,
+    compress: bool,
+    writer: &mut impl Write,
+) -> BarResult<()> {
+    let header = ser_header(header);
+    match compress {
+        true => {
+            writer.write_u8(COMPRESSED_HEADER)?;
+            let mut encoder = DeflateEncoder::new(writer, flate2::Compression::best());
+            rmpv::encode::write_value(&mut encoder, &header)?;
+            encoder.finish()?;
+        }
+        false => rmpv::encode::write_value(writer, &header)?,
+    }
+    Ok(())
+}
+
+/// Serialize a header with every data offset moved forward by the size of the front header, returning the
+/// shifted header and the front header bytes padded to their final size. Offsets are written as integers
+/// that grow with their value, so the header is serialized until the space reserved for it fits it
+pub(super) fn front_header(header: &Header, compress: bool) -> BarResult<(Header, Vec<u8>)> {
+    let mut reserved = 0;
+    loop {
+        let mut shifted = header.clone();
+        let shift = (FRONT_HEADER_MAGIC.len() + 8 + reserved) as u64;
+        match shifted.solid {
+            //Offsets of files in solid archives are into the decompressed stream, so only the stream moves
+            Some(ref mut solid) => solid.off += shift,
+            None => shift_offsets(&mut shifted.root, shift),
+        }
+        let mut bytes = Vec::with_capacity(reserved);
+        write_header(&shifted, compress, &mut bytes)?;
+        if bytes.len() <= reserved {
+            bytes.resize(reserved, 0);
+            return Ok((shifted, bytes));
+        }
+        reserved = bytes.len();
+    }
+}
+
+/// Move the data offset of every file in a directory and its subdirectories forward by `by` bytes
+fn shift_offsets(dir: &mut entry::Dir, by: u64) {
+    for entry in dir.data.values_mut() {
+        match entry {
+            Entry::File(file) if file.inherited => (),
+            Entry::File(file) => file.off += by,
+            Entry::Dir(dir) => shift_offsets(dir, by),
+        }
+    }
+}
+
+/// Remove every file from a directory and its subdirectories whose data doesn't fit in the first `len`
+/// bytes of the archive, adding the paths of removed files to `lost`
+pub(super) fn remove_lost(
+    dir: &mut entry::Dir,
+    path: &path::Path,
+    len: u64,
+    lost: &mut Vec<String>,
+) {
+    dir.data.retain(|name, entry| match entry {
+        Entry::File(file) => {
+            let fits = file.inherited || file.off + file.size as u64 <= len;
+            if !fits {
+                lost.push(path.join(name).display().to_string());
+            }
+            fits
+        }
+        Entry::Dir(dir) => {
+            remove_lost(dir, &path.join(name), len, lost);
+            true
+        }
+    });
 }
 
 /// Create a file value from a `File` entry
 pub(super) fn ser_fileentry(file: &entry::File) -> Value {
     use rmpv::{Integer, Utf8String};
-    Value::Map(vec![
+    let mut map = vec![
         (
             Value::Integer(Integer::from(OFFSET)),
             Value::Integer(Integer::from(file.off)),
@@ -152,187 +488,388 @@ pub(super) fn ser_fileentry(file: &entry::File) -> Value {
             Value::Integer(Integer::from(COMPRESSMETHOD)),
             Value::String(Utf8String::from(file.compression.to_string())),
         ),
-    ])
+    ];
+    if file.inherited {
+        map.push((
+            Value::Integer(Integer::from(INHERITED)),
+            Value::Boolean(true),
+        ))
+    }
+    if file.normalized {
+        map.push((
+            Value::Integer(Integer::from(NORMALIZED)),
+            Value::Boolean(true),
+        ))
+    }
+
+    Value::Map(map)
 }
 
-impl Bar<io::Cursor<Vec<u8>>> {
-    /// Create a new `Bar` archive with an in-memory `Vec` as backing storage
-    #[inline]
-    #[must_use]
-    pub fn new(name: impl ToString) -> Self {
-        Self {
-            data: io::Cursor::new(Vec::new()),
-            header: Header {
-                meta: Meta {
-                    name: name.to_string(),
-                    ..Default::default()
-                },
-                root: entry::Dir {
-                    meta: RefCell::new(Meta {
-                        name: "root".to_owned(),
-                        ..Default::default()
-                    }),
-                    data: HashMap::new(),
-                },
+/// Replace `$VAR` and `${VAR}` in `path` with the values of environment variables, leaving variables that
+/// aren't set in the environment as they are
+fn expand_env(path: &str) -> String {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
             },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+
+        match std::env::var(name) {
+            Ok(val) if !name.is_empty() => expanded.push_str(&val),
+            _ => expanded.push_str(&rest[start..start + 1 + len]),
         }
+        rest = &after[len..];
     }
+    expanded.push_str(rest);
+    expanded
 }
 
-impl<S: Read + Seek> Bar<S> {
-    /// The file name of a metadata file in uncompressed archives
-    pub(super) const ROOT_METADATA_FILE: &'static str = ".__barmeta.msgpack";
-
-    /// Get a hashmap of file paths in the archive to their metadata bincode
-    pub(super) fn all_entry_metadata(&self, path: impl AsRef<path::Path>) -> Value {
-        use rmpv::Utf8String;
-        let mut vec = vec![];
-        fn walk_dir(vec: &mut Vec<(Value, Value)>, dir: &entry::Dir, path: path::PathBuf) {
-            for (name, item) in dir.data.iter() {
-                match item {
-                    Entry::Dir(dir) => {
-                        vec.push((
-                            Value::String(Utf8String::from(path.join(name).to_str().unwrap())),
-                            ser_meta(&dir.meta.borrow()),
-                        ));
-                        walk_dir(vec, dir, path.join(name));
-                    }
-                    Entry::File(file) => vec.push((
-                        Value::String(Utf8String::from(path.join(name).to_str().unwrap())),
-                        ser_meta(&file.meta.borrow()),
-                    )),
-                }
-            }
+/// The number of bytes at the start of a file that are compressed to check if the file is worth compressing
+const COMPRESS_PROBE_SIZE: u64 = 64 * 1024;
+
+/// Compress the first block of `data` with `compression` and return `none` compression if the block
+/// doesn't shrink to 95% of its size or smaller, or `compression` if it does. This catches files that are
+/// already compressed like images, videos, and other archives. `data` is seeked back to its start
+pub(super) fn probe_compression<R: Read + Seek>(
+    data: &mut R,
+    compression: CompressType,
+) -> BarResult<CompressType> {
+    let mut block = Vec::new();
+    data.take(COMPRESS_PROBE_SIZE).read_to_end(&mut block)?;
+    data.seek(SeekFrom::Start(0))?;
+
+    let compressed = match compression {
+        CompressType(_, CompressMethod::None) => return Ok(compression),
+        CompressType(quality, CompressMethod::Deflate) => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), quality);
+            encoder.write_all(&block)?;
+            encoder.finish()?.len()
         }
-        walk_dir(&mut vec, &self.header.root, path.as_ref().to_owned());
-        vec.push((
-            Value::String(Utf8String::from("/")),
-            ser_meta(&self.header.meta),
-        ));
-
-        Value::Map(vec)
-    }
+        CompressType(quality, CompressMethod::Gzip) => {
+            let mut encoder = GzEncoder::new(Vec::new(), quality);
+            encoder.write_all(&block)?;
+            encoder.finish()?.len()
+        }
+    };
+    Ok(match compressed * 100 > block.len() * 95 {
+        true => "none".parse().unwrap(),
+        false => compression,
+    })
+}
 
-    /// Read all entry metadata from a root file when packing a previously unpacked directory
-    pub(super) fn read_all_entry_metadata(
-        file: impl AsRef<std::path::Path>,
-    ) -> BarResult<HashMap<String, Meta>> {
-        let mut data = match std::fs::File::open(file.as_ref()) {
-            Ok(data) => data,
-            Err(_) => {
-                return Ok(HashMap::new());
-            }
-        };
-        let val = rmpv::decode::read_value(&mut data)?;
+/// Compress the first block of `data` at the fastest and best DEFLATE levels and choose a compression for
+/// the whole file from the results: `none` if the block doesn't shrink to 95% of its size or smaller,
+/// `high-deflate` if the best level shrinks the block noticeably more than the fastest level, and
+/// `fast-deflate` otherwise. DEFLATE is always chosen over gzip because it stores the same data without a
+/// gzip header. `data` is seeked back to its start
+pub(super) fn auto_compression<R: Read + Seek>(data: &mut R) -> BarResult<CompressType> {
+    let mut block = Vec::new();
+    data.take(COMPRESS_PROBE_SIZE).read_to_end(&mut block)?;
+    data.seek(SeekFrom::Start(0))?;
+
+    let deflated = |quality| -> io::Result<usize> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), quality);
+        encoder.write_all(&block)?;
+        Ok(encoder.finish()?.len())
+    };
+    let fast = deflated(flate2::Compression::fast())?;
+    let best = deflated(flate2::Compression::best())?;
+    Ok(if best * 100 > block.len() * 95 {
+        "none".parse().unwrap()
+    } else if best * 100 < fast * 95 {
+        "high-deflate".parse().unwrap()
+    } else {
+        "fast-deflate".parse().unwrap()
+    })
+}
 
-        let val = val.as_map().ok_or_else(|| {
-            BarErr::BadMetadataFile("Header map of paths to metadata is not a map".into())
-        })?;
+/// A function that chooses the compression of a packed file from its path in the archive, its size, and its
+/// data. The file must be seeked back to its start if it is read
+pub(super) type ChooseCompression<'a> =
+    dyn Fn(&std::path::Path, u64, &mut std::fs::File) -> BarResult<CompressType> + 'a;
+
+/// Read the glob patterns in the [IGNORE_FILE] of `dir` if it has one. Patterns match paths relative to
+/// `dir`, and blank lines and lines starting with `#` are skipped. Leading and trailing `/`s are removed, so
+/// a pattern ending with one matches both files and directories
+pub(super) fn read_ignore_file(dir: &path::Path) -> BarResult<Option<globset::GlobSet>> {
+    let list = match std::fs::read_to_string(dir.join(IGNORE_FILE)) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut set = globset::GlobSetBuilder::new();
+    for pattern in list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        let pattern = pattern.trim_matches('/');
+        set.add(
+            globset::Glob::new(pattern)
+                .map_err(|e| BarErr::InvalidIgnorePattern(pattern.to_owned(), e.to_string()))?,
+        );
+    }
+    set.build()
+        .map(Some)
+        .map_err(|e| BarErr::InvalidIgnorePattern(list, e.to_string()))
+}
 
-        let map = val
-            .iter()
-            .map(|(path, meta)| -> BarResult<_> {
-                let path = path.as_str().ok_or_else(|| {
-                    BarErr::BadMetadataFile("The keys for metada's map are not strings".into())
-                })?;
-                let meta = Self::read_meta(meta)?; //Read the metadata
-                Ok((path.to_owned().replace("\\", "/"), meta))
-            })
-            .collect::<BarResult<HashMap<String, Meta>>>()?;
+/// Get the current time in seconds since the unix epoch
+pub(super) fn now() -> Option<i64> {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|time| time.as_secs() as i64)
+}
 
-        Ok(map)
+/// Replace every CRLF line ending in `data` with LF
+pub(super) fn crlf_to_lf(data: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if !(byte == b'\r' && bytes.peek() == Some(&&b'\n')) {
+            converted.push(byte);
+        }
     }
+    converted
+}
 
-    /// Read all files in a directory into a list of [Entry]s, reading metadata files if possible
-    pub(super) fn pack_read_dir<W: Write>(
-        dir: &std::path::Path,
-        off: &mut u64,
-        writer: &mut W,
-        meta_vec: &HashMap<String, Meta>,
-        compress: CompressType,
-        prog: &ProgressBar,
-    ) -> BarResult<Vec<Entry>> {
-        let mut vec = vec![];
+/// Replace every LF line ending in `data` with CRLF
+pub(super) fn lf_to_crlf(data: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(data.len() + data.len() / 32);
+    for &byte in data {
+        if byte == b'\n' {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+    }
+    converted
+}
 
-        for file in std::fs::read_dir(dir)? {
-            let file = file?;
-            prog.set_message(format!("Writing file {} to archive", file.path().display()));
+/// Read the data of a file as it is stored in the archive into `buf`, which must be the size of the file.
+/// Data in a solid archive is read by decompressing the archive's stream up to the end of the file
+pub(super) fn read_stored<R: Read + Seek>(
+    file: &entry::File,
+    back: &mut R,
+    solid: Option<&Solid>,
+    buf: &mut [u8],
+) -> io::Result<()> {
+    match solid {
+        Some(solid) => {
+            back.seek(SeekFrom::Start(solid.off))?;
+            let mut stream = solid_stream(solid, back);
+            io::copy(&mut (&mut stream).take(file.off), &mut io::sink())?;
+            stream.read_exact(buf)
+        }
+        None => {
+            back.seek(SeekFrom::Start(file.off))?;
+            back.read_exact(buf)
+        }
+    }
+}
 
-            let name = file.file_name().to_str().unwrap().to_owned();
+/// Create a reader that decompresses a solid archive's stream from `reader`, which must be positioned at the
+/// start of the stream
+pub(super) fn solid_stream<'a, R: Read + 'a>(solid: &Solid, reader: R) -> Box<dyn Read + 'a> {
+    let stream = reader.take(solid.size);
+    match solid.compression.1 {
+        CompressMethod::Deflate => Box::new(DeflateDecoder::new(stream)),
+        CompressMethod::Gzip => Box::new(MultiGzDecoder::new(stream)),
+        CompressMethod::None => Box::new(stream),
+    }
+}
 
-            if name == Self::ROOT_METADATA_FILE {
-                continue;
+/// Walk a list of directory names starting at `dir`, creating any directories that don't exist yet and
+/// returning the last directory in the list
+pub(super) fn create_dirs<'a>(
+    dir: &'a mut entry::Dir,
+    names: &[String],
+) -> BarResult<&'a mut entry::Dir> {
+    match names.split_first() {
+        Some((name, rest)) => {
+            entry::validate_name(name)?;
+            let entry = dir.data.entry(name.clone()).or_insert_with(|| {
+                Entry::Dir(entry::Dir {
+                    meta: RefCell::new(Meta::new(name.clone())),
+                    data: HashMap::new(),
+                })
+            });
+            match entry {
+                Entry::Dir(dir) => create_dirs(dir, rest),
+                Entry::File(_) => Err(BarErr::NotADirectory(name.clone())),
             }
+        }
+        None => Ok(dir),
+    }
+}
 
-            //See if we have any metadata files to go with this one
-            let meta = match meta_vec.get(&file.path().to_str().unwrap().replace("\\", "/")) {
-                Some(meta) => meta.clone(),
-                None => Meta {
-                    name: name.clone(),
-                    ..Default::default()
-                },
-            };
-
-            match file.metadata().unwrap().is_dir() {
-                true => {
-                    let directory = entry::Dir {
-                        meta: RefCell::new(meta),
-                        data: Self::pack_read_dir(
-                            &file.path(),
-                            off,
-                            writer,
-                            meta_vec,
-                            compress,
-                            prog,
-                        )?
-                        .into_iter()
-                        .map(|entry| (entry.name(), entry))
-                        .collect(),
-                    };
-                    vec.push(Entry::Dir(directory));
-                }
-                false => {
-                    let read_prog = match prog.is_hidden() {
-                        true => ProgressBar::hidden(),
-                        false => ProgressBar::new(0).with_style(
-                            ProgressStyle::default_bar()
-                                .template(
-                                    "[{bar}] {bytes}/{total_bytes} {binary_bytes_per_sec} {msg}",
-                                )
-                                .progress_chars("=>-"),
-                        ),
-                    };
-
-                    let mut data = std::fs::File::open(file.path())?; //Open the file at the given location
-                    let size = data.metadata()?.len();
+/// Remove the entry at `path` from `root` and its subdirectories and return it
+pub(super) fn remove_entry(root: &mut entry::Dir, path: &path::Path) -> BarResult<Entry> {
+    let mut names = path_names(path);
+    let name = names
+        .pop()
+        .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))?;
+    let parent = match names.is_empty() {
+        true => Some(root),
+        false => root
+            .entry_mut(names.join("/"))
+            .and_then(|entry| entry.as_dir_mut()),
+    };
+    parent
+        .and_then(|parent| parent.data.remove(&name))
+        .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))
+}
 
-                    let file = entry::File {
-                        compression: compress,
-                        off: *off,
-                        size: size as u32,
-                        meta: RefCell::new(meta),
-                    };
-                    *off += size;
-                    std::io::copy(&mut read_prog.wrap_read(&mut data), writer)?;
-                    read_prog.finish_and_clear();
-                    vec.push(Entry::File(file))
-                }
-            }
+/// Split a path into its normal components, ignoring root and `.` / `..`
+/// components
+pub(super) fn path_names(path: &path::Path) -> Vec<String> {
+    path.components()
+        .filter_map(|c| match c {
+            path::Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
 
-            prog.tick();
-        }
-        Ok(vec)
-    }
+impl TryFrom<&Value> for Meta {
+    type Error = BarErr;
 
-    /// Read a file entry from the header
-    pub(super) fn read_file_entry(val: &Value) -> BarResult<entry::File> {
-        let val = val.as_map().ok_or_else(|| {
-            BarErr::InvalidHeaderFormat(format!("File field is not an map, it is a {}", val))
-        })?;
-        let val = val
-            .iter()
+    /// Read a metadata map from a header value
+    fn try_from(val: &Value) -> BarResult<Self> {
+        match val {
+            Value::Map(map) => {
+                let map = map
+                    .iter()
+                    .map(|(key, val)| match key {
+                        Value::Integer(num) if num.is_u64() => {
+                            Ok((num.as_u64().unwrap(), val.clone()))
+                        }
+                        other => Err(BarErr::InvalidHeaderFormat(format!(
+                            "Key for metadata field is not an integer value, it is {}",
+                            other
+                        ))),
+                    })
+                    .collect::<BarResult<HashMap<u64, Value>>>()?;
+                Ok(Meta {
+                    name: map
+                        .get(&(NAME as u64))
+                        .map_or(Result::<_, BarErr>::Ok(None), |val| {
+                            Ok(Some(
+                                val.as_str()
+                                    .ok_or_else(|| {
+                                        BarErr::InvalidHeaderFormat(
+                                            "The NAME field of metadata is not a string".into(),
+                                        )
+                                    })?
+                                    .to_owned(),
+                            ))
+                        })?
+                        .ok_or_else(|| {
+                            BarErr::InvalidHeaderFormat(
+                                "The NAME field of metadata is not present".into(),
+                            )
+                        })?,
+                    used: map
+                        .get(&(USED as u64))
+                        .unwrap_or(&Value::Boolean(false))
+                        .as_bool()
+                        .ok_or_else(|| {
+                            BarErr::InvalidHeaderFormat(
+                                "USED field of metadata is not a boolean".into(),
+                            )
+                        })?,
+                    note: map
+                        .get(&(NOTE as u64))
+                        .map_or(Result::<_, BarErr>::Ok(None), |val| {
+                            Ok(Some(
+                                val.as_str()
+                                    .ok_or_else(|| {
+                                        BarErr::InvalidHeaderFormat(
+                                            "The NOTE field of metadata is not a string".into(),
+                                        )
+                                    })?
+                                    .to_owned(),
+                            ))
+                        })?,
+                    access_count: map.get(&(ACCESSCOUNT as u64)).map_or(
+                        Result::<_, BarErr>::Ok(0),
+                        |val| {
+                            val.as_u64().map(|count| count as u32).ok_or_else(|| {
+                                BarErr::InvalidHeaderFormat(
+                                    "The ACCESSCOUNT field of metadata is not an integer".into(),
+                                )
+                            })
+                        },
+                    )?,
+                    last_access: map.get(&(LASTACCESS as u64)).map_or(
+                        Result::<_, BarErr>::Ok(None),
+                        |val| {
+                            Ok(Some(val.as_i64().ok_or_else(|| {
+                                BarErr::InvalidHeaderFormat(
+                                    "The LASTACCESS field of metadata is not an integer".into(),
+                                )
+                            })?))
+                        },
+                    )?,
+                    modified: map.get(&(MODIFIED as u64)).map_or(
+                        Result::<_, BarErr>::Ok(None),
+                        |val| {
+                            Ok(Some(val.as_i64().ok_or_else(|| {
+                                BarErr::InvalidHeaderFormat(
+                                    "The MODIFIED field of metadata is not an integer".into(),
+                                )
+                            })?))
+                        },
+                    )?,
+                    thumbnail: map.get(&(THUMBNAIL as u64)).map_or(
+                        Result::<_, BarErr>::Ok(None),
+                        |val| {
+                            Ok(Some(
+                                val.as_slice()
+                                    .ok_or_else(|| {
+                                        BarErr::InvalidHeaderFormat(
+                                            "The THUMBNAIL field of metadata is not binary".into(),
+                                        )
+                                    })?
+                                    .to_owned(),
+                            ))
+                        },
+                    )?,
+                })
+            }
+            other => Err(BarErr::InvalidHeaderFormat(format!(
+                "Metadata field is not a map, it is a {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&Value> for entry::File {
+    type Error = BarErr;
+
+    /// Read a file entry from a header value
+    fn try_from(val: &Value) -> BarResult<Self> {
+        let val = val.as_map().ok_or_else(|| {
+            BarErr::InvalidHeaderFormat(format!("File field is not an map, it is a {}", val))
+        })?;
+        let val = val
+            .iter()
             .map(|(key, val)| match key {
-                Value::Integer(num) => Ok((num.as_u64().unwrap(), val.clone())),
+                Value::Integer(num) if num.is_u64() => Ok((num.as_u64().unwrap(), val.clone())),
                 other => Err(BarErr::InvalidHeaderFormat(format!(
                     "Key for metadata field is not an integer value, it is {}",
                     other
@@ -342,7 +879,7 @@ impl<S: Read + Seek> Bar<S> {
         let meta = val.get(&(META as u64)).ok_or_else(|| {
             BarErr::InvalidHeaderFormat("META field not present in FILE entry".into())
         })?;
-        let meta = Self::read_meta(meta)?;
+        let meta = Meta::try_from(meta)?;
 
         let compression = val
             .get(&(COMPRESSMETHOD as u64))
@@ -358,7 +895,21 @@ impl<S: Read + Seek> Bar<S> {
         let compression = entry::CompressType::from_str(compression).map_err(|e| {
             BarErr::InvalidHeaderFormat(format!("Unrecognized compression method {}", e))
         })?;
+        let inherited = val.get(&(INHERITED as u64)).map_or(Ok(false), |val| {
+            val.as_bool().ok_or_else(|| {
+                BarErr::InvalidHeaderFormat("INHERITED field in FILE entry is not a boolean".into())
+            })
+        })?;
+        let normalized = val.get(&(NORMALIZED as u64)).map_or(Ok(false), |val| {
+            val.as_bool().ok_or_else(|| {
+                BarErr::InvalidHeaderFormat(
+                    "NORMALIZED field in FILE entry is not a boolean".into(),
+                )
+            })
+        })?;
         Ok(entry::File {
+            inherited,
+            normalized,
             off: val
                 .get(&(OFFSET as u64))
                 .ok_or_else(|| {
@@ -381,15 +932,19 @@ impl<S: Read + Seek> Bar<S> {
             compression,
         })
     }
+}
+
+impl TryFrom<&Value> for entry::Dir {
+    type Error = BarErr;
 
     /// Read a directory entry from a header value
-    pub(super) fn read_dir_entry(val: &Value) -> BarResult<entry::Dir> {
+    fn try_from(val: &Value) -> BarResult<Self> {
         let val = val.as_array().ok_or_else(|| {
             BarErr::InvalidHeaderFormat(format!("Directory field is not an array, it is a {}", val))
         })?;
-        match (val.get(0), val.get(1)) {
+        match (val.first(), val.get(1)) {
             (Some(meta), Some(files)) => {
-                let meta = Self::read_meta(meta)?;
+                let meta = Meta::try_from(meta)?;
                 let files = files.as_array().ok_or_else(|| {
                     BarErr::InvalidHeaderFormat(format!(
                         "Directory files item is not an array, it is a {}",
@@ -398,7 +953,7 @@ impl<S: Read + Seek> Bar<S> {
                 })?;
                 let files = files
                     .iter()
-                    .map(|val| Self::read_entry(val))
+                    .map(Entry::try_from)
                     .collect::<BarResult<Vec<Entry>>>()?;
                 Ok(entry::Dir {
                     data: files
@@ -414,128 +969,28 @@ impl<S: Read + Seek> Bar<S> {
             ))),
         }
     }
+}
 
-    /// Read a metada map from a value
-    pub(super) fn read_meta(val: &Value) -> BarResult<Meta> {
-        match val {
-            Value::Map(map) => {
-                let map = map
-                    .iter()
-                    .map(|(key, val)| match key {
-                        Value::Integer(num) => Ok((num.as_u64().unwrap(), val.clone())),
-                        other => Err(BarErr::InvalidHeaderFormat(format!(
-                            "Key for metadata field is not an integer value, it is {}",
-                            other
-                        ))),
-                    })
-                    .collect::<BarResult<HashMap<u64, Value>>>()?;
-                Ok(Meta {
-                    name: map
-                        .get(&(NAME as u64))
-                        .map_or(Result::<_, BarErr>::Ok(None), |val| {
-                            Ok(Some(
-                                val.as_str()
-                                    .ok_or_else(|| {
-                                        BarErr::InvalidHeaderFormat(
-                                            "The NAME field of metadata is not a string".into(),
-                                        )
-                                    })?
-                                    .to_owned(),
-                            ))
-                        })?
-                        .ok_or_else(|| {
-                            BarErr::InvalidHeaderFormat(
-                                "The NAME field of metadata is not present".into(),
-                            )
-                        })?,
-                    used: map
-                        .get(&(USED as u64))
-                        .unwrap_or(&Value::Boolean(false))
-                        .as_bool()
-                        .ok_or_else(|| {
-                            BarErr::InvalidHeaderFormat(
-                                "USED field of metadata is not a boolean".into(),
-                            )
-                        })?,
-                    note: map
-                        .get(&(NOTE as u64))
-                        .map_or(Result::<_, BarErr>::Ok(None), |val| {
-                            Ok(Some(
-                                val.as_str()
-                                    .ok_or_else(|| {
-                                        BarErr::InvalidHeaderFormat(
-                                            "The NOTE field of metadata is not a string".into(),
-                                        )
-                                    })?
-                                    .to_owned(),
-                            ))
-                        })?,
-                })
-            }
-            other => Err(BarErr::InvalidHeaderFormat(format!(
-                "Metadata field is not a map, it is a {}",
-                other
-            ))),
-        }
-    }
-
-    /// Get the position in the reader that our header data starts and return
-    /// (file data size, header size)
-    pub(super) fn get_header_pos(data: &mut S) -> BarResult<(u64, u64)> {
-        data.seek(SeekFrom::End(0))?; //Seek to the end of the file, then back 8 bytes
-        let file_size = data.stream_position()?;
-        data.seek(SeekFrom::End(-8))?;
-
-        let data_size = data.read_u64::<LittleEndian>()?;
-        let header_size = (file_size - data_size) - 8;
-        data.seek(SeekFrom::Start(data_size))?;
-
-        Ok((data_size, header_size))
-    }
-
-    /// Read header bytes from the internal reader by seeking to the end and reading the file size
-    pub(super) fn read_header(data: &mut S) -> BarResult<Header> {
-        let (_, header_size) = Self::get_header_pos(data)?;
-        let mut header_bytes = vec![0u8; header_size as usize];
-        data.read_exact(&mut header_bytes)?;
-
-        let header_val = rmpv::decode::read_value(&mut header_bytes.as_slice())?; //Read the value from the header bytes
-        let header_val = header_val.as_array().ok_or_else(|| {
-            BarErr::InvalidHeaderFormat(format!(
-                "The top level header is not an array, it is a {:?}",
-                header_val
-            ))
-        })?;
-        match (header_val.get(0), header_val.get(1)) {
-            (Some(metadata), Some(root)) => {
-                let meta = Self::read_meta(metadata)?; //Get the metadata of the header
-                let dir = Self::read_dir_entry(root)?;
-
-                Ok(Header { meta, root: dir })
-            }
-            _ => Err(BarErr::InvalidHeaderFormat(
-                "The top level header array does not contain four elements".into(),
-            )),
-        }
-    }
+impl TryFrom<&Value> for Entry {
+    type Error = BarErr;
 
     /// Entry: Array [
     /// Boolean (DIR is false, FILE is true),
     /// if DIR <Directory>
-    /// if FILE <File>   
+    /// if FILE <File>
     /// ]
-    pub(super) fn read_entry(val: &Value) -> BarResult<Entry> {
+    fn try_from(val: &Value) -> BarResult<Self> {
         let val = val
             .as_array()
             .ok_or_else(|| BarErr::InvalidHeaderFormat("An entry field is not an array".into()))?;
-        match (val.get(0), val.get(1)) {
+        match (val.first(), val.get(1)) {
             (Some(is_dir), Some(entry)) => {
                 let is_file = is_dir.as_bool().ok_or_else(|| {
                     BarErr::InvalidHeaderFormat("Entry flag is not a boolean".into())
                 })?;
                 match is_file {
-                    true => Ok(Entry::File(Self::read_file_entry(entry)?)),
-                    false => Ok(Entry::Dir(Self::read_dir_entry(entry)?)),
+                    true => Ok(Entry::File(entry::File::try_from(entry)?)),
+                    false => Ok(Entry::Dir(entry::Dir::try_from(entry)?)),
                 }
             }
             _ => Err(BarErr::InvalidHeaderFormat(format!(
@@ -544,67 +999,577 @@ impl<S: Read + Seek> Bar<S> {
             ))),
         }
     }
+}
 
-    /// Save a file's contents to a Writer, optionally decompressing the file's data
-    pub(super) fn save_file(
-        file: &entry::File,
-        writer: &mut impl Write,
-        back: &mut S,
-        decompress: bool,
-        prog: bool,
-    ) -> BarResult<()> {
-        let prog = match prog {
-            true => ProgressBar::new(file.size as u64).with_style(
-                ProgressStyle::default_bar()
-                    .template("[{bar}] {bytes} {binary_bytes_per_sec} {msg}")
-                    .progress_chars("=>-"),
-            ),
-            false => ProgressBar::hidden(),
-        };
+impl TryFrom<&Value> for Solid {
+    type Error = BarErr;
 
-        let mut data = vec![0u8; file.size as usize];
-        back.seek(SeekFrom::Start(file.off))?;
-        prog.wrap_read(back).read_exact(&mut data)?;
-        prog.reset();
+    /// Read the location of a solid archive's compressed stream from a header value
+    fn try_from(val: &Value) -> BarResult<Self> {
+        let val = val.as_map().ok_or_else(|| {
+            BarErr::InvalidHeaderFormat(format!("Solid field is not an map, it is a {}", val))
+        })?;
+        let field = |key: u8, name: &str| {
+            val.iter()
+                .find(|(k, _)| k.as_u64() == Some(key as u64))
+                .map(|(_, v)| v)
+                .ok_or_else(|| {
+                    BarErr::InvalidHeaderFormat(format!(
+                        "{} field not present in solid field",
+                        name
+                    ))
+                })
+        };
 
-        prog.set_message(format!("Saving unpacked file {}", file.meta.borrow().name));
+        let compression = field(COMPRESSMETHOD, "COMPRESSMETHOD")?
+            .as_str()
+            .ok_or_else(|| {
+                BarErr::InvalidHeaderFormat(
+                    "COMPRESSMETHOD field in solid field is not a string".into(),
+                )
+            })?;
+        let compression = entry::CompressType::from_str(compression).map_err(|e| {
+            BarErr::InvalidHeaderFormat(format!("Unrecognized compression method {}", e))
+        })?;
+        Ok(Solid {
+            compression,
+            off: field(OFFSET, "OFFSET")?.as_u64().ok_or_else(|| {
+                BarErr::InvalidHeaderFormat("OFFSET field in solid field is not a u64".into())
+            })?,
+            size: field(SIZE, "SIZE")?.as_u64().ok_or_else(|| {
+                BarErr::InvalidHeaderFormat("SIZE field in solid field is not a u64".into())
+            })?,
+        })
+    }
+}
 
-        let bytes = match decompress {
-            true => match file.compression {
-                CompressType(_, CompressMethod::Deflate) => {
-                    let mut encoder = DeflateDecoder::new(data.as_slice());
+impl TryFrom<&Value> for Header {
+    type Error = BarErr;
 
-                    let mut decoded = Vec::with_capacity(file.size as usize);
-                    encoder.read_to_end(&mut decoded)?;
-                    drop(data);
-                    decoded
-                }
-                CompressType(_, CompressMethod::Gzip) => {
-                    let mut encoder = GzDecoder::new(data.as_slice());
-                    let mut decoded = Vec::with_capacity(file.size as usize);
-                    encoder.read_to_end(&mut decoded)?;
-                    drop(data);
-                    decoded
-                }
-                CompressType(_, CompressMethod::None) => data,
-            },
-            false => data,
+    /// Read the top level header array containing the archive's metadata, root directory, and the location
+    /// of the compressed stream if the archive is solid
+    fn try_from(val: &Value) -> BarResult<Self> {
+        let val = val.as_array().ok_or_else(|| {
+            BarErr::InvalidHeaderFormat(format!(
+                "The top level header is not an array, it is a {:?}",
+                val
+            ))
+        })?;
+        //Get a field that is stored with the archive's metadata
+        let field = |metadata: &Value, key: u8| {
+            metadata
+                .as_map()?
+                .iter()
+                .find(|(k, _)| k.as_u64() == Some(key as u64))
+                .map(|(_, val)| val.clone())
         };
-
-        io::copy(&mut bytes.as_slice(), &mut prog.wrap_write(writer))?;
-        prog.finish_and_clear();
-
-        Ok(())
+        let string_field = |metadata: &Value, key: u8, name: &str| {
+            field(metadata, key)
+                .map(|val| {
+                    val.as_str().map(str::to_owned).ok_or_else(|| {
+                        BarErr::InvalidHeaderFormat(format!(
+                            "The {} field of the header is not a string",
+                            name
+                        ))
+                    })
+                })
+                .transpose()
+        };
+        match (val.first(), val.get(1)) {
+            (Some(metadata), Some(root)) => Ok(Header {
+                meta: Meta::try_from(metadata)?, //Get the metadata of the header
+                description: string_field(metadata, DESCRIPTION, "DESCRIPTION")?,
+                source_path: string_field(metadata, SOURCEPATH, "SOURCEPATH")?,
+                created: field(metadata, CREATED)
+                    .map(|val| {
+                        val.as_i64().ok_or_else(|| {
+                            BarErr::InvalidHeaderFormat(
+                                "The CREATED field of the header is not an integer".into(),
+                            )
+                        })
+                    })
+                    .transpose()?,
+                counts: {
+                    let count = |key: u8| {
+                        metadata
+                            .as_map()?
+                            .iter()
+                            .find(|(k, _)| k.as_u64() == Some(key as u64))?
+                            .1
+                            .as_u64()
+                    };
+                    match (count(FILECOUNT), count(DIRCOUNT)) {
+                        (Some(files), Some(dirs)) => Some(EntryCounts { files, dirs }),
+                        _ => None,
+                    }
+                },
+                root: entry::Dir::try_from(root)?,
+                solid: val.get(2).map(Solid::try_from).transpose()?,
+            }),
+            _ => Err(BarErr::InvalidHeaderFormat(
+                "The top level header array does not contain four elements".into(),
+            )),
+        }
     }
+}
 
-    /// Save an entry to a file or to a folder if it is a [Dir](Entry::Dir), used to save an unpacked directory
-    pub(super) fn save_entry(
-        dir: &std::path::Path,
+impl Bar<io::Cursor<Vec<u8>>> {
+    /// Create a new `Bar` archive with an in-memory `Vec` as backing storage
+    #[inline]
+    #[must_use]
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            data: io::Cursor::new(Vec::new()),
+            header: Header {
+                meta: Meta::new(name.to_string()),
+                description: None,
+                counts: None,
+                source_path: None,
+                created: None,
+                root: entry::Dir {
+                    meta: RefCell::new(Meta::new("root")),
+                    data: HashMap::new(),
+                },
+                solid: None,
+            },
+            compress_header: false,
+            redundant_header: false,
+            io_buffer_size: DEFAULT_IO_BUFFER_SIZE,
+            skipped: Vec::new(),
+            decompression_limit: Some(DEFAULT_DECOMPRESSION_LIMIT),
+            hash_updates: false,
+            temp_dir: None,
+        }
+    }
+}
+
+impl<S: Read + Seek> Bar<S> {
+    /// The file name of a metadata file in uncompressed archives
+    pub(super) const ROOT_METADATA_FILE: &'static str = ".__barmeta.msgpack";
+
+    /// Get a hashmap of file paths in the archive to their metadata bincode
+    pub(super) fn all_entry_metadata(&self, path: impl AsRef<path::Path>) -> Value {
+        use rmpv::Utf8String;
+        let mut vec = vec![];
+        fn walk_dir(vec: &mut Vec<(Value, Value)>, dir: &entry::Dir, path: path::PathBuf) {
+            for (name, item) in dir.data.iter() {
+                match item {
+                    Entry::Dir(dir) => {
+                        vec.push((
+                            Value::String(Utf8String::from(path.join(name).to_str().unwrap())),
+                            ser_meta(&dir.meta.borrow()),
+                        ));
+                        walk_dir(vec, dir, path.join(name));
+                    }
+                    Entry::File(file) => vec.push((
+                        Value::String(Utf8String::from(path.join(name).to_str().unwrap())),
+                        ser_meta(&file.meta.borrow()),
+                    )),
+                }
+            }
+        }
+        walk_dir(&mut vec, &self.header.root, path.as_ref().to_owned());
+        vec.push((
+            Value::String(Utf8String::from("/")),
+            ser_meta(&self.header.meta),
+        ));
+
+        Value::Map(vec)
+    }
+
+    /// Read all entry metadata from the root metadata file in `dir` when packing a previously unpacked
+    /// directory, keyed by the path of each entry relative to `dir` and with the archive's metadata at an
+    /// empty path. Environment variables in the stored paths like `$HOME` or `${HOME}` are expanded
+    pub(super) fn read_all_entry_metadata(
+        dir: &std::path::Path,
+    ) -> BarResult<HashMap<path::PathBuf, Meta>> {
+        let mut data = match std::fs::File::open(dir.join(Self::ROOT_METADATA_FILE)) {
+            Ok(data) => data,
+            Err(_) => {
+                return Ok(HashMap::new());
+            }
+        };
+        let val = rmpv::decode::read_value(&mut data)?;
+
+        let val = val.as_map().ok_or_else(|| {
+            BarErr::BadMetadataFile("Header map of paths to metadata is not a map".into())
+        })?;
+
+        //Stored paths start with the directory that the archive was unpacked to, which may be spelled
+        //differently than `dir` even if it is the same directory, so paths that don't start with `dir` are
+        //compared after resolving them
+        let canonical = std::fs::canonicalize(dir).ok();
+        let relative = |path: &str| -> Option<path::PathBuf> {
+            let path = path::Path::new(path);
+            if let Ok(relative) = path.strip_prefix(dir) {
+                return Some(relative.to_path_buf());
+            }
+            let path = std::fs::canonicalize(path).ok()?;
+            Some(path.strip_prefix(canonical.as_ref()?).ok()?.to_path_buf())
+        };
+
+        let mut map = HashMap::new();
+        for (path, meta) in val {
+            let path = path.as_str().ok_or_else(|| {
+                BarErr::BadMetadataFile("The keys for metada's map are not strings".into())
+            })?;
+            let meta = Meta::try_from(meta)?; //Read the metadata
+            let path = match path {
+                "/" => Some(path::PathBuf::new()),
+                path => relative(&expand_env(path)),
+            };
+            if let Some(path) = path {
+                map.insert(path, meta);
+            }
+        }
+        Ok(map)
+    }
+
+    /// Read all files in a directory into a list of [Entry]s, reading metadata files if possible
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn pack_read_dir<W: Write>(
+        dir: &std::path::Path,
+        relative: &std::path::Path,
+        off: &mut u64,
+        writer: &mut W,
+        meta_vec: &HashMap<std::path::PathBuf, Meta>,
+        choose: &ChooseCompression,
+        prog: &ProgressBar,
+        filter: &dyn Fn(&std::path::Path, bool) -> bool,
+        skipped: &mut Vec<std::path::PathBuf>,
+    ) -> BarResult<Vec<Entry>> {
+        let mut vec = vec![];
+
+        //Entries matching the patterns of this directory's ignore file are left out along with entries that
+        //parent directories leave out
+        let ignore = read_ignore_file(dir)?;
+        let filter = |path: &std::path::Path, is_dir: bool| {
+            filter(path, is_dir)
+                && match &ignore {
+                    Some(ignore) => !ignore.is_match(path.strip_prefix(relative).unwrap_or(path)),
+                    None => true,
+                }
+        };
+
+        for file in std::fs::read_dir(dir)? {
+            let file = file?;
+            if !prog.is_hidden() {
+                prog.set_message(format!("Writing file {} to archive", file.path().display()));
+            }
+
+            let name = file
+                .file_name()
+                .into_string()
+                .map_err(|_| BarErr::NonUtf8Name(file.path()))?;
+
+            if name == Self::ROOT_METADATA_FILE {
+                continue;
+            }
+
+            let fs_meta = file.metadata()?;
+            let is_dir = fs_meta.is_dir();
+            let relative = relative.join(&name);
+            if !filter(&relative, is_dir) {
+                continue;
+            }
+            //Opening a FIFO blocks until something writes to it, so only regular files are read. The
+            //directory entry's metadata doesn't follow symbolic links, so the file's metadata is read again
+            let fs_meta = match is_dir {
+                true => fs_meta,
+                false => std::fs::metadata(file.path())?,
+            };
+            if !is_dir && !fs_meta.is_file() {
+                log!(warn, "Skipping special file {}", file.path().display());
+                prog.println(format!(
+                    "Skipping {}, it is not a regular file or directory",
+                    file.path().display()
+                ));
+                skipped.push(relative);
+                continue;
+            }
+
+            //See if we have any metadata files to go with this one
+            let mut meta = match meta_vec.get(&relative) {
+                Some(meta) => meta.clone(),
+                None => Meta::new(name.clone()),
+            };
+            if let (None, Ok(modified)) = (meta.modified, fs_meta.modified()) {
+                meta = meta.with_modified(modified);
+            }
+
+            match is_dir {
+                true => {
+                    let directory = entry::Dir {
+                        meta: RefCell::new(meta),
+                        data: Self::pack_read_dir(
+                            &file.path(),
+                            &relative,
+                            off,
+                            writer,
+                            meta_vec,
+                            choose,
+                            prog,
+                            &filter,
+                            skipped,
+                        )?
+                        .into_iter()
+                        .map(|entry| (entry.name(), entry))
+                        .collect(),
+                    };
+                    vec.push(Entry::Dir(directory));
+                }
+                false => {
+                    let mut data = std::fs::File::open(file.path())?; //Open the file at the given location
+                    let size = fs_meta.len();
+                    let compression = choose(&relative, size, &mut data)?;
+
+                    log!(
+                        debug,
+                        "Packing file {} ({} bytes) at offset {}",
+                        relative.display(),
+                        size,
+                        off
+                    );
+                    let file = entry::File {
+                        inherited: false,
+                        normalized: false,
+                        compression,
+                        off: *off,
+                        size: size as u32,
+                        meta: RefCell::new(meta),
+                    };
+                    *off += size;
+                    //Only show the progress of reading a file when progress is shown, a progress bar
+                    //costs more than reading a small file
+                    match prog.is_hidden() {
+                        true => {
+                            std::io::copy(&mut data, writer)?;
+                        }
+                        false => {
+                            let read_prog = ProgressBar::new(size).with_style(
+                                ProgressStyle::default_bar()
+                                    .template(
+                                        "[{bar}] {bytes}/{total_bytes} {binary_bytes_per_sec} {msg}",
+                                    )
+                                    .progress_chars("=>-"),
+                            );
+                            std::io::copy(&mut read_prog.wrap_read(&mut data), writer)?;
+                            read_prog.finish_and_clear();
+                        }
+                    }
+                    vec.push(Entry::File(file))
+                }
+            }
+
+            prog.tick();
+        }
+        Ok(vec)
+    }
+
+    /// Get the number of bytes after the header, which is 8 for the file data size and another 40 for
+    /// the hash trailer if the archive has one
+    pub(super) fn trailer_len(data: &mut S) -> BarResult<u64> {
+        let file_size = data.seek(SeekFrom::End(0))?;
+        if file_size < 48 {
+            return Ok(8);
+        }
+        data.seek(SeekFrom::End(-8))?;
+        let mut magic = [0u8; HASH_TRAILER_MAGIC.len()];
+        data.read_exact(&mut magic)?;
+        Ok(match &magic == HASH_TRAILER_MAGIC {
+            true => 48,
+            false => 8,
+        })
+    }
+
+    /// Hash the first `len` bytes of the reader with SHA-256, leaving the reader positioned after them
+    pub(super) fn hash_range(data: &mut S, len: u64) -> BarResult<[u8; 32]> {
+        use sha2::{Digest, Sha256};
+        data.seek(SeekFrom::Start(0))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut data.take(len), &mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Get the position in the reader that our header data starts and return
+    /// (file data size, header size)
+    pub(super) fn get_header_pos(data: &mut S) -> BarResult<(u64, u64)> {
+        let trailer = Self::trailer_len(data)?;
+        let file_size = data.seek(SeekFrom::End(0))?;
+        if file_size < trailer {
+            return Err(BarErr::InvalidHeaderFormat(format!(
+                "The archive is {} bytes long, too short to contain a header",
+                file_size
+            )));
+        }
+        data.seek(SeekFrom::End(-(trailer as i64)))?;
+
+        let data_size = data.read_u64::<Endian>()?;
+        let header_size = file_size
+            .checked_sub(data_size)
+            .and_then(|size| size.checked_sub(trailer))
+            .ok_or_else(|| {
+                BarErr::InvalidHeaderFormat(format!(
+                    "The header offset {} is past the end of the archive",
+                    data_size
+                ))
+            })?;
+        //Every header contains at least the root directory, so an empty header means the offset is wrong
+        if header_size == 0 {
+            return Err(BarErr::InvalidHeaderFormat(format!(
+                "The header offset {} leaves no room for a header before the end of the archive",
+                data_size
+            )));
+        }
+        data.seek(SeekFrom::Start(data_size))?;
+
+        Ok((data_size, header_size))
+    }
+
+    /// Read header bytes from the internal reader by seeking to the end and reading the file size,
+    /// returning the header and if it was compressed. If the header at the end of the archive can't be
+    /// read, the redundant copy at the front of the archive is used if there is one
+    pub(super) fn read_header(data: &mut S) -> BarResult<(Header, bool)> {
+        match Self::read_tail_header(data) {
+            Ok(header) => Ok(header),
+            Err(e) => match Self::read_front_header(data) {
+                Ok(Some(header)) => Ok(header),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Read the header at the end of the archive
+    fn read_tail_header(data: &mut S) -> BarResult<(Header, bool)> {
+        let (_, header_size) = Self::get_header_pos(data)?;
+        let mut header_bytes = vec![0u8; header_size as usize];
+        data.read_exact(&mut header_bytes)?;
+        Self::parse_header(&header_bytes)
+    }
+
+    /// Read the redundant copy of the header at the front of the archive, returning `None` if the archive
+    /// doesn't start with a front header
+    pub(super) fn read_front_header(data: &mut S) -> BarResult<Option<(Header, bool)>> {
+        data.seek(SeekFrom::Start(0))?;
+        let mut magic = [0u8; FRONT_HEADER_MAGIC.len()];
+        match data.read_exact(&mut magic) {
+            Ok(()) if &magic == FRONT_HEADER_MAGIC => (),
+            Ok(()) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let header_size = data.read_u64::<Endian>()?;
+        let mut header_bytes = Vec::new();
+        data.take(header_size).read_to_end(&mut header_bytes)?;
+        Self::parse_header(&header_bytes).map(Some)
+    }
+
+    /// Decode header bytes, decompressing them if they begin with the compressed header flag
+    pub(super) fn parse_header(header_bytes: &[u8]) -> BarResult<(Header, bool)> {
+        let compressed = header_bytes.first() == Some(&COMPRESSED_HEADER);
+        let header_val = match compressed {
+            true => rmpv::decode::read_value(&mut DeflateDecoder::new(&header_bytes[1..]))?,
+            false => rmpv::decode::read_value(&mut &*header_bytes)?, //Read the value from the header bytes
+        };
+        Ok((Header::try_from(&header_val)?, compressed))
+    }
+
+    /// Save a file's contents to a Writer, optionally decompressing the file's data. Decompression stops with
+    /// an error if the decompressed data is larger than `limit` bytes
+    pub(super) fn save_file(
+        file: &entry::File,
+        writer: &mut impl Write,
+        back: &mut S,
+        solid: Option<&Solid>,
+        decompress: bool,
+        prog: bool,
+        limit: Option<u64>,
+    ) -> BarResult<()> {
+        if file.inherited {
+            log!(
+                warn,
+                "Can't extract inherited file {}",
+                file.meta.borrow().name
+            );
+            return Err(BarErr::InheritedFile(file.meta.borrow().name.clone()));
+        }
+        log!(
+            debug,
+            "Extracting file {} ({} bytes) from offset {}",
+            file.meta.borrow().name,
+            file.size,
+            file.off
+        );
+        let prog = match prog {
+            true => ProgressBar::new(file.size as u64).with_style(
+                ProgressStyle::default_bar()
+                    .template("[{bar}] {bytes} {binary_bytes_per_sec} {msg}")
+                    .progress_chars("=>-"),
+            ),
+            false => ProgressBar::hidden(),
+        };
+
+        let mut data = vec![0u8; file.size as usize];
+        read_stored(file, &mut prog.wrap_read(back), solid, &mut data)?;
+        prog.reset();
+
+        prog.set_message(format!("Saving unpacked file {}", file.meta.borrow().name));
+
+        //Read at most one byte past the limit, so that data over the limit is detected without decompressing
+        //all of it
+        let max = limit.map_or(u64::MAX, |limit| limit.saturating_add(1));
+        let bytes = match decompress {
+            true => match file.compression {
+                CompressType(_, CompressMethod::Deflate) => {
+                    let mut encoder = DeflateDecoder::new(data.as_slice()).take(max);
+
+                    let mut decoded = Vec::with_capacity(file.size as usize);
+                    encoder.read_to_end(&mut decoded)?;
+                    drop(data);
+                    decoded
+                }
+                CompressType(_, CompressMethod::Gzip) => {
+                    let mut encoder = MultiGzDecoder::new(data.as_slice()).take(max);
+                    let mut decoded = Vec::with_capacity(file.size as usize);
+                    encoder.read_to_end(&mut decoded)?;
+                    drop(data);
+                    decoded
+                }
+                CompressType(_, CompressMethod::None) => data,
+            },
+            false => data,
+        };
+        if let Some(limit) = limit.filter(|&limit| decompress && bytes.len() as u64 > limit) {
+            return Err(BarErr::DecompressionLimitExceeded(
+                file.meta.borrow().name.clone(),
+                limit,
+            ));
+        }
+        //Text that was normalized when it was packed is extracted with the platform's line endings
+        let bytes = match decompress && file.normalized && cfg!(windows) {
+            true => lf_to_crlf(&bytes),
+            false => bytes,
+        };
+
+        io::copy(&mut bytes.as_slice(), &mut prog.wrap_write(writer))?;
+        prog.finish_and_clear();
+
+        Ok(())
+    }
+
+    /// Save an entry to a file or to a folder if it is a [Dir](Entry::Dir), used to save an unpacked directory
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn save_entry(
+        dir: &std::path::Path,
         entry: &Entry,
         back: &mut S,
+        solid: Option<&Solid>,
         prog: bool,
         decompress: bool,
         recurse: bool,
+        buf_size: usize,
+        limit: Option<u64>,
     ) -> BarResult<()> {
         let path = dir.join(entry.name());
 
@@ -620,15 +1585,27 @@ impl<S: Read + Seek> Bar<S> {
                     dirprog.set_message(format!("Saving directory {}", dir.meta.borrow().name));
                     std::fs::create_dir_all(path.clone())?;
                     for (_, file) in dir.data.iter() {
-                        Self::save_entry(path.as_ref(), file, back, prog, decompress, recurse)?;
+                        Self::save_entry(
+                            path.as_ref(),
+                            file,
+                            back,
+                            solid,
+                            prog,
+                            decompress,
+                            recurse,
+                            buf_size,
+                            limit,
+                        )?;
                         dirprog.inc(1);
                     }
                 }
                 dirprog.finish_and_clear();
             }
             Entry::File(file) => {
-                let mut file_data = std::fs::File::create(path)?;
-                Self::save_file(file, &mut file_data, back, decompress, prog)?;
+                let mut file_data =
+                    io::BufWriter::with_capacity(buf_size, std::fs::File::create(path)?);
+                Self::save_file(file, &mut file_data, back, solid, decompress, prog, limit)?;
+                file_data.flush()?;
             }
         }
         Ok(())
@@ -652,7 +1629,6 @@ mod tests {
         let file = reader.file_mut("subdir/test.txt").unwrap();
         file.meta.borrow_mut().note =
             Some("This is a testing note about the file test.txt testing".into());
-        drop(file);
 
         reader.save_unpacked("output", false).unwrap();
         drop(reader);
@@ -660,4 +1636,1973 @@ mod tests {
         let back = io::Cursor::new(vec![0u8; 2048]);
         let _packer = Bar::pack("output/test", back, "high-gzip".parse().unwrap(), false).unwrap();
     }
+
+    #[test]
+    pub fn test_compress_header() {
+        let mut bar = Bar::new("many");
+        for i in 0..1000 {
+            bar.root_mut()
+                .add_entry(Entry::File(entry::File {
+                    inherited: false,
+                    normalized: false,
+                    meta: RefCell::new(Meta::new(format!("file_number_{}.txt", i))),
+                    compression: "none".parse().unwrap(),
+                    off: 0,
+                    size: 0,
+                }))
+                .unwrap();
+        }
+
+        let mut plain = io::Cursor::new(Vec::new());
+        bar.save(&mut plain, false).unwrap();
+        bar.set_compress_header(true);
+        let mut compressed = io::Cursor::new(Vec::new());
+        bar.save(&mut compressed, false).unwrap();
+
+        let (_, plain_size) = Bar::get_header_pos(&mut plain).unwrap();
+        let (_, compressed_size) = Bar::get_header_pos(&mut compressed).unwrap();
+        assert!(compressed_size < plain_size / 2);
+
+        let bar = Bar::unpack_reader(compressed).unwrap();
+        assert!(bar.compress_header);
+        assert_eq!(bar.root().entries().count(), 1000);
+        assert!(bar.file("file_number_999.txt").is_some());
+    }
+
+    #[test]
+    pub fn test_touch() {
+        let mut bar = Bar::new("touched");
+        bar.root_mut()
+            .add_entry(Entry::File(entry::File {
+                inherited: false,
+                normalized: false,
+                meta: RefCell::new(Meta::new("file.txt")),
+                compression: "none".parse().unwrap(),
+                off: 0,
+                size: 0,
+            }))
+            .unwrap();
+        bar.touch("file.txt").unwrap();
+        bar.touch("file.txt").unwrap();
+        assert!(bar.touch("missing.txt").is_err());
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let bar = Bar::unpack_reader(saved).unwrap();
+        let meta = bar.entry("file.txt").unwrap().meta();
+        assert!(meta.used);
+        assert_eq!(meta.access_count, 2);
+        assert!(meta.last_access.is_some());
+    }
+
+    #[test]
+    pub fn test_empty_file() {
+        for compression in ["none", "fast-deflate", "high-gzip"] {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("empty.txt"), b"").unwrap();
+            std::fs::write(dir.path().join("full.txt"), b"Not empty").unwrap();
+
+            let back = io::Cursor::new(Vec::new());
+            let mut bar = Bar::pack(dir.path(), back, compression.parse().unwrap(), false).unwrap();
+            assert_eq!(bar.file("empty.txt").unwrap().size(), 0);
+            let mut saved = io::Cursor::new(Vec::new());
+            bar.save(&mut saved, false).unwrap();
+            let mut bar = Bar::unpack_reader(saved).unwrap();
+
+            let out = tempfile::tempdir().unwrap();
+            let entry = bar.entry("empty.txt").unwrap().clone();
+            bar.entry_data(out.path(), entry, true, false, false)
+                .unwrap();
+            assert_eq!(std::fs::read(out.path().join("empty.txt")).unwrap(), b"");
+
+            let mut data = Vec::new();
+            bar.open("empty.txt")
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            assert!(data.is_empty());
+
+            let file = bar.file("full.txt").unwrap().clone();
+            let mut data = Vec::new();
+            bar.file_data(file, &mut data, true, false).unwrap();
+            assert_eq!(data, b"Not empty");
+        }
+    }
+
+    #[test]
+    pub fn test_clone_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("original.txt"), b"Shared file data").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "fast-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        bar.clone_entry("original.txt", "sub/copy.txt").unwrap();
+        assert!(matches!(
+            bar.clone_entry("original.txt", "sub/copy.txt"),
+            Err(BarErr::EntryExists(_))
+        ));
+        assert!(matches!(
+            bar.clone_entry("missing.txt", "copy.txt"),
+            Err(BarErr::NoEntry(_))
+        ));
+
+        let copy = bar.file("sub/copy.txt").unwrap().clone();
+        let original = bar.file("original.txt").unwrap().clone();
+        assert_eq!(copy.meta.borrow().name, "copy.txt");
+        assert_eq!(copy.off(), original.off());
+
+        let (mut copy_data, mut original_data) = (Vec::new(), Vec::new());
+        bar.file_data(copy, &mut copy_data, true, false).unwrap();
+        bar.file_data(original, &mut original_data, true, false)
+            .unwrap();
+        assert_eq!(copy_data, b"Shared file data");
+        assert_eq!(copy_data, original_data);
+    }
+
+    #[test]
+    pub fn test_pack_files() {
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        std::fs::write(first.path().join("a.txt"), b"First file").unwrap();
+        std::fs::write(second.path().join("b.txt"), b"Second file").unwrap();
+
+        let files = vec![
+            (first.path().join("a.txt"), path::PathBuf::from("a.txt")),
+            (
+                second.path().join("b.txt"),
+                path::PathBuf::from("nested/dir/renamed.txt"),
+            ),
+        ];
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack_files(
+            "picked",
+            &files,
+            back,
+            "high-gzip".parse().unwrap(),
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(bar.meta().name, "picked");
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        for (path, data) in [
+            ("a.txt", b"First file" as &[u8]),
+            ("nested/dir/renamed.txt", b"Second file"),
+        ] {
+            let file = bar.file(path).unwrap().clone();
+            let mut out = Vec::new();
+            bar.file_data(file, &mut out, true, false).unwrap();
+            assert_eq!(out, data);
+        }
+
+        let files = vec![
+            (first.path().join("a.txt"), path::PathBuf::from("same.txt")),
+            (second.path().join("b.txt"), path::PathBuf::from("same.txt")),
+        ];
+        let back = io::Cursor::new(Vec::new());
+        assert!(matches!(
+            Bar::pack_files("dup", &files, back, "none".parse().unwrap(), false, false),
+            Err(BarErr::EntryExists(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), vec![b'a'; 1000]).unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), vec![b'b'; 1000]).unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        bar.set_redundant_header(true);
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let saved = saved.into_inner();
+
+        //Corrupt the trailing header offset, which is before the hash trailer
+        let mut corrupt = saved.clone();
+        let len = corrupt.len() - 40;
+        corrupt[len - 8..len].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Bar::unpack_reader(io::Cursor::new(corrupt.clone())).is_ok());
+
+        let (mut bar, lost) = Bar::recover_lost(io::Cursor::new(corrupt)).unwrap();
+        assert!(lost.is_empty());
+        for (path, data) in [("a.txt", b'a'), ("sub/b.txt", b'b')] {
+            let file = bar.file(path).unwrap().clone();
+            let mut out = Vec::new();
+            bar.file_data(file, &mut out, true, false).unwrap();
+            assert_eq!(out, vec![data; 1000]);
+        }
+
+        //Cut off the data of the last file in the archive along with the header
+        let last = bar
+            .entries()
+            .chain(bar.dir("sub").unwrap().entries())
+            .filter_map(Entry::as_file)
+            .max_by_key(|file| file.off)
+            .unwrap();
+        let last_name = last.meta.borrow().name.clone();
+        let truncated = saved[..last.off as usize + 1].to_vec();
+
+        let (bar, lost) = Bar::recover_lost(io::Cursor::new(truncated)).unwrap();
+        assert_eq!(lost.len(), 1);
+        assert!(lost[0].ends_with(&last_name));
+        assert_eq!(
+            bar.entries().filter_map(Entry::as_file).count()
+                + bar.dir("sub").unwrap().entries().count(),
+            1
+        );
+    }
+
+    #[test]
+    pub fn test_redundant_header() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"Some file contents").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "high-deflate".parse().unwrap(), false).unwrap();
+        bar.set_redundant_header(true);
+        bar.set_compress_header(true);
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut saved = saved.into_inner();
+        assert!(saved.starts_with(FRONT_HEADER_MAGIC));
+
+        //Zero the header at the end of the archive, leaving the trailing header offset intact
+        let mut cursor = io::Cursor::new(saved.as_slice());
+        let (header_pos, header_size) = Bar::get_header_pos(&mut cursor).unwrap();
+        let header_pos = header_pos as usize;
+        saved[header_pos..header_pos + header_size as usize].fill(0);
+
+        let mut bar = Bar::unpack_reader(io::Cursor::new(saved)).unwrap();
+        assert!(bar.redundant_header);
+        assert!(bar.compress_header);
+        let file = bar.file("a.txt").unwrap().clone();
+        let mut out = Vec::new();
+        bar.file_data(file, &mut out, true, false).unwrap();
+        assert_eq!(out, b"Some file contents");
+    }
+
+    #[test]
+    pub fn test_into_iter() {
+        let mut bar = Bar::new("iter");
+        for name in ["a", "b", "c"] {
+            bar.header
+                .root
+                .add_entry(Entry::Dir(entry::Dir {
+                    meta: RefCell::new(Meta::new(name)),
+                    data: HashMap::new(),
+                }))
+                .unwrap();
+        }
+
+        let mut count = 0;
+        for entry in &bar {
+            assert!(entry.as_dir().is_some());
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        for entry in &mut bar {
+            entry.meta_mut().note = Some("Visited".to_owned());
+        }
+        assert!((&bar)
+            .into_iter()
+            .all(|entry| entry.meta().note.as_deref() == Some("Visited")));
+    }
+
+    #[test]
+    pub fn test_grep() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(
+            dir.path().join("src/main.rs"),
+            "fn main() {\r\n    // TODO: print something\r\n}\r\n// todo later",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "Nothing to do here").unwrap();
+        std::fs::write(dir.path().join("data.bin"), b"\0\0TODO\0").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "fast-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        let mut matches = vec![];
+        bar.grep(&regex::Regex::new("TODO").unwrap(), |path, num, line| {
+            matches.push((path.to_owned(), num, line.to_owned()))
+        })
+        .unwrap();
+        assert_eq!(
+            matches,
+            vec![(
+                path::PathBuf::from("src/main.rs"),
+                2,
+                "    // TODO: print something".to_owned()
+            )]
+        );
+
+        let mut count = 0;
+        bar.grep(&regex::Regex::new("(?i)todo").unwrap(), |_, _, _| {
+            count += 1
+        })
+        .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    pub fn test_save_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("x/y")).unwrap();
+        for path in ["b.txt", "x/c.rs", "x/y/a.txt", "a.rs", "x/y/README"] {
+            std::fs::write(dir.path().join(path), path).unwrap();
+        }
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save_sorted(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        let mut files = ["b.txt", "x/c.rs", "x/y/a.txt", "a.rs", "x/y/README"]
+            .iter()
+            .map(|path| (bar.file(path).unwrap().off, *path))
+            .collect::<Vec<_>>();
+        files.sort();
+        let order = files.iter().map(|(_, path)| *path).collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            ["x/y/README", "a.rs", "x/c.rs", "x/y/a.txt", "b.txt"]
+        );
+
+        for path in order {
+            let file = bar.file(path).unwrap().clone();
+            let mut out = Vec::new();
+            bar.file_data(file, &mut out, true, false).unwrap();
+            assert_eq!(out, path.as_bytes());
+        }
+    }
+
+    #[test]
+    pub fn test_save_solid() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("logs")).unwrap();
+        let contents = |i: usize| {
+            format!(
+                "Log file number {}\nEverything went as expected and no errors occurred\n",
+                i
+            )
+        };
+        for i in 0..50 {
+            std::fs::write(dir.path().join(format!("logs/{}.log", i)), contents(i)).unwrap();
+        }
+
+        let pack = || {
+            let back = io::Cursor::new(Vec::new());
+            Bar::pack(dir.path(), back, "high-deflate".parse().unwrap(), false).unwrap()
+        };
+        let mut individual = io::Cursor::new(Vec::new());
+        pack().save(&mut individual, false).unwrap();
+        let mut solid = io::Cursor::new(Vec::new());
+        pack()
+            .save_solid(&mut solid, "high-deflate".parse().unwrap(), false)
+            .unwrap();
+        assert!(solid.get_ref().len() < individual.get_ref().len());
+
+        let mut bar = Bar::unpack_reader(solid).unwrap();
+        assert!(bar.header.solid.is_some());
+        for i in [0, 17, 49] {
+            let path = format!("logs/{}.log", i);
+            let file = bar.file(&path).unwrap().clone();
+            let mut out = Vec::new();
+            bar.file_data(file, &mut out, true, false).unwrap();
+            assert_eq!(out, contents(i).as_bytes());
+
+            let mut out = String::new();
+            bar.open(&path).unwrap().read_to_string(&mut out).unwrap();
+            assert_eq!(out, contents(i));
+        }
+
+        let mut redundant = io::Cursor::new(Vec::new());
+        let mut bar = pack();
+        bar.set_redundant_header(true);
+        bar.save_solid(&mut redundant, "fast-gzip".parse().unwrap(), false)
+            .unwrap();
+        let mut bar = Bar::unpack_reader(redundant).unwrap();
+        let file = bar.file("logs/8.log").unwrap().clone();
+        let mut out = Vec::new();
+        bar.file_data(file, &mut out, true, false).unwrap();
+        assert_eq!(out, contents(8).as_bytes());
+
+        //Saving a solid archive normally writes every file individually
+        let mut resaved = io::Cursor::new(Vec::new());
+        bar.save(&mut resaved, false).unwrap();
+        let mut bar = Bar::unpack_reader(resaved).unwrap();
+        assert!(bar.header.solid.is_none());
+        let file = bar.file("logs/3.log").unwrap().clone();
+        let mut out = Vec::new();
+        bar.file_data(file, &mut out, true, false).unwrap();
+        assert_eq!(out, contents(3).as_bytes());
+    }
+
+    /// Create an integer key for a handcrafted header map
+    fn key(key: u8) -> Value {
+        Value::Integer(key.into())
+    }
+
+    #[test]
+    pub fn test_parse_values() {
+        let meta = Value::Map(vec![
+            (key(NAME), Value::String("file.txt".into())),
+            (key(NOTE), Value::String("A note".into())),
+            (key(USED), Value::Boolean(true)),
+        ]);
+        let parsed = Meta::try_from(&meta).unwrap();
+        assert_eq!(parsed.name, "file.txt");
+        assert_eq!(parsed.note.as_deref(), Some("A note"));
+        assert!(parsed.used);
+        assert_eq!(parsed.access_count, 0);
+
+        let file = Value::Map(vec![
+            (key(OFFSET), Value::Integer(128.into())),
+            (key(SIZE), Value::Integer(64.into())),
+            (key(META), meta.clone()),
+            (key(COMPRESSMETHOD), Value::String("fast-gzip".into())),
+        ]);
+        let parsed = entry::File::try_from(&file).unwrap();
+        assert_eq!((parsed.off(), parsed.size()), (128, 64));
+        assert_eq!(parsed.compression().1, CompressMethod::Gzip);
+
+        let dir = Value::Array(vec![
+            Value::Map(vec![(key(NAME), Value::String("docs".into()))]),
+            Value::Array(vec![Value::Array(vec![Value::Boolean(true), file.clone()])]),
+        ]);
+        let parsed = Entry::try_from(&Value::Array(vec![Value::Boolean(false), dir])).unwrap();
+        let parsed = parsed.as_dir().unwrap();
+        assert_eq!(parsed.meta.borrow().name, "docs");
+        assert!(parsed.entry("file.txt").unwrap().as_file().is_some());
+
+        //Missing and mistyped fields are errors
+        let no_name = Value::Map(vec![(key(USED), Value::Boolean(true))]);
+        assert!(matches!(
+            Meta::try_from(&no_name),
+            Err(BarErr::InvalidHeaderFormat(_))
+        ));
+        let bad_size = Value::Map(vec![
+            (key(OFFSET), Value::Integer(0.into())),
+            (key(SIZE), Value::String("big".into())),
+            (key(META), meta),
+            (key(COMPRESSMETHOD), Value::String("none".into())),
+        ]);
+        assert!(entry::File::try_from(&bad_size).is_err());
+        assert!(Entry::try_from(&Value::Array(vec![Value::Boolean(true)])).is_err());
+        assert!(entry::Dir::try_from(&file).is_err());
+    }
+
+    #[test]
+    pub fn test_read_file_raw() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Raw data ".repeat(100)).unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "medium-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let stored = saved.get_ref().clone();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        let (compression, raw) = bar.read_file_raw("a.txt").unwrap();
+        let file = bar.file("a.txt").unwrap();
+        assert_eq!(compression.1, CompressMethod::Gzip);
+        assert_eq!(compression.0.level(), 5);
+        assert_eq!(
+            raw,
+            &stored[file.off as usize..file.off as usize + file.size as usize]
+        );
+
+        let mut decoded = String::new();
+        MultiGzDecoder::new(raw.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "Raw data ".repeat(100));
+        assert!(matches!(
+            bar.read_file_raw("missing.txt"),
+            Err(BarErr::NoEntry(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_reproducible() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            let path = dir.path().join(format!("dir_{}/file_{}.txt", i % 4, i));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("File number {}", i)).unwrap();
+        }
+
+        let save = || {
+            let back = io::Cursor::new(Vec::new());
+            let mut bar =
+                Bar::pack(dir.path(), back, "fast-deflate".parse().unwrap(), false).unwrap();
+            let mut saved = Vec::new();
+            bar.save(&mut saved, false).unwrap();
+            saved
+        };
+        assert_eq!(save(), save());
+    }
+
+    #[test]
+    pub fn test_archive_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Hashed data ".repeat(10)).unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "fast-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = Vec::new();
+        bar.save(&mut saved, false).unwrap();
+        assert_eq!(&saved[saved.len() - 8..], HASH_TRAILER_MAGIC);
+
+        let verify = |data: Vec<u8>| {
+            Bar::unpack_reader(io::Cursor::new(data)).and_then(|mut bar| bar.verify_archive_hash())
+        };
+        assert!(verify(saved.clone()).is_ok());
+        for i in 0..saved.len() {
+            let mut corrupt = saved.clone();
+            corrupt[i] ^= 0xff;
+            assert!(
+                verify(corrupt).is_err(),
+                "Flipping byte {} was not detected",
+                i
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_store_incompressible() {
+        use sha2::{Digest, Sha256};
+
+        let dir = tempfile::tempdir().unwrap();
+        let random = (0..4096u32)
+            .flat_map(|i| Sha256::digest(i.to_le_bytes()))
+            .collect::<Vec<_>>();
+        std::fs::write(dir.path().join("random.bin"), &random).unwrap();
+        std::fs::write(dir.path().join("text.txt"), "Compressible ".repeat(1000)).unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let compression = "high-gzip".parse().unwrap();
+        let mut bar =
+            Bar::pack_filtered(dir.path(), back, compression, true, false, |_, _| true).unwrap();
+        assert_eq!(
+            bar.file("random.bin").unwrap().compression().1,
+            CompressMethod::None
+        );
+        assert_eq!(
+            bar.file("text.txt").unwrap().compression().1,
+            CompressMethod::Gzip
+        );
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        let file = bar.file("random.bin").unwrap().clone();
+        let mut out = Vec::new();
+        bar.file_data(file, &mut out, true, false).unwrap();
+        assert_eq!(out, random);
+    }
+
+    #[test]
+    pub fn test_pack_auto() {
+        use sha2::{Digest, Sha256};
+
+        let dir = tempfile::tempdir().unwrap();
+        let random = (0..4096u32)
+            .flat_map(|i| Sha256::digest(i.to_le_bytes()))
+            .collect::<Vec<_>>();
+        std::fs::write(dir.path().join("random.bin"), &random).unwrap();
+        std::fs::write(dir.path().join("text.txt"), "Compressible ".repeat(1000)).unwrap();
+        std::fs::write(dir.path().join("empty.txt"), "").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack_auto(dir.path(), back, false, |_, _| true).unwrap();
+        let method = |bar: &Bar<_>, path| bar.file(path).unwrap().compression().1;
+        assert_eq!(method(&bar, "random.bin"), CompressMethod::None);
+        assert_eq!(method(&bar, "text.txt"), CompressMethod::Deflate);
+        assert_eq!(method(&bar, "empty.txt"), CompressMethod::None);
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        assert!(bar.file("text.txt").unwrap().size < 1000);
+        let mut data = String::new();
+        bar.open("text.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Compressible ".repeat(1000));
+
+        let files = [(dir.path().join("random.bin"), "data/random.bin".into())];
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack_files_auto("list", &files, back, false).unwrap();
+        assert_eq!(method(&bar, "data/random.bin"), CompressMethod::None);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    pub fn test_log_messages() {
+        use std::sync::Mutex;
+
+        /// A logger that keeps every message logged to it
+        struct Capture(Mutex<Vec<String>>);
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+
+            fn flush(&self) {}
+        }
+
+        static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("logged.txt"), "Logged data").unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "high-gzip".parse().unwrap(), false).unwrap();
+        bar.save(&mut io::Cursor::new(Vec::new()), false).unwrap();
+
+        let logs = CAPTURE.0.lock().unwrap();
+        let logged = |msg: &str| logs.iter().any(|log| log.starts_with(msg));
+        assert!(logged("Packing file logged.txt (11 bytes) at offset 0"));
+        assert!(logged(
+            "Compressing file logged.txt with high-gzip at offset 0"
+        ));
+        assert!(logged("Saved archive with "));
+    }
+
+    #[test]
+    pub fn test_estimate_recompressed_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = (0..2000)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        std::fs::write(dir.path().join("text.txt"), &text).unwrap();
+
+        let packed = |compression: &str| {
+            let back = io::Cursor::new(Vec::new());
+            let mut bar = Bar::pack(dir.path(), back, compression.parse().unwrap(), false).unwrap();
+            let mut saved = io::Cursor::new(Vec::new());
+            bar.save(&mut saved, false).unwrap();
+            Bar::unpack_reader(saved).unwrap()
+        };
+
+        let mut bar = packed("fast-gzip");
+        let size = bar.file("text.txt").unwrap().size();
+        for target in ["high-deflate", "medium-gzip", "none"] {
+            let estimate = bar
+                .estimate_recompressed_size("text.txt", target.parse().unwrap())
+                .unwrap();
+            let actual = packed(target).file("text.txt").unwrap().size();
+            assert_eq!(estimate, actual as u64);
+        }
+        assert_eq!(bar.file("text.txt").unwrap().size(), size);
+        assert!(bar
+            .estimate_recompressed_size("missing.txt", "none".parse().unwrap())
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_description() {
+        let mut bar = Bar::new("described");
+        assert_eq!(bar.description(), None);
+        bar.set_description(Some("An archive with a description".into()));
+        bar.header.meta.note = Some("A separate note".into());
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        assert_eq!(bar.description(), Some("An archive with a description"));
+        assert_eq!(bar.meta().note.as_deref(), Some("A separate note"));
+
+        bar.set_description(None);
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        assert_eq!(Bar::unpack_reader(saved).unwrap().description(), None);
+    }
+
+    #[test]
+    pub fn test_entry_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/nested")).unwrap();
+        std::fs::create_dir(dir.path().join("empty")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "First file").unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "Second file").unwrap();
+        std::fs::write(dir.path().join("sub/nested/c.txt"), "Third file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "fast-gzip".parse().unwrap(), false).unwrap();
+        assert_eq!(bar.header.counts, None);
+        let walked = EntryCounts::count(bar.root());
+        assert_eq!(walked, EntryCounts { files: 3, dirs: 3 });
+        assert_eq!(bar.entry_counts(), walked);
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        assert_eq!(bar.header.counts, Some(walked));
+        let bar = Bar::unpack_reader(saved).unwrap();
+        assert_eq!(bar.header.counts, Some(walked));
+        assert_eq!(bar.entry_counts(), EntryCounts::count(bar.root()));
+    }
+
+    #[test]
+    pub fn test_content_eq() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Compared data ".repeat(2000)).unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "Nested data").unwrap();
+        let saved = |mut bar: Bar<io::Cursor<Vec<u8>>>| {
+            let mut saved = io::Cursor::new(Vec::new());
+            bar.save(&mut saved, false).unwrap();
+            Bar::unpack_reader(saved).unwrap()
+        };
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = saved(Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap());
+        let back = io::Cursor::new(Vec::new());
+        let mut repacked = saved(
+            bar.repack(back, "high-gzip".parse().unwrap(), false)
+                .unwrap(),
+        );
+        assert_ne!(
+            bar.file("a.txt").unwrap().size(),
+            repacked.file("a.txt").unwrap().size()
+        );
+        assert!(bar.content_eq(&mut repacked).unwrap());
+        assert!(bar.content_eq_strict(&mut repacked).unwrap());
+
+        repacked
+            .file_mut("sub/b.txt")
+            .unwrap()
+            .meta
+            .borrow_mut()
+            .note = Some("Changed".into());
+        assert!(bar.content_eq(&mut repacked).unwrap());
+        assert!(!bar.content_eq_strict(&mut repacked).unwrap());
+
+        std::fs::write(dir.path().join("sub/b.txt"), "Nested dat!").unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut changed = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        assert!(!bar.content_eq(&mut changed).unwrap());
+
+        std::fs::remove_file(dir.path().join("sub/b.txt")).unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut removed = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        assert!(!bar.content_eq(&mut removed).unwrap());
+    }
+
+    #[test]
+    pub fn test_save_hashed() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Hashed data ".repeat(100)).unwrap();
+        let hashed = |dir: &path::Path| {
+            let back = io::Cursor::new(Vec::new());
+            let mut bar = Bar::pack(dir, back, "fast-deflate".parse().unwrap(), false).unwrap();
+            let mut saved = Vec::new();
+            let hash = bar.save_hashed(&mut saved, false).unwrap();
+            (hash, saved)
+        };
+
+        let (first, saved) = hashed(dir.path());
+        let (second, _) = hashed(dir.path());
+        assert_eq!(first, second);
+        //The returned hash is the one stored in the trailer
+        let trailer = saved.len() - HASH_TRAILER_MAGIC.len() - 32;
+        assert_eq!(saved[trailer..trailer + 32], first);
+
+        std::fs::write(dir.path().join("a.txt"), "Hashed data!".repeat(100)).unwrap();
+        let (changed, _) = hashed(dir.path());
+        assert_ne!(first, changed);
+    }
+
+    #[test]
+    pub fn test_entry_type() {
+        use entry::EntryType;
+        let mut bar = Bar::new("types");
+        bar.get_or_create_dir("a/b").unwrap();
+        bar.dir_mut("a")
+            .unwrap()
+            .add_entry(Entry::File(entry::File {
+                inherited: false,
+                normalized: false,
+                meta: RefCell::new(Meta::new("f.txt")),
+                compression: "none".parse().unwrap(),
+                off: 0,
+                size: 0,
+            }))
+            .unwrap();
+
+        assert_eq!(bar.entry_type("a"), Some(EntryType::Dir));
+        assert_eq!(bar.entry_type("a/b"), Some(EntryType::Dir));
+        assert_eq!(bar.entry_type("a/f.txt"), Some(EntryType::File));
+        assert_eq!(bar.entry_type("a/missing"), None);
+        assert_eq!(bar.entry_type("a/f.txt/g"), None);
+    }
+
+    #[test]
+    pub fn test_map_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Mapped data ".repeat(100)).unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "high-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let path = dir.path().join("mapped.bar");
+        std::fs::write(&path, saved.get_ref()).unwrap();
+
+        let read = |bar: &mut Bar<io::Cursor<Vec<u8>>>| {
+            let mut data = String::new();
+            bar.open("a.txt")
+                .unwrap()
+                .read_to_string(&mut data)
+                .unwrap();
+            data
+        };
+        let mut bar = Bar::unpack_reader(saved).unwrap().map_backend(|data| data);
+        assert_eq!(read(&mut bar), "Mapped data ".repeat(100));
+
+        let mut bar = Bar::unpack(&path).unwrap().map_backend(|mut file| {
+            let mut data = Vec::new();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_to_end(&mut data).unwrap();
+            io::Cursor::new(data)
+        });
+        assert_eq!(read(&mut bar), "Mapped data ".repeat(100));
+        assert_eq!(bar.file("a.txt").unwrap().meta.borrow().name, "a.txt");
+    }
+
+    #[test]
+    pub fn test_get_or_create_dir() {
+        let mut bar = Bar::new("dirs");
+        bar.get_or_create_dir("a/b/c").unwrap();
+        assert!(bar.dir("a").is_some());
+        assert!(bar.dir("a/b").is_some());
+        assert_eq!(bar.dir("a/b/c").unwrap().meta.borrow().name, "c");
+
+        let file = |name: &str| {
+            Entry::File(entry::File {
+                inherited: false,
+                normalized: false,
+                meta: RefCell::new(Meta::new(name)),
+                compression: "none".parse().unwrap(),
+                off: 0,
+                size: 0,
+            })
+        };
+        //Existing directories are returned without being replaced
+        bar.get_or_create_dir("a/b")
+            .unwrap()
+            .add_entry(file("e.txt"))
+            .unwrap();
+        bar.get_or_create_dir("a/b/d").unwrap();
+        assert_eq!(bar.dir("a/b").unwrap().entries().count(), 3);
+        //An empty path is the root directory
+        assert_eq!(bar.get_or_create_dir("").unwrap().entries().count(), 1);
+
+        bar.get_or_create_dir("a")
+            .unwrap()
+            .add_entry(file("f.txt"))
+            .unwrap();
+        assert!(matches!(
+            bar.get_or_create_dir("a/f.txt/g"),
+            Err(BarErr::NotADirectory(name)) if name == "f.txt"
+        ));
+    }
+
+    #[test]
+    pub fn test_prune_unused() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("watched")).unwrap();
+        std::fs::create_dir_all(dir.path().join("unwatched/nested")).unwrap();
+        std::fs::create_dir_all(dir.path().join("empty")).unwrap();
+        for path in [
+            "a.txt",
+            "b.txt",
+            "watched/c.txt",
+            "watched/d.txt",
+            "unwatched/e.txt",
+            "unwatched/nested/f.txt",
+        ] {
+            std::fs::write(dir.path().join(path), path).unwrap();
+        }
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        for path in ["a.txt", "watched/c.txt"] {
+            bar.touch(path).unwrap();
+        }
+
+        let removed = bar.prune_unused().unwrap();
+        assert_eq!(
+            removed,
+            [
+                "b.txt",
+                "unwatched/e.txt",
+                "unwatched/nested/f.txt",
+                "watched/d.txt"
+            ]
+            .iter()
+            .map(std::path::PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+        assert!(bar.file("a.txt").is_some());
+        assert!(bar.file("watched/c.txt").is_some());
+        assert!(bar.dir("unwatched").is_none());
+        assert!(bar.dir("empty").is_some());
+        assert!(bar.prune_unused().unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_metadata_env_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Expanded").unwrap();
+        std::env::set_var("BAR_TEST_META_DIR", dir.path());
+
+        let meta = Value::Map(vec![(
+            Value::from("${BAR_TEST_META_DIR}/a.txt"),
+            ser_meta(&Meta::new("a.txt").with_note("Found through $BAR_TEST_META_DIR")),
+        )]);
+        let mut file = std::fs::File::create(
+            dir.path()
+                .join(Bar::<io::Cursor<Vec<u8>>>::ROOT_METADATA_FILE),
+        )
+        .unwrap();
+        rmpv::encode::write_value(&mut file, &meta).unwrap();
+        drop(file);
+
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let note = bar.file("a.txt").unwrap().meta.borrow().note.clone();
+        assert_eq!(note.as_deref(), Some("Found through $BAR_TEST_META_DIR"));
+
+        assert_eq!(
+            expand_env("$BAR_TEST_META_DIR/a/$BAR_TEST_UNSET_VAR/${BAR_TEST_UNSET_VAR}/$"),
+            format!(
+                "{}/a/$BAR_TEST_UNSET_VAR/${{BAR_TEST_UNSET_VAR}}/$",
+                dir.path().display()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_non_utf8_name() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"bad\xffname.txt");
+        std::fs::write(dir.path().join(name), "Data").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        match Bar::pack(dir.path(), back, "none".parse().unwrap(), false) {
+            Err(BarErr::NonUtf8Name(path)) => assert_eq!(path, dir.path().join(name)),
+            other => panic!("Expected a non UTF-8 name error, got {:?}", other),
+        }
+
+        std::fs::remove_file(dir.path().join(name)).unwrap();
+        std::fs::write(dir.path().join("good.txt"), "Data").unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        match bar.clone_entry("good.txt", name) {
+            Err(BarErr::NonUtf8Name(path)) => assert_eq!(path, std::path::Path::new(name)),
+            other => panic!("Expected a non UTF-8 name error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn test_layout_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Layout ".repeat(50)).unwrap();
+        std::fs::write(dir.path().join("b.txt"), "More layout data").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let mut saved = Vec::new();
+        bar.save(&mut saved, false).unwrap();
+
+        let mut bar = Bar::unpack_reader(io::Cursor::new(saved.clone())).unwrap();
+        let data_size = bar.data_size().unwrap();
+        let header_size = bar.header_size().unwrap();
+        assert_eq!(data_size, 350 + 16);
+        assert_eq!(data_size + header_size + 8 + 40, saved.len() as u64);
+
+        let header_end = (data_size + header_size) as usize;
+        assert!(
+            Bar::<io::Cursor<Vec<u8>>>::parse_header(&saved[data_size as usize..header_end])
+                .is_ok()
+        );
+        assert_eq!(&saved[header_end..header_end + 8], &data_size.to_le_bytes());
+    }
+
+    #[test]
+    pub fn test_thumbnail() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("video.mp4"), "Not really a video").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        assert_eq!(bar.thumbnail("video.mp4"), None);
+        bar.set_thumbnail("video.mp4", vec![0x89, b'P', b'N', b'G', 0, 1, 2])
+            .unwrap();
+        assert!(matches!(
+            bar.set_thumbnail("missing.mp4", vec![]),
+            Err(BarErr::NoEntry(_))
+        ));
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let bar = Bar::unpack_reader(saved).unwrap();
+        assert_eq!(
+            bar.thumbnail("video.mp4"),
+            Some(vec![0x89, b'P', b'N', b'G', 0, 1, 2])
+        );
+        assert_eq!(bar.thumbnail("missing.mp4"), None);
+    }
+
+    #[test]
+    pub fn test_pack_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("same.txt"), "Unchanged ".repeat(200)).unwrap();
+        std::fs::write(dir.path().join("sub/changed.txt"), "Old contents").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut base = Bar::pack(dir.path(), back, "fast-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        base.save(&mut saved, false).unwrap();
+        let mut base = Bar::unpack_reader(saved).unwrap();
+
+        std::fs::write(dir.path().join("sub/changed.txt"), "New contents").unwrap();
+        std::fs::write(dir.path().join("added.txt"), "Added").unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut diff = Bar::pack_diff(
+            dir.path(),
+            &mut base,
+            back,
+            "fast-gzip".parse().unwrap(),
+            false,
+        )
+        .unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        diff.save(&mut saved, false).unwrap();
+        let mut diff = Bar::unpack_reader(saved).unwrap();
+
+        assert!(diff.file("same.txt").unwrap().inherited());
+        assert!(!diff.file("sub/changed.txt").unwrap().inherited());
+        assert!(!diff.file("added.txt").unwrap().inherited());
+        assert!(matches!(
+            diff.open("same.txt"),
+            Err(BarErr::InheritedFile(_))
+        ));
+
+        //Only the changed and added files are stored in the differential archive
+        let stored = ["sub/changed.txt", "added.txt"]
+            .iter()
+            .map(|path| diff.file(path).unwrap().size as u64)
+            .sum::<u64>();
+        assert_eq!(diff.data_size().unwrap(), stored);
+
+        let out = tempfile::tempdir().unwrap();
+        diff.save_unpacked_with_base(out.path(), &mut base, false)
+            .unwrap();
+        let unpacked = out.path().join(&diff.meta().name);
+        for (path, contents) in [
+            ("same.txt", "Unchanged ".repeat(200)),
+            ("sub/changed.txt", "New contents".to_owned()),
+            ("added.txt", "Added".to_owned()),
+        ] {
+            assert_eq!(
+                std::fs::read_to_string(unpacked.join(path)).unwrap(),
+                contents
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_skip_fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Regular file").unwrap();
+        let status = std::process::Command::new("mkfifo")
+            .arg(dir.path().join("sub/pipe"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        assert!(bar.file("a.txt").is_some());
+        assert!(bar.entry("sub/pipe").is_none());
+        assert_eq!(bar.skipped(), [std::path::PathBuf::from("sub/pipe")]);
+    }
+
+    #[test]
+    pub fn test_copy_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        std::fs::write(input.join("a.txt"), "Copied file ".repeat(500)).unwrap();
+        let pack = |input: &path::Path, name: &str| {
+            let back = tempfile::tempfile().unwrap();
+            let mut bar = Bar::pack(input, back, "high-gzip".parse().unwrap(), false).unwrap();
+            let path = dir.path().join(name);
+            bar.save(&mut std::fs::File::create(&path).unwrap(), false)
+                .unwrap();
+            Bar::unpack(&path).unwrap()
+        };
+        let mut src = pack(&input, "src.bar");
+        std::fs::remove_file(input.join("a.txt")).unwrap();
+        std::fs::write(input.join("b.txt"), "Existing file").unwrap();
+        let mut dest = pack(&input, "dest.bar");
+        dest.set_hash_updates(true);
+
+        src.file_mut("a.txt").unwrap().normalized = true;
+        dest.copy_from(&mut src, "a.txt", "copied/a.txt").unwrap();
+        assert!(matches!(
+            dest.copy_from(&mut src, "a.txt", "b.txt"),
+            Err(BarErr::EntryExists(_))
+        ));
+        assert!(matches!(
+            dest.copy_from(&mut src, "missing.txt", "c.txt"),
+            Err(BarErr::NoEntry(_))
+        ));
+        drop(dest);
+
+        let mut dest = Bar::unpack(dir.path().join("dest.bar")).unwrap();
+        dest.verify_archive_hash().unwrap();
+        let (compression, raw) = dest.read_file_raw("copied/a.txt").unwrap();
+        assert_eq!(compression.1, entry::CompressMethod::Gzip);
+        assert_eq!(raw, src.read_file_raw("a.txt").unwrap().1);
+        let mut data = String::new();
+        dest.open("copied/a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Copied file ".repeat(500));
+        assert_eq!(
+            dest.file("copied/a.txt").unwrap().meta.borrow().name,
+            "a.txt"
+        );
+        assert!(dest.file("copied/a.txt").unwrap().normalized());
+        assert!(dest.file("b.txt").is_some());
+    }
+
+    #[test]
+    pub fn test_decompression_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bomb.bin"), vec![0u8; 1 << 20]).unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "high-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        assert_eq!(bar.decompression_limit, Some(DEFAULT_DECOMPRESSION_LIMIT));
+        let file = bar.file("bomb.bin").unwrap().clone();
+        assert!(file.size < 4096);
+        bar.set_decompression_limit(Some(1 << 16));
+        assert!(matches!(
+            bar.file_data(file.clone(), &mut io::sink(), true, false),
+            Err(BarErr::DecompressionLimitExceeded(name, limit)) if name == "bomb.bin" && limit == 1 << 16
+        ));
+        //The stored data is smaller than the limit, so it can still be copied without decompressing it
+        bar.file_data(file.clone(), &mut io::sink(), false, false)
+            .unwrap();
+
+        let err = io::copy(&mut bar.open("bomb.bin").unwrap(), &mut io::sink()).unwrap_err();
+        assert!(matches!(
+            err.into_inner().unwrap().downcast::<BarErr>().as_deref(),
+            Ok(BarErr::DecompressionLimitExceeded(_, _))
+        ));
+
+        bar.set_decompression_limit(Some(1 << 20));
+        let mut data = Vec::new();
+        bar.file_data(file, &mut data, true, false).unwrap();
+        assert_eq!(data.len(), 1 << 20);
+        assert_eq!(
+            io::copy(&mut bar.open("bomb.bin").unwrap(), &mut io::sink()).unwrap(),
+            1 << 20
+        );
+    }
+
+    #[test]
+    pub fn test_pack_with() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("small.txt"), "Small file").unwrap();
+        std::fs::write(dir.path().join("sub/big.txt"), "Big file ".repeat(1000)).unwrap();
+
+        let seen = RefCell::new(Vec::new());
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack_with(dir.path(), back, false, |path, size| {
+            seen.borrow_mut().push((path.to_path_buf(), size));
+            match size > 1000 {
+                true => "none".parse().unwrap(),
+                false => "high-gzip".parse().unwrap(),
+            }
+        })
+        .unwrap();
+        let mut seen = seen.into_inner();
+        seen.sort();
+        assert_eq!(
+            seen,
+            [
+                (path::PathBuf::from("small.txt"), 10),
+                (path::PathBuf::from("sub/big.txt"), 9000)
+            ]
+        );
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        let small = bar.file("small.txt").unwrap().compression;
+        let big = bar.file("sub/big.txt").unwrap().compression;
+        assert_eq!(small.1, CompressMethod::Gzip);
+        assert_eq!(big.1, CompressMethod::None);
+        assert_eq!(bar.file("sub/big.txt").unwrap().size, 9000);
+
+        let mut data = String::new();
+        bar.open("sub/big.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Big file ".repeat(1000));
+    }
+
+    #[test]
+    pub fn test_unpack_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "First file").unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "Second file ".repeat(100)).unwrap();
+
+        for (compress, redundant) in [(false, false), (true, true)] {
+            let back = io::Cursor::new(Vec::new());
+            let mut bar =
+                Bar::pack(dir.path(), back, "medium-gzip".parse().unwrap(), false).unwrap();
+            bar.set_compress_header(compress);
+            bar.set_redundant_header(redundant);
+            let mut file = tempfile::tempfile().unwrap();
+            bar.save(&mut file, false).unwrap();
+
+            let mut bytes = Vec::new();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_to_end(&mut bytes).unwrap();
+            let mut unpacked = Bar::unpack_bytes(bytes.as_slice()).unwrap();
+            assert_eq!(unpacked.compress_header, compress);
+            assert_eq!(unpacked.redundant_header, redundant);
+            let mut names = unpacked
+                .entries()
+                .map(|entry| entry.name())
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(names, ["a.txt", "sub"]);
+            let mut data = String::new();
+            unpacked
+                .open("sub/b.txt")
+                .unwrap()
+                .read_to_string(&mut data)
+                .unwrap();
+            assert_eq!(data, "Second file ".repeat(100));
+        }
+        assert!(Bar::unpack_bytes([0u8; 16]).is_err());
+    }
+
+    #[test]
+    pub fn test_read_file_range() {
+        let contents = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        for compression in ["none", "high-gzip"] {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("file.bin"), &contents).unwrap();
+            let back = io::Cursor::new(Vec::new());
+            let mut bar = Bar::pack(dir.path(), back, compression.parse().unwrap(), false).unwrap();
+            let mut saved = io::Cursor::new(Vec::new());
+            bar.save(&mut saved, false).unwrap();
+
+            let mut bar = Bar::unpack_reader(saved).unwrap();
+            assert_eq!(
+                bar.read_file_range("file.bin", 10, 10).unwrap(),
+                contents[10..20]
+            );
+            assert_eq!(
+                bar.read_file_range("file.bin", 990, 100).unwrap(),
+                contents[990..]
+            );
+            assert!(bar
+                .read_file_range("file.bin", 2000, 10)
+                .unwrap()
+                .is_empty());
+            assert!(matches!(
+                bar.read_file_range("missing.bin", 0, 10),
+                Err(BarErr::NoEntry(_))
+            ));
+        }
+    }
+
+    #[test]
+    pub fn test_inconsistent_trailer() {
+        let mut bar = Bar::new("trailer");
+        bar.header.meta.note = Some("Header data".into());
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let saved = saved.into_inner();
+        let (data_size, header_end) = (saved.len() - 48, saved.len() - 40);
+        assert!(Bar::unpack_reader(io::Cursor::new(saved.clone())).is_ok());
+
+        //Point the file data size past the end of the archive, then at the end of the header
+        for bad in [u64::MAX / 2, data_size as u64] {
+            let mut corrupt = saved.clone();
+            corrupt[data_size..header_end].copy_from_slice(&bad.to_le_bytes());
+            assert!(matches!(
+                Bar::unpack_reader(io::Cursor::new(corrupt)),
+                Err(BarErr::InvalidHeaderFormat(_))
+            ));
+        }
+        assert!(matches!(
+            Bar::unpack_reader(io::Cursor::new(vec![0u8; 4])),
+            Err(BarErr::InvalidHeaderFormat(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_rebuild_index() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/old.txt"), "Renamed file").unwrap();
+        std::fs::write(dir.path().join("sub/other.txt"), "Other file").unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+
+        bar.file("sub/old.txt").unwrap().meta.borrow_mut().name = "new.txt".into();
+        bar.dir("sub").unwrap().meta.borrow_mut().name = "renamed".into();
+        assert!(bar.file("renamed/new.txt").is_none());
+        bar.rebuild_index().unwrap();
+        assert!(bar.file("sub/old.txt").is_none());
+        assert!(bar.file("renamed/new.txt").is_some());
+        assert!(bar.file("renamed/other.txt").is_some());
+
+        let mut data = String::new();
+        bar.open("renamed/new.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Renamed file");
+
+        //Renaming an entry to the name of another entry in the same directory can't be fixed
+        bar.file("renamed/new.txt").unwrap().meta.borrow_mut().name = "other.txt".into();
+        assert!(matches!(bar.rebuild_index(), Err(BarErr::EntryExists(_))));
+        assert_eq!(bar.dir("renamed").unwrap().entries().count(), 2);
+    }
+
+    #[test]
+    pub fn test_normalize_line_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("crlf.txt"), "First line\r\nSecond line\r\n").unwrap();
+        std::fs::write(dir.path().join("lf.txt"), "First line\nSecond line\n").unwrap();
+        std::fs::write(dir.path().join("binary.bin"), b"Binary\0\r\ndata\r\n").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "fast-gzip".parse().unwrap(), false).unwrap();
+        assert_eq!(
+            bar.normalize_line_endings().unwrap(),
+            [
+                std::path::Path::new("crlf.txt"),
+                std::path::Path::new("lf.txt")
+            ]
+        );
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        assert!(bar.file("crlf.txt").unwrap().normalized());
+        assert!(bar.file("lf.txt").unwrap().normalized());
+        assert!(!bar.file("binary.bin").unwrap().normalized());
+
+        //Text is stored with LF line endings and binary files are stored unchanged
+        let mut data = Vec::new();
+        bar.open("crlf.txt")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"First line\nSecond line\n");
+        data.clear();
+        bar.open("binary.bin")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"Binary\0\r\ndata\r\n");
+
+        let expected: &[u8] = match cfg!(windows) {
+            true => b"First line\r\nSecond line\r\n",
+            false => b"First line\nSecond line\n",
+        };
+        for name in ["crlf.txt", "lf.txt"] {
+            let mut data = Vec::new();
+            let file = bar.file(name).unwrap().clone();
+            bar.file_data(file, &mut data, true, false).unwrap();
+            assert_eq!(data, expected);
+        }
+
+        let text = b"Mixed\r\nline\nendings\r\r\n";
+        assert_eq!(crlf_to_lf(text), b"Mixed\nline\nendings\r\n");
+        assert_eq!(lf_to_crlf(b"Mixed\nline\n"), b"Mixed\r\nline\r\n");
+    }
+
+    #[test]
+    pub fn test_multi_member_gzip() {
+        //Two gzip members written one after the other, like the output of `cat a.gz b.gz`
+        let mut members = Vec::new();
+        for text in ["First member ", "Second member"] {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(text.repeat(10).as_bytes()).unwrap();
+            members.extend(encoder.finish().unwrap());
+        }
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("members.gz"), &members).unwrap();
+
+        //The members are stored as they are and then read as one gzip compressed file
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        bar.header
+            .root
+            .data
+            .get_mut("members.gz")
+            .unwrap()
+            .as_file_mut()
+            .unwrap()
+            .compression = "medium-gzip".parse().unwrap();
+
+        let expected = "First member ".repeat(10) + &"Second member".repeat(10);
+        let file = bar.file("members.gz").unwrap().clone();
+        let mut data = Vec::new();
+        bar.file_data(file, &mut data, true, false).unwrap();
+        assert_eq!(String::from_utf8(data).unwrap(), expected);
+
+        let mut data = String::new();
+        bar.open("members.gz")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    pub fn test_pack_cancellable() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "First file").unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "Second file").unwrap();
+        let compression: CompressType = "fast-gzip".parse().unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let bar = Bar::pack_cancellable(
+            dir.path(),
+            io::Cursor::new(Vec::new()),
+            compression,
+            false,
+            &cancel,
+        )
+        .unwrap();
+        assert!(bar.file("sub/b.txt").is_some());
+
+        cancel.store(true, Ordering::Relaxed);
+        assert!(matches!(
+            Bar::pack_cancellable(
+                dir.path(),
+                io::Cursor::new(Vec::new()),
+                compression,
+                false,
+                &cancel
+            ),
+            Err(BarErr::Cancelled)
+        ));
+        //Cancelling doesn't write anything to the packed directory
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    pub fn test_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Packed file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let source = std::fs::canonicalize(dir.path()).unwrap();
+        assert_eq!(bar.source_path(), source.to_str());
+        assert_eq!(bar.created(), None);
+
+        std::env::remove_var("SOURCE_DATE_EPOCH");
+        let before = now().unwrap();
+        bar.record_created();
+        let after = now().unwrap();
+        assert!((before..=after).contains(&bar.created().unwrap()));
+        std::env::set_var("SOURCE_DATE_EPOCH", "1000");
+        bar.record_created();
+        std::env::remove_var("SOURCE_DATE_EPOCH");
+        assert_eq!(bar.created(), Some(1000));
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let unpacked = Bar::unpack_reader(saved).unwrap();
+        assert_eq!(unpacked.source_path(), source.to_str());
+        assert_eq!(unpacked.created(), bar.created());
+
+        //Archives that weren't packed from a directory have no provenance
+        let mut bar = Bar::new("empty");
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let unpacked = Bar::unpack_reader(saved).unwrap();
+        assert_eq!(unpacked.source_path(), None);
+        assert_eq!(unpacked.created(), None);
+    }
+
+    #[test]
+    pub fn test_files_sorted_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/nested")).unwrap();
+        std::fs::write(dir.path().join("small.txt"), "a".repeat(10)).unwrap();
+        std::fs::write(dir.path().join("sub/large.txt"), "b".repeat(1000)).unwrap();
+        std::fs::write(dir.path().join("sub/nested/medium.txt"), "c".repeat(100)).unwrap();
+        std::fs::write(dir.path().join("sub/nested/same.txt"), "d".repeat(100)).unwrap();
+        std::fs::write(dir.path().join("empty.txt"), "").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let sorted = bar.files_sorted_by_size();
+        let paths = sorted
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap().replace('\\', "/"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "sub/large.txt",
+                "sub/nested/medium.txt",
+                "sub/nested/same.txt",
+                "small.txt",
+                "empty.txt"
+            ]
+        );
+        assert!(sorted
+            .windows(2)
+            .all(|pair| pair[0].1.size() >= pair[1].1.size()));
+        assert_eq!(sorted[0].1.size(), 1000);
+    }
+
+    #[test]
+    pub fn test_ignore_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/build")).unwrap();
+        std::fs::create_dir_all(dir.path().join("build")).unwrap();
+        let files = [
+            "keep.txt",
+            "app.log",
+            "notes.tmp",
+            "build/out.bin",
+            "sub/debug.log",
+            "sub/scratch.tmp",
+            "sub/kept.txt",
+            "sub/build/out.bin",
+        ];
+        for file in files {
+            std::fs::write(dir.path().join(file), file).unwrap();
+        }
+        std::fs::write(dir.path().join(IGNORE_FILE), "# Logs\n*.log\n\n/build/\n").unwrap();
+        std::fs::write(dir.path().join("sub").join(IGNORE_FILE), "*.tmp\n").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        for kept in ["keep.txt", "notes.tmp", "sub/kept.txt", "sub/build/out.bin"] {
+            assert!(bar.file(kept).is_some(), "{} should be packed", kept);
+        }
+        //Patterns of the root ignore file apply to subdirectories, and patterns in a subdirectory only
+        //apply to its own entries
+        for ignored in ["app.log", "sub/debug.log", "sub/scratch.tmp", "build"] {
+            assert!(
+                bar.entry(ignored).is_none(),
+                "{} should be ignored",
+                ignored
+            );
+        }
+        assert!(bar.file(IGNORE_FILE).is_some());
+
+        std::fs::write(dir.path().join(IGNORE_FILE), "a[\n").unwrap();
+        let back = io::Cursor::new(Vec::new());
+        assert!(matches!(
+            Bar::pack(dir.path(), back, "none".parse().unwrap(), false),
+            Err(BarErr::InvalidIgnorePattern(pattern, _)) if pattern == "a["
+        ));
+    }
+
+    #[test]
+    pub fn test_raw_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Layout ".repeat(50)).unwrap();
+
+        for redundant in [false, true] {
+            let back = io::Cursor::new(Vec::new());
+            let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+            bar.set_redundant_header(redundant);
+            let mut saved = Vec::new();
+            bar.save(&mut saved, false).unwrap();
+
+            let mut bar = Bar::unpack_reader(io::Cursor::new(saved.clone())).unwrap();
+            let layout = bar.raw_layout().unwrap();
+            assert_eq!(layout.front_header.is_some(), redundant);
+            let data_start = layout.front_header.clone().map_or(0, |front| front.end);
+            assert_eq!(layout.data, data_start..data_start + 350);
+            assert_eq!(layout.data.end, bar.data_size().unwrap());
+            assert_eq!(layout.header.start, layout.data.end);
+            assert_eq!(
+                layout.header.end - layout.header.start,
+                bar.header_size().unwrap()
+            );
+            assert_eq!(layout.data_size, layout.header.end..layout.header.end + 8);
+            assert_eq!(layout.hash, Some(layout.data_size.end..saved.len() as u64));
+
+            let range =
+                |range: std::ops::Range<u64>| &saved[range.start as usize..range.end as usize];
+            assert_eq!(range(layout.data.clone()), "Layout ".repeat(50).as_bytes());
+            assert_eq!(range(layout.data_size), &layout.data.end.to_le_bytes());
+            assert!(Bar::<io::Cursor<Vec<u8>>>::parse_header(range(layout.header)).is_ok());
+            if let Some(front) = layout.front_header {
+                assert_eq!(&range(front)[..8], FRONT_HEADER_MAGIC);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_dir_metadata_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir_all(input.join("sub/nested")).unwrap();
+        std::fs::write(input.join("sub/nested/a.txt"), "Nested file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(&input, back, "none".parse().unwrap(), false).unwrap();
+        bar.dir("sub/nested").unwrap().meta.borrow_mut().note = Some("Nested note".into());
+        bar.dir("sub/nested").unwrap().meta.borrow_mut().used = true;
+        bar.dir("sub").unwrap().meta.borrow_mut().note = Some("Parent note".into());
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        let out = dir.path().join("out");
+        std::fs::create_dir(&out).unwrap();
+        bar.save_unpacked(&out, false).unwrap();
+
+        let check = |bar: &Bar<io::Cursor<Vec<u8>>>| {
+            let nested = bar.dir("sub/nested").unwrap().meta.borrow().clone();
+            assert_eq!(nested.note.as_deref(), Some("Nested note"));
+            assert!(nested.used);
+            assert_eq!(nested.name, "nested");
+            let sub = bar.dir("sub").unwrap().meta.borrow().clone();
+            assert_eq!(sub.note.as_deref(), Some("Parent note"));
+        };
+        //The archive is unpacked to a directory named after it, which is packed again through different
+        //spellings of the same path
+        let unpacked = out.join("input");
+        for path in [
+            unpacked.clone(),
+            dir.path().join("./out/input/"),
+            std::fs::canonicalize(&unpacked).unwrap(),
+        ] {
+            let back = io::Cursor::new(Vec::new());
+            let repacked = Bar::pack(&path, back, "none".parse().unwrap(), false).unwrap();
+            check(&repacked);
+        }
+    }
+
+    #[test]
+    pub fn test_entries_modified_since() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in [
+            "old.txt",
+            "sub/new.txt",
+            "sub/newest.txt",
+            "exact.txt",
+            "unknown.txt",
+        ] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let modified = |path: &str, time: Option<i64>| {
+            bar.file(path).unwrap().meta.borrow_mut().modified = time
+        };
+        modified("old.txt", Some(1_000));
+        modified("exact.txt", Some(2_000));
+        modified("sub/new.txt", Some(3_000));
+        modified("sub/newest.txt", Some(4_000));
+        modified("unknown.txt", None);
+
+        let since = |ts| {
+            bar.entries_modified_since(ts)
+                .map(|(path, file)| {
+                    assert_eq!(
+                        file.meta.borrow().name,
+                        path.file_name().unwrap().to_str().unwrap()
+                    );
+                    path.to_str().unwrap().replace('\\', "/")
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(since(2_000), ["sub/new.txt", "sub/newest.txt"]);
+        assert_eq!(
+            since(0),
+            ["exact.txt", "old.txt", "sub/new.txt", "sub/newest.txt"]
+        );
+        assert!(since(4_000).is_empty());
+    }
+
+    #[test]
+    pub fn test_list() {
+        use entry::EntryType;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/nested")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Top file").unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "Child file").unwrap();
+        std::fs::write(dir.path().join("sub/nested/c.txt"), "Nested file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        assert_eq!(
+            bar.list("").unwrap(),
+            [
+                ("a.txt".to_owned(), EntryType::File),
+                ("sub".to_owned(), EntryType::Dir)
+            ]
+        );
+        //Entries of subdirectories aren't listed
+        assert_eq!(
+            bar.list("sub").unwrap(),
+            [
+                ("b.txt".to_owned(), EntryType::File),
+                ("nested".to_owned(), EntryType::Dir)
+            ]
+        );
+        assert!(matches!(
+            bar.list("sub/b.txt"),
+            Err(BarErr::NotADirectory(_))
+        ));
+        assert!(matches!(bar.list("missing"), Err(BarErr::NoEntry(_))));
+    }
+
+    #[test]
+    pub fn test_strip_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "First file").unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "Second file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        for path in ["a.txt", "sub/b.txt", "sub"] {
+            bar.touch(path).unwrap();
+            bar.entry(path).unwrap().meta_mut().note = Some(format!("Note for {}", path));
+        }
+        bar.header.meta.note = Some("Archive note".to_owned());
+        bar.header.meta.used = true;
+        bar.header.meta.access_count = 3;
+        bar.header.meta.last_access = Some(1);
+        bar.record_created();
+        assert!(bar.file("a.txt").unwrap().meta.borrow().modified.is_some());
+        assert!(bar.source_path().is_some());
+
+        bar.strip_metadata(true, true, false, false);
+        for path in ["a.txt", "sub/b.txt", "sub"] {
+            let meta = bar.entry(path).unwrap().meta();
+            assert_eq!(meta.note, None);
+            assert!(!meta.used);
+            assert_eq!(meta.access_count, 0);
+            assert!(meta.last_access.is_some());
+        }
+        assert_eq!(bar.header.meta.note, None);
+        assert!(!bar.header.meta.used);
+        assert_eq!(bar.header.meta.access_count, 0);
+        assert_eq!(bar.header.meta.last_access, Some(1));
+        assert!(bar.header.created.is_some());
+        assert!(bar.source_path().is_some());
+
+        bar.strip_metadata(false, false, true, false);
+        for path in ["a.txt", "sub/b.txt", "sub"] {
+            let meta = bar.entry(path).unwrap().meta();
+            assert_eq!(meta.modified, None);
+            assert_eq!(meta.last_access, None);
+        }
+        assert_eq!(bar.header.meta.last_access, None);
+        assert_eq!(bar.header.created, None);
+        assert!(bar.source_path().is_some());
+
+        bar.strip_metadata(false, false, false, true);
+        assert_eq!(bar.source_path(), None);
+        assert_eq!(bar.file("sub/b.txt").unwrap().meta.borrow().name, "b.txt");
+        let mut data = String::new();
+        bar.open("sub/b.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Second file");
+    }
+
+    #[test]
+    pub fn test_persist_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir_all(input.join("sub")).unwrap();
+        std::fs::write(input.join("sub/a.txt"), "Persisted file ".repeat(100)).unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(&input, back, "none".parse().unwrap(), false).unwrap();
+
+        let mut persisted = bar.persist_to(dir.path().join("backing")).unwrap();
+        let mut data = String::new();
+        persisted
+            .open("sub/a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Persisted file ".repeat(100));
+
+        //An unpacked archive in memory becomes an archive file that can be updated in place
+        let path = dir.path().join("archive.bar");
+        let mut saved = Vec::new();
+        persisted.save(&mut saved, false).unwrap();
+        let unpacked = Bar::unpack_bytes(saved).unwrap();
+        let mut persisted = unpacked.persist_to(&path).unwrap();
+        persisted.set_hash_updates(true);
+        persisted.touch("sub/a.txt").unwrap();
+        persisted.save_updated(false).unwrap();
+        let mut bar = Bar::unpack(&path).unwrap();
+        bar.verify_archive_hash().unwrap();
+        assert!(bar.file("sub/a.txt").unwrap().meta.borrow().used);
+        let mut data = String::new();
+        bar.open("sub/a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Persisted file ".repeat(100));
+    }
+
+    #[test]
+    pub fn test_save_updated_front_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        std::fs::write(input.join("a.txt"), "First file").unwrap();
+        std::fs::write(input.join("b.txt"), "Second file").unwrap();
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(&input, back, "none".parse().unwrap(), false).unwrap();
+        bar.set_redundant_header(true);
+        let path = dir.path().join("archive.bar");
+        bar.save(&mut std::fs::File::create(&path).unwrap(), false)
+            .unwrap();
+
+        //Damage the header at the end after an update, so that only the front copy can be read
+        let damage = |path: &std::path::Path| {
+            let mut saved = std::fs::read(path).unwrap();
+            let (header_pos, header_size) =
+                Bar::get_header_pos(&mut io::Cursor::new(saved.as_slice())).unwrap();
+            saved[header_pos as usize..(header_pos + header_size) as usize].fill(0);
+            saved
+        };
+
+        let mut bar = Bar::unpack(&path).unwrap();
+        bar.remove_entry("b.txt").unwrap();
+        bar.touch("a.txt").unwrap();
+        bar.save_updated(false).unwrap();
+        let mut recovered = Bar::recover(io::Cursor::new(damage(&path))).unwrap();
+        assert!(recovered.file("b.txt").is_none());
+        assert!(recovered.file("a.txt").unwrap().meta.borrow().used);
+        let mut data = String::new();
+        recovered
+            .open("a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "First file");
+
+        //A header that outgrew its reserved space erases the front copy instead of leaving it outdated
+        let mut bar = Bar::unpack(&path).unwrap();
+        bar.set_hash_updates(true);
+        bar.entry("a.txt").unwrap().meta_mut().note = Some("A long note ".repeat(100));
+        bar.save_updated(false).unwrap();
+        let mut bar = Bar::unpack(&path).unwrap();
+        bar.verify_archive_hash().unwrap();
+        assert!(!bar.redundant_header);
+        assert!(bar.file("a.txt").unwrap().meta.borrow().note.is_some());
+        assert!(Bar::recover(io::Cursor::new(damage(&path))).is_err());
+    }
+
+    #[test]
+    pub fn test_save_temp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        std::fs::write(input.join("a.txt"), "Spooled file ".repeat(100)).unwrap();
+        let missing = dir.path().join("missing");
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(&input, back, "none".parse().unwrap(), false).unwrap();
+
+        //The file data is spooled in the chosen directory when a redundant header is written
+        bar.set_redundant_header(true);
+        bar.set_temp_dir(Some(missing.clone()));
+        assert!(matches!(
+            bar.save(&mut io::sink(), false),
+            Err(BarErr::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+        bar.set_temp_dir(Some(dir.path().to_path_buf()));
+        let mut solid = io::Cursor::new(Vec::new());
+        bar.save_solid(&mut solid, "fast-deflate".parse().unwrap(), false)
+            .unwrap();
+
+        //Saving a solid archive again decompresses its stream into the chosen directory
+        let mut bar = Bar::unpack_reader(solid).unwrap();
+        bar.set_temp_dir(Some(missing));
+        assert!(matches!(
+            bar.save(&mut io::sink(), false),
+            Err(BarErr::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+        bar.set_temp_dir(Some(dir.path().to_path_buf()));
+        let mut saved = Vec::new();
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(io::Cursor::new(saved)).unwrap();
+        let mut data = String::new();
+        bar.open("a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Spooled file ".repeat(100));
+    }
 }
@@ -2,11 +2,89 @@ use flate2::write::{DeflateEncoder, GzEncoder};
 use indicatif::ProgressBar;
 use std::{
     cell::RefCell,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     io::{Read, Seek, SeekFrom, Write},
     path,
 };
 
+/// An error returned when an entry's name can't be used, see [validate_name]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NameError {
+    #[error("Entry names can't be empty")]
+    Empty,
+
+    #[error("The name {0} is reserved")]
+    Reserved(String),
+
+    #[error("Entry names can't contain the character {0:?}")]
+    InvalidChar(char),
+
+    #[error("Entry names can't end with the character {0:?}")]
+    InvalidEnding(char),
+}
+
+/// The file name rules of a platform that entry names can be validated against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameRules {
+    /// Names can contain any character except `/` and null
+    Unix,
+
+    /// Names can't contain `\\ / < > : " | ? *` or control characters, can't end with `.` or a space, and
+    /// can't be a reserved device name like `CON` or `LPT1`
+    Windows,
+}
+
+impl NameRules {
+    /// Get the rules of the platform that this program was compiled for
+    pub const fn native() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Unix
+        }
+    }
+
+    /// Check that `name` can be used as the name of an entry that is unpacked on this platform
+    pub fn validate(self, name: &str) -> Result<(), NameError> {
+        if name.is_empty() {
+            return Err(NameError::Empty);
+        }
+        if name == "." || name == ".." {
+            return Err(NameError::Reserved(name.to_owned()));
+        }
+        let invalid = |c: char| match self {
+            Self::Unix => matches!(c, '/' | '\0'),
+            Self::Windows => {
+                c.is_ascii_control()
+                    || matches!(c, '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*')
+            }
+        };
+        if let Some(c) = name.chars().find(|c| invalid(*c)) {
+            return Err(NameError::InvalidChar(c));
+        }
+
+        if self == Self::Windows {
+            if let Some(c) = name.chars().last().filter(|c| matches!(c, '.' | ' ')) {
+                return Err(NameError::InvalidEnding(c));
+            }
+            let stem = name.split('.').next().unwrap_or(name).to_ascii_uppercase();
+            let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+                || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+                    && matches!(stem.as_bytes().get(3..), Some([b'1'..=b'9'])));
+            if reserved {
+                return Err(NameError::Reserved(name.to_owned()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that `name` can be used as the name of an entry on the platform this program was compiled for.
+/// See [NameRules::validate]
+pub fn validate_name(name: &str) -> Result<(), NameError> {
+    NameRules::native().validate(name)
+}
+
 /// The `CompressMethod` represents all ways that a [File]'s data can be compressed in the archive
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompressMethod {
@@ -18,7 +96,8 @@ pub enum CompressMethod {
     None,
 }
 
-/// The `CompressType` struct specifies both quality and mode of compression
+/// The `CompressType` struct specifies both quality and mode of compression. It is written as a string like
+/// `high-gzip`, where the quality is one of `high`, `medium`, or `fast`, or a numeric level from 0 to 9
 #[derive(Debug, Clone, Copy)]
 pub struct CompressType(pub flate2::Compression, pub CompressMethod);
 
@@ -35,7 +114,10 @@ impl std::str::FromStr for CompressType {
             "high" => flate2::Compression::best(),
             "fast" => flate2::Compression::fast(),
             "medium" => flate2::Compression::new(5),
-            other => return Err(other.to_string()),
+            other => match other.parse::<u32>() {
+                Ok(level) if level <= 9 => flate2::Compression::new(level),
+                _ => return Err(other.to_string()),
+            },
         };
         let method = match method {
             "gzip" => CompressMethod::Gzip,
@@ -47,16 +129,16 @@ impl std::str::FromStr for CompressType {
     }
 }
 
-impl ToString for CompressType {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for CompressType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.1 == CompressMethod::None {
-            return "none".into();
+            return write!(f, "none");
         }
         let quality = match self.0.level() {
-            9 => "high",
-            1 => "fast",
-            5 => "medium",
-            _ => unreachable!(),
+            9 => "high".to_owned(),
+            1 => "fast".to_owned(),
+            5 => "medium".to_owned(),
+            level => level.to_string(),
         };
 
         let method = match self.1 {
@@ -65,12 +147,25 @@ impl ToString for CompressType {
             CompressMethod::None => unreachable!(),
         };
 
-        quality.to_owned() + "-" + method
+        write!(f, "{}-{}", quality, method)
+    }
+}
+
+impl CompressType {
+    /// Estimate how long compressing `bytes` bytes with this compression takes, in units of the time it
+    /// takes to copy one byte without compressing it. The estimate is rough, but higher compression levels
+    /// always cost more, so it can be used to weight progress bars by compression time instead of size
+    pub fn cost(&self, bytes: u64) -> u64 {
+        let factor = match self.1 {
+            CompressMethod::None => 1,
+            CompressMethod::Deflate | CompressMethod::Gzip => 4 + 2 * self.0.level() as u64,
+        };
+        bytes.saturating_mul(factor)
     }
 }
 
 /// Metadata values that can be applied to all entries, like notes and if this entry has been used / watched before
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Meta {
     /// The note that a user left for this entry
     pub note: Option<String>,
@@ -80,6 +175,62 @@ pub struct Meta {
 
     /// The name of this entry
     pub name: String,
+
+    /// How many times this entry has been accessed
+    pub access_count: u32,
+
+    /// When this entry was last accessed, in seconds since the unix epoch
+    pub last_access: Option<i64>,
+
+    /// When this entry was last modified before it was packed, in seconds since the unix epoch
+    pub modified: Option<i64>,
+
+    /// A small preview image of this entry, stored in the header so it can be shown without reading file data
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+impl Meta {
+    /// Create new metadata for an entry with the given name and no other metadata set
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the note of this metadata
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+
+    /// Set if this entry has been used / watched
+    #[must_use]
+    pub fn with_used(mut self, used: bool) -> Self {
+        self.used = used;
+        self
+    }
+
+    /// Set the last modified time of this metadata from a filesystem modification time
+    #[must_use]
+    pub fn with_modified(mut self, modified: std::time::SystemTime) -> Self {
+        self.modified = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|time| time.as_secs() as i64);
+        self
+    }
+
+    /// Mark this entry as used, incrementing the access count and setting the last access time to now
+    pub fn touch(&mut self) {
+        self.used = true;
+        self.access_count = self.access_count.saturating_add(1);
+        self.last_access = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|time| time.as_secs() as i64);
+    }
 }
 
 /// The `File` entry is used in the [File](Entry::File) entry variant and contains all possible metadata like notes,
@@ -96,13 +247,44 @@ pub struct File {
 
     /// The size of this file in the file data section in bytes
     pub(crate) size: u32,
+
+    /// If this file's data is stored in the base archive of a differential archive instead of this
+    /// archive, see [pack_diff](fn@crate::ar::Bar::pack_diff)
+    pub(crate) inherited: bool,
+
+    /// If this file is text whose line endings were converted from CRLF to LF when it was packed, see
+    /// [normalize_line_endings](fn@crate::ar::Bar::normalize_line_endings)
+    pub(crate) normalized: bool,
 }
 
 impl File {
+    /// Create an entry for a file whose `size` bytes of data are at the start of a reader, to be compressed
+    /// into an archive with [write_data](fn@File::write_data)
+    pub(crate) fn unwritten(meta: Meta, compression: CompressType, size: u32) -> Self {
+        Self {
+            meta: RefCell::new(meta),
+            compression,
+            off: 0,
+            size,
+            inherited: false,
+            normalized: false,
+        }
+    }
+
     pub const fn compression(&self) -> &CompressType {
         &self.compression
     }
 
+    /// Check if this file's data is stored in the base archive of a differential archive
+    pub const fn inherited(&self) -> bool {
+        self.inherited
+    }
+
+    /// Check if this file's line endings were normalized to LF when it was packed
+    pub const fn normalized(&self) -> bool {
+        self.normalized
+    }
+
     /// Write this `File`s data to a writer, compressing / encrypting bytes as needed
     pub fn write_data<W: Write, R: Read + Seek>(
         &self,
@@ -111,7 +293,18 @@ impl File {
         reader: &mut R,
         prog: &ProgressBar,
     ) -> std::io::Result<Entry> {
+        //Data of inherited files stays in the base archive
+        if self.inherited {
+            return Ok(Entry::File(self.clone()));
+        }
         prog.set_message(format!("Saving file {}", self.meta.borrow().name));
+        log!(
+            debug,
+            "Compressing file {} with {} at offset {}",
+            self.meta.borrow().name,
+            self.compression,
+            off
+        );
 
         let this_prog = match prog.is_hidden() {
             false => ProgressBar::new(0).with_style(
@@ -123,6 +316,40 @@ impl File {
         };
 
         reader.seek(SeekFrom::Start(self.off))?;
+
+        //Uncompressed data is copied straight to the writer instead of being read into memory first
+        if self.compression.1 == CompressMethod::None {
+            this_prog.set_message("Copying file data");
+            let copied = std::io::copy(
+                &mut this_prog.wrap_read(reader).take(self.size as u64),
+                writer,
+            )?;
+            this_prog.finish_and_clear();
+            if copied != self.size as u64 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "File {} has {} bytes of data, but only {} could be read",
+                        self.meta.borrow().name,
+                        self.size,
+                        copied
+                    ),
+                ));
+            }
+
+            let ret = Entry::File(Self {
+                meta: self.meta.clone(),
+                off: *off,
+                size: self.size,
+                compression: self.compression,
+                inherited: false,
+                normalized: self.normalized,
+            });
+            *off += copied;
+            prog.inc(self.compression.cost(self.size as u64));
+            return Ok(ret);
+        }
+
         let mut buf = vec![0u8; self.size as usize];
 
         this_prog.set_message("Reading file data from archive");
@@ -163,6 +390,8 @@ impl File {
             off: *off,
             size: bytes.len() as u32,
             compression: self.compression,
+            inherited: false,
+            normalized: self.normalized,
         });
 
         this_prog.set_message("Writing compressed bytes");
@@ -171,6 +400,7 @@ impl File {
 
         *off += bytes.len() as u64;
         drop(bytes);
+        prog.inc(self.compression.cost(self.size as u64));
         Ok(ret)
     }
 
@@ -194,6 +424,8 @@ pub struct Dir {
 }
 
 impl Dir {
+    /// Write the data of every file in this directory and its subdirectories to a writer, in order of
+    /// entry name so that the same directory is always written the same way
     pub fn write_data<W: Write, R: Read + Seek>(
         &self,
         off: &mut u64,
@@ -206,6 +438,8 @@ impl Dir {
             data: self
                 .data
                 .iter()
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
                 .map(
                     |(key, val)| match val.write_file_data(off, writer, reader, prog) {
                         Ok(val) => Ok((key.clone(), val)),
@@ -216,9 +450,13 @@ impl Dir {
         }))
     }
 
-    /// Add an entry to the directory using its name
-    pub fn add_entry(&mut self, entry: Entry) {
-        self.data.insert(entry.name(), entry);
+    /// Add an entry to the directory using its name, returning an error if the name is not valid on this
+    /// platform
+    pub fn add_entry(&mut self, entry: Entry) -> Result<(), NameError> {
+        let name = entry.name();
+        validate_name(&name)?;
+        self.data.insert(name, entry);
+        Ok(())
     }
 
     fn get_entry<'a>(
@@ -251,6 +489,30 @@ impl Dir {
         }
     }
 
+    /// Get a directly contained entry by name, preferring an exact match and falling back to
+    /// comparing names case-insensitively
+    fn child_ci(&self, name: &str) -> Option<&Entry> {
+        self.data.get(name).or_else(|| {
+            let name = name.to_lowercase();
+            self.data
+                .iter()
+                .find(|(key, _)| key.to_lowercase() == name)
+                .map(|(_, entry)| entry)
+        })
+    }
+
+    fn get_entry_ci<'a>(
+        &self,
+        mut paths: impl Iterator<Item = path::Component<'a>>,
+    ) -> Option<&Entry> {
+        match paths.next() {
+            Some(path) => self
+                .child_ci(path.as_os_str().to_str().unwrap())?
+                .get_entry_ci(paths),
+            None => None,
+        }
+    }
+
     #[inline]
     pub fn entry(&self, paths: impl AsRef<path::Path>) -> Option<&Entry> {
         self.get_entry(paths.as_ref().components())
@@ -260,10 +522,26 @@ impl Dir {
         self.get_entry_mut(paths.as_ref().components())
     }
 
+    /// Get an entry like [entry](fn@Dir::entry), but compare each component of the path to entry
+    /// names case-insensitively. Exact matches are preferred when a directory contains several entries
+    /// that only differ in case
+    #[inline]
+    pub fn entry_ci(&self, paths: impl AsRef<path::Path>) -> Option<&Entry> {
+        self.get_entry_ci(paths.as_ref().components())
+    }
+
     /// Get an iterator over the contained entries
     #[inline]
     pub fn entries(&self) -> impl Iterator<Item = &Entry> {
-        self.data.iter().map(|(_, entry)| entry)
+        self.data.values()
+    }
+
+    /// Get an iterator over the contained entries sorted by name, which unlike [entries](fn@Dir::entries)
+    /// yields entries in the same order every time
+    pub fn entries_sorted(&self) -> impl Iterator<Item = &Entry> {
+        let mut entries = self.data.iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(name, _)| *name);
+        entries.into_iter().map(|(_, entry)| entry)
     }
 
     /// Get a mutable iterator over the contained entries
@@ -271,6 +549,48 @@ impl Dir {
     pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut Entry> {
         self.data.iter_mut().map(|(_, entry)| entry)
     }
+
+    /// Write one JSON object per line to a writer for every entry in this directory and all
+    /// subdirectories, with paths relative to this directory. Lines are written while the tree is
+    /// walked so the listing is never held in memory.
+    ///
+    /// Files are written as `{"path": ..., "type": "file", "size": ..., "compression": ...}` and
+    /// directories as `{"path": ..., "type": "dir"}`
+    pub fn write_manifest_jsonl(&self, w: &mut impl Write) -> std::io::Result<()> {
+        fn walk_dir(dir: &Dir, path: &path::Path, w: &mut impl Write) -> std::io::Result<()> {
+            for entry in dir.entries_sorted() {
+                let path = path.join(entry.name());
+                let line = match entry {
+                    Entry::File(file) => serde_json::json!({
+                        "path": path,
+                        "type": "file",
+                        "size": file.size,
+                        "compression": file.compression.to_string(),
+                    }),
+                    Entry::Dir(_) => serde_json::json!({
+                        "path": path,
+                        "type": "dir",
+                    }),
+                };
+                serde_json::to_writer(&mut *w, &line)?;
+                w.write_all(b"\n")?;
+
+                if let Entry::Dir(dir) = entry {
+                    walk_dir(dir, &path, w)?;
+                }
+            }
+            Ok(())
+        }
+
+        walk_dir(self, path::Path::new(""), w)
+    }
+}
+
+/// The kind of an [Entry], which can be checked without borrowing the entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryType {
+    File,
+    Dir,
 }
 
 /// The `Entry` struct represents one entry in the bar archive. It is the end result of parsing a
@@ -317,6 +637,15 @@ impl Entry {
         }
     }
 
+    /// Get whether this entry is a file or a directory
+    #[inline]
+    pub const fn entry_type(&self) -> EntryType {
+        match self {
+            Self::File(_) => EntryType::File,
+            Self::Dir(_) => EntryType::Dir,
+        }
+    }
+
     pub const fn as_dir(&self) -> Option<&Dir> {
         match self {
             Self::Dir(dir) => Some(dir),
@@ -355,7 +684,7 @@ impl Entry {
     }
 
     /// Get the metadata of this entry
-    pub fn meta(&self) -> std::cell::Ref<Meta> {
+    pub fn meta(&self) -> std::cell::Ref<'_, Meta> {
         match self {
             Self::Dir(ref dir) => dir.meta.borrow(),
             Self::File(ref file) => file.meta.borrow(),
@@ -363,13 +692,24 @@ impl Entry {
     }
 
     /// Get a mutable reference to this entry's metadata
-    pub fn meta_mut(&self) -> std::cell::RefMut<Meta> {
+    pub fn meta_mut(&self) -> std::cell::RefMut<'_, Meta> {
         match self {
             Self::File(f) => f.meta.borrow_mut(),
             Self::Dir(d) => d.meta.borrow_mut(),
         }
     }
 
+    /// Get the size of a file, or the total size of every file in a directory and its subdirectories. Sizes
+    /// are of the data stored in the archive, so this is the compressed size for unpacked archives and the
+    /// uncompressed size for packed archives that haven't been saved yet. The uncompressed size of a
+    /// compressed file isn't stored in the header, so it can only be found by decompressing the file
+    pub fn size_recursive(&self) -> u64 {
+        match self {
+            Self::File(file) => file.size as u64,
+            Self::Dir(dir) => dir.entries().map(Self::size_recursive).sum(),
+        }
+    }
+
     fn get_entry<'a>(
         &self,
         mut paths: impl Iterator<Item = path::Component<'a>>,
@@ -388,6 +728,21 @@ impl Entry {
         }
     }
 
+    fn get_entry_ci<'a>(
+        &self,
+        mut paths: impl Iterator<Item = path::Component<'a>>,
+    ) -> Option<&Entry> {
+        match paths.next() {
+            Some(path) => match self {
+                Self::Dir(dir) => dir
+                    .child_ci(path.as_os_str().to_str().unwrap())?
+                    .get_entry_ci(paths),
+                Self::File(_) => None,
+            },
+            None => Some(self),
+        }
+    }
+
     fn get_entry_mut<'a>(
         &mut self,
         mut paths: impl Iterator<Item = path::Component<'a>>,
@@ -419,10 +774,7 @@ mod tests {
         root.data.insert(
             "test".into(),
             Entry::Dir(Dir {
-                meta: RefCell::new(Meta {
-                    name: "test".into(),
-                    ..Default::default()
-                }),
+                meta: RefCell::new(Meta::new("test")),
                 ..Default::default()
             }),
         );
@@ -431,10 +783,9 @@ mod tests {
             Entry::Dir(dir) => dir.data.insert(
                 "test.txt".into(),
                 Entry::File(File {
-                    meta: RefCell::new(Meta {
-                        name: "test.txt".into(),
-                        ..Default::default()
-                    }),
+                    inherited: false,
+                    normalized: false,
+                    meta: RefCell::new(Meta::new("test.txt")),
                     compression: "none".parse().unwrap(),
                     off: 0,
                     size: 0,
@@ -444,4 +795,234 @@ mod tests {
         };
         let _ = root.entry("test/test.txt").unwrap();
     }
+
+    #[test]
+    pub fn test_entry_ci() {
+        let mut root = Dir::default();
+        let mut docs = Dir {
+            meta: RefCell::new(Meta::new("Docs")),
+            ..Default::default()
+        };
+        docs.add_entry(Entry::File(File {
+            inherited: false,
+            normalized: false,
+            meta: RefCell::new(Meta::new("Report.txt")),
+            compression: "none".parse().unwrap(),
+            off: 0,
+            size: 0,
+        }))
+        .unwrap();
+        root.add_entry(Entry::Dir(docs)).unwrap();
+
+        assert!(root.entry("docs/report.txt").is_none());
+        let entry = root.entry_ci("docs/report.TXT").unwrap();
+        assert_eq!(entry.name(), "Report.txt");
+        assert!(root.entry_ci("DOCS").unwrap().as_dir().is_some());
+        assert!(root.entry_ci("docs/missing.txt").is_none());
+    }
+
+    #[test]
+    pub fn test_manifest_jsonl() {
+        let file = |name: &str| {
+            Entry::File(File {
+                inherited: false,
+                normalized: false,
+                meta: RefCell::new(Meta::new(name)),
+                compression: "high-gzip".parse().unwrap(),
+                off: 0,
+                size: 10,
+            })
+        };
+        let mut nested = Dir {
+            meta: RefCell::new(Meta::new("nested")),
+            ..Default::default()
+        };
+        nested.add_entry(file("b.txt")).unwrap();
+        nested.add_entry(file("c.txt")).unwrap();
+        let mut root = Dir::default();
+        root.add_entry(file("a.txt")).unwrap();
+        root.add_entry(Entry::Dir(nested)).unwrap();
+
+        let mut manifest = Vec::new();
+        root.write_manifest_jsonl(&mut manifest).unwrap();
+        let lines = String::from_utf8(manifest).unwrap();
+        let lines = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 4);
+        let nested_file = lines
+            .iter()
+            .find(|line| line["path"] == "nested/b.txt")
+            .unwrap();
+        assert_eq!(nested_file["type"], "file");
+        assert_eq!(nested_file["size"], 10);
+        assert_eq!(nested_file["compression"], "high-gzip");
+    }
+
+    #[test]
+    pub fn test_meta_builder() {
+        let meta = Meta::new("movie.mp4")
+            .with_note("Watch again")
+            .with_used(true);
+        assert_eq!(meta.name, "movie.mp4");
+        assert_eq!(meta.note.as_deref(), Some("Watch again"));
+        assert!(meta.used);
+        assert_eq!(meta.access_count, 0);
+        assert_eq!(meta.last_access, None);
+
+        let meta = Meta::new(String::from("plain"));
+        assert_eq!(meta.note, None);
+        assert!(!meta.used);
+    }
+
+    #[test]
+    pub fn test_entries_sorted() {
+        let mut dir = Dir::default();
+        let names = [
+            "kiwi", "apple", "mango", "Zebra", "banana", "cherry", "10", "2",
+        ];
+        for name in names {
+            dir.add_entry(Entry::Dir(Dir {
+                meta: RefCell::new(Meta::new(name)),
+                ..Default::default()
+            }))
+            .unwrap();
+        }
+
+        let sorted = dir.entries_sorted().map(Entry::name).collect::<Vec<_>>();
+        let mut expected = names.to_vec();
+        expected.sort_unstable();
+        assert_eq!(sorted, expected);
+
+        let manifest = |dir: &Dir| {
+            let mut manifest = Vec::new();
+            dir.write_manifest_jsonl(&mut manifest).unwrap();
+            manifest
+        };
+        assert_eq!(manifest(&dir), manifest(&dir.clone()));
+        let first = String::from_utf8(manifest(&dir)).unwrap();
+        assert!(first.starts_with(r#"{"path":"10","type":"dir"}"#));
+    }
+
+    #[test]
+    pub fn test_compress_type_levels() {
+        let named: CompressType = "medium-deflate".parse().unwrap();
+        assert_eq!(named.0.level(), 5);
+        assert_eq!(named.to_string(), "medium-deflate");
+
+        let numeric: CompressType = "7-gzip".parse().unwrap();
+        assert_eq!(numeric.0.level(), 7);
+        assert_eq!(numeric.1, CompressMethod::Gzip);
+        assert_eq!(numeric.to_string(), "7-gzip");
+
+        //Levels that have a name are written with it
+        assert_eq!(
+            "9-deflate".parse::<CompressType>().unwrap().to_string(),
+            "high-deflate"
+        );
+        assert!("10-gzip".parse::<CompressType>().is_err());
+        assert!("ultra-gzip".parse::<CompressType>().is_err());
+    }
+
+    #[test]
+    pub fn test_compress_cost() {
+        let cost = |compression: &str| compression.parse::<CompressType>().unwrap().cost(1000);
+        assert_eq!(cost("none"), 1000);
+        assert!(cost("fast-gzip") > cost("none"));
+        assert!(cost("medium-gzip") > cost("fast-gzip"));
+        assert!(cost("high-gzip") > cost("medium-gzip"));
+        assert!(cost("high-deflate") > cost("8-deflate"));
+        assert_eq!(
+            cost("0-deflate") * 2,
+            "0-deflate".parse::<CompressType>().unwrap().cost(2000)
+        );
+        assert_eq!(
+            CompressType(flate2::Compression::best(), CompressMethod::Gzip).cost(u64::MAX),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    pub fn test_validate_name() {
+        for name in [
+            "file.txt",
+            "dir",
+            ".hidden",
+            "name with spaces",
+            "conference.txt",
+            "COM0",
+        ] {
+            assert_eq!(NameRules::Unix.validate(name), Ok(()));
+            assert_eq!(NameRules::Windows.validate(name), Ok(()));
+        }
+        for rules in [NameRules::Unix, NameRules::Windows] {
+            assert_eq!(rules.validate(""), Err(NameError::Empty));
+            assert_eq!(rules.validate(".."), Err(NameError::Reserved("..".into())));
+            assert_eq!(rules.validate("a/b"), Err(NameError::InvalidChar('/')));
+            assert_eq!(rules.validate("a\0b"), Err(NameError::InvalidChar('\0')));
+        }
+
+        for name in [
+            "a\\b", "what?", "a:b", "end.", "end ", "CON", "nul.txt", "Lpt3",
+        ] {
+            assert_eq!(NameRules::Unix.validate(name), Ok(()));
+            assert!(NameRules::Windows.validate(name).is_err());
+        }
+        assert_eq!(
+            NameRules::Windows.validate("aux.tar.gz"),
+            Err(NameError::Reserved("aux.tar.gz".into()))
+        );
+        assert_eq!(
+            NameRules::Windows.validate("end."),
+            Err(NameError::InvalidEnding('.'))
+        );
+
+        let mut dir = Dir::default();
+        let named = |name: &str| {
+            Entry::Dir(Dir {
+                meta: RefCell::new(Meta::new(name)),
+                ..Default::default()
+            })
+        };
+        assert!(dir.add_entry(named("a/b")).is_err());
+        assert!(dir.add_entry(named("ok")).is_ok());
+        assert_eq!(dir.data.len(), 1);
+    }
+
+    #[test]
+    pub fn test_size_recursive() {
+        let file = |name: &str, size: u32| {
+            Entry::File(File {
+                inherited: false,
+                normalized: false,
+                meta: RefCell::new(Meta::new(name)),
+                compression: "none".parse().unwrap(),
+                off: 0,
+                size,
+            })
+        };
+        let dir = |name: &str, entries: Vec<Entry>| {
+            let mut dir = Dir {
+                meta: RefCell::new(Meta::new(name)),
+                ..Default::default()
+            };
+            for entry in entries {
+                dir.add_entry(entry).unwrap();
+            }
+            Entry::Dir(dir)
+        };
+
+        let nested = dir("nested", vec![file("c.txt", 300), dir("empty", vec![])]);
+        let sub = dir("sub", vec![file("b.txt", 20), nested]);
+        let root = dir("root", vec![file("a.txt", 4), sub]);
+        assert_eq!(root.size_recursive(), 324);
+        assert_eq!(root.entry("sub").unwrap().size_recursive(), 320);
+        assert_eq!(root.entry("sub/nested/empty").unwrap().size_recursive(), 0);
+        assert_eq!(
+            root.entry("sub/nested/c.txt").unwrap().size_recursive(),
+            300
+        );
+    }
 }
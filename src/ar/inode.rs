@@ -0,0 +1,152 @@
+//! The `inode` module contains the [Inodes] struct, used to number the entries of an archive like the
+//! inodes of a filesystem
+
+use super::entry::{self, Entry};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A single numbered entry in an [Inodes] table
+#[derive(Debug)]
+struct Node {
+    /// Path of the entry from the archive's root directory
+    path: PathBuf,
+
+    /// Inode of the directory containing this entry, the root directory is its own parent
+    parent: u64,
+
+    /// Inodes of the entries in this directory sorted by name, empty for files
+    children: Vec<u64>,
+}
+
+/// `Inodes` assigns a stable number to every entry in an archive so that the archive can be exposed as a
+/// read-only filesystem, where entries are looked up by inode instead of by path. The root directory is
+/// always [ROOT](Inodes::ROOT), and the other entries are numbered depth-first in name order so the same
+/// archive is always numbered the same way
+#[derive(Debug)]
+pub struct Inodes {
+    /// Numbered entries, indexed by inode minus [ROOT](Inodes::ROOT)
+    nodes: Vec<Node>,
+
+    /// Inodes of entries by the inode of their parent directory and their name
+    names: HashMap<(u64, String), u64>,
+}
+
+impl Inodes {
+    /// The inode of the root directory
+    pub const ROOT: u64 = 1;
+
+    /// Number every entry in `root` and its subdirectories
+    pub fn new(root: &entry::Dir) -> Self {
+        let mut inodes = Self {
+            nodes: vec![Node {
+                path: PathBuf::new(),
+                parent: Self::ROOT,
+                children: Vec::new(),
+            }],
+            names: HashMap::new(),
+        };
+        inodes.add_dir(Self::ROOT, root);
+        inodes
+    }
+
+    /// Number the entries of `dir`, which has already been numbered as `ino`
+    fn add_dir(&mut self, ino: u64, dir: &entry::Dir) {
+        for entry in dir.entries_sorted() {
+            let name = entry.name();
+            let child = Self::ROOT + self.nodes.len() as u64;
+            self.nodes.push(Node {
+                path: self.node(ino).path.join(&name),
+                parent: ino,
+                children: Vec::new(),
+            });
+            self.nodes[(ino - Self::ROOT) as usize].children.push(child);
+            self.names.insert((ino, name), child);
+            if let Entry::Dir(dir) = entry {
+                self.add_dir(child, dir);
+            }
+        }
+    }
+
+    /// Get the node numbered `ino`, panicking if it does not exist
+    fn node(&self, ino: u64) -> &Node {
+        &self.nodes[(ino - Self::ROOT) as usize]
+    }
+
+    /// Get the node numbered `ino`, or `None` if no entry has that inode
+    fn get(&self, ino: u64) -> Option<&Node> {
+        ino.checked_sub(Self::ROOT)
+            .and_then(|i| self.nodes.get(i as usize))
+    }
+
+    /// Get the number of entries in the table, including the root directory
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if the table is empty, which is never true because the root directory is always numbered
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Get the inode of the entry named `name` in the directory numbered `parent`
+    pub fn lookup(&self, parent: u64, name: &str) -> Option<u64> {
+        self.names.get(&(parent, name.to_owned())).copied()
+    }
+
+    /// Get the path of the entry numbered `ino` from the archive's root directory, which can be passed to
+    /// [entry](fn@super::Bar::entry) or [open](fn@super::Bar::open). The root directory's path is empty
+    pub fn path(&self, ino: u64) -> Option<&Path> {
+        self.get(ino).map(|node| node.path.as_path())
+    }
+
+    /// Get the inode of the directory containing the entry numbered `ino`
+    pub fn parent(&self, ino: u64) -> Option<u64> {
+        self.get(ino).map(|node| node.parent)
+    }
+
+    /// Get the inodes of the entries in the directory numbered `ino` sorted by name
+    pub fn children(&self, ino: u64) -> Option<&[u64]> {
+        self.get(ino).map(|node| node.children.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ar::Bar;
+    use std::io;
+
+    #[test]
+    pub fn test_inodes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/nested")).unwrap();
+        std::fs::write(dir.path().join("b.txt"), "Second file").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "First file").unwrap();
+        std::fs::write(dir.path().join("sub/nested/c.txt"), "Third file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let inodes = Inodes::new(bar.root());
+        assert_eq!(inodes.len(), 6);
+        assert_eq!(inodes.path(Inodes::ROOT), Some(Path::new("")));
+        assert_eq!(inodes.parent(Inodes::ROOT), Some(Inodes::ROOT));
+        assert_eq!(inodes.path(0), None);
+        assert_eq!(inodes.path(7), None);
+
+        //Entries are numbered depth-first in name order
+        assert_eq!(inodes.children(Inodes::ROOT), Some(&[2, 3, 4][..]));
+        assert_eq!(inodes.lookup(Inodes::ROOT, "a.txt"), Some(2));
+        assert_eq!(inodes.lookup(Inodes::ROOT, "missing"), None);
+
+        let sub = inodes.lookup(Inodes::ROOT, "sub").unwrap();
+        let nested = inodes.lookup(sub, "nested").unwrap();
+        let file = inodes.lookup(nested, "c.txt").unwrap();
+        assert_eq!(inodes.path(file), Some(Path::new("sub/nested/c.txt")));
+        assert_eq!(inodes.parent(file), Some(nested));
+        assert_eq!(inodes.children(file), Some(&[][..]));
+        assert!(bar.file(inodes.path(file).unwrap()).is_some());
+        assert_eq!(Inodes::new(bar.root()).lookup(nested, "c.txt"), Some(file));
+    }
+}
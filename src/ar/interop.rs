@@ -0,0 +1,337 @@
+//! Conversions between bar archives and other common archive formats like tar and zip
+
+use super::bar::{create_dirs, path_names, Solid};
+use super::entry::{self, CompressMethod, CompressType, Entry, Meta};
+use super::{Bar, BarResult};
+use std::{
+    cell::RefCell,
+    io::{self, Read, Seek, Write},
+    path,
+};
+
+impl Bar<io::Cursor<Vec<u8>>> {
+    /// Convert a tar archive into a `Bar` archive with an in-memory `Vec` as backing storage.
+    /// Every regular file in the tar archive is copied into the backing storage and will be
+    /// compressed using `compression` when the archive is saved. Entries that are not regular files
+    /// or directories, like symlinks, are skipped
+    pub fn from_tar<R: Read>(reader: R, compression: CompressType) -> BarResult<Self> {
+        let mut bar = Self::new("tar");
+        let mut tar = tar::Archive::new(reader);
+        let mut off = 0u64; //The current offset into the backing storage
+
+        for file in tar.entries()? {
+            let mut file = file?;
+            let names = path_names(&file.path()?);
+            let (name, parents) = match names.split_last() {
+                Some(split) => split,
+                None => continue,
+            };
+            let parent = create_dirs(&mut bar.header.root, parents)?;
+
+            let kind = file.header().entry_type();
+            if kind.is_dir() {
+                create_dirs(parent, std::slice::from_ref(name))?;
+            } else if kind.is_file() {
+                let size = io::copy(&mut file, &mut bar.data)?;
+                parent.add_entry(Entry::File(entry::File {
+                    inherited: false,
+                    normalized: false,
+                    meta: RefCell::new(Meta::new(name.clone())),
+                    compression,
+                    off,
+                    size: size as u32,
+                }))?;
+                off += size;
+            }
+        }
+
+        Ok(bar)
+    }
+
+    /// Convert a zip archive into a `Bar` archive with an in-memory `Vec` as backing storage.
+    /// Files that were stored without compression in the zip archive are stored uncompressed, and
+    /// all other files are compressed with DEFLATE at medium quality when the archive is saved
+    pub fn from_zip<R: Read + Seek>(reader: R) -> BarResult<Self> {
+        let mut bar = Self::new("zip");
+        let mut zip = zip::ZipArchive::new(reader)?;
+        let mut off = 0u64; //The current offset into the backing storage
+
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            let names = match file.enclosed_name() {
+                Some(path) => path_names(path),
+                None => continue,
+            };
+            let (name, parents) = match names.split_last() {
+                Some(split) => split,
+                None => continue,
+            };
+            let parent = create_dirs(&mut bar.header.root, parents)?;
+
+            if file.is_dir() {
+                create_dirs(parent, std::slice::from_ref(name))?;
+            } else {
+                let compression = match file.compression() {
+                    zip::CompressionMethod::Stored => "none",
+                    _ => "medium-deflate",
+                };
+                let size = io::copy(&mut file, &mut bar.data)?;
+                parent.add_entry(Entry::File(entry::File {
+                    inherited: false,
+                    normalized: false,
+                    meta: RefCell::new(Meta::new(name.clone())),
+                    compression: compression.parse().unwrap(),
+                    off,
+                    size: size as u32,
+                }))?;
+                off += size;
+            }
+        }
+
+        Ok(bar)
+    }
+}
+
+impl<S: Read + Seek> Bar<S> {
+    /// Export this archive as a tar archive, decompressing every file and writing it to `w` with a
+    /// tar header. Directories are written as directory entries, and because bar archives don't
+    /// store modes, files are given mode `644` and directories mode `755`. Entries keep their stored
+    /// modification time, and entries without one are given a modification time of 0
+    pub fn to_tar<W: Write>(&mut self, w: W) -> BarResult<()> {
+        /// Recursively write a directory's contents to the tar builder
+        fn write_dir<S: Read + Seek, W: Write>(
+            dir: &entry::Dir,
+            path: &path::Path,
+            back: &mut S,
+            solid: Option<&Solid>,
+            limit: Option<u64>,
+            builder: &mut tar::Builder<W>,
+        ) -> BarResult<()> {
+            for entry in dir.entries() {
+                let path = path.join(entry.name());
+                let mut header = tar::Header::new_gnu();
+                header.set_mtime(entry.meta().modified.map_or(0, |time| time.max(0) as u64));
+                match entry {
+                    Entry::Dir(dir) => {
+                        header.set_entry_type(tar::EntryType::Directory);
+                        header.set_mode(0o755);
+                        header.set_size(0);
+                        builder.append_data(&mut header, &path, io::empty())?;
+                        write_dir(dir, &path, back, solid, limit, builder)?;
+                    }
+                    Entry::File(file) => {
+                        let mut data = Vec::with_capacity(file.size as usize);
+                        Bar::save_file(file, &mut data, back, solid, true, false, limit)?;
+
+                        header.set_entry_type(tar::EntryType::Regular);
+                        header.set_mode(0o644);
+                        header.set_size(data.len() as u64);
+                        builder.append_data(&mut header, &path, data.as_slice())?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        let mut builder = tar::Builder::new(w);
+        write_dir(
+            &self.header.root,
+            path::Path::new(""),
+            &mut self.data,
+            self.header.solid.as_ref(),
+            self.decompression_limit,
+            &mut builder,
+        )?;
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    /// Export this archive as a zip archive, decompressing every file and writing it to `w`.
+    /// Files compressed with DEFLATE or gzip are compressed with DEFLATE at the same quality in the zip
+    /// archive, and uncompressed files are stored without compression
+    pub fn to_zip<W: Write + Seek>(&mut self, w: W) -> BarResult<()> {
+        /// Recursively write a directory's contents to the zip writer
+        fn write_dir<S: Read + Seek, W: Write + Seek>(
+            dir: &entry::Dir,
+            path: &str,
+            back: &mut S,
+            solid: Option<&Solid>,
+            limit: Option<u64>,
+            zip: &mut zip::ZipWriter<W>,
+        ) -> BarResult<()> {
+            for entry in dir.entries() {
+                let path = format!("{}{}", path, entry.name());
+                match entry {
+                    Entry::Dir(dir) => {
+                        zip.add_directory(path.as_str(), Default::default())?;
+                        write_dir(dir, &(path + "/"), back, solid, limit, zip)?;
+                    }
+                    Entry::File(file) => {
+                        let options = match file.compression {
+                            CompressType(_, CompressMethod::None) => {
+                                zip::write::FileOptions::default()
+                                    .compression_method(zip::CompressionMethod::Stored)
+                            }
+                            CompressType(quality, _) => zip::write::FileOptions::default()
+                                .compression_method(zip::CompressionMethod::Deflated)
+                                .compression_level(Some(quality.level() as i32)),
+                        };
+                        zip.start_file(path, options)?;
+                        Bar::save_file(file, zip, back, solid, true, false, limit)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        let mut zip = zip::ZipWriter::new(w);
+        write_dir(
+            &self.header.root,
+            "",
+            &mut self.data,
+            self.header.solid.as_ref(),
+            self.decompression_limit,
+            &mut zip,
+        )?;
+        zip.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Build a small tar archive in memory from a list of paths and file contents
+    fn build_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "empty/", io::empty())
+            .unwrap();
+
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    pub fn test_from_tar() {
+        let files: &[(&str, &[u8])] = &[
+            ("a.txt", b"Top level file"),
+            ("dir/b.txt", b"Nested file contents"),
+            ("dir/nested/c.txt", b"Deeply nested file contents"),
+        ];
+        let tar = build_tar(files);
+
+        let mut bar = Bar::from_tar(tar.as_slice(), "high-gzip".parse().unwrap()).unwrap();
+        assert!(bar.dir("empty").is_some());
+        assert!(bar.dir("dir/nested").is_some());
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        for (path, data) in files {
+            let file = bar.file(path).unwrap().clone();
+            let mut out = Vec::new();
+            bar.file_data(file, &mut out, true, false).unwrap();
+            assert_eq!(out.as_slice(), *data);
+        }
+    }
+
+    #[test]
+    pub fn test_to_tar() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/nested")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"Top level file").unwrap();
+        std::fs::write(dir.path().join("sub/nested/b.txt"), b"Nested file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "fast-deflate".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        let mut tar = Vec::new();
+        bar.to_tar(&mut tar).unwrap();
+
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let mut files = HashMap::new();
+        let mut dirs = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().into_owned();
+            let modified = bar.entry(&path).unwrap().meta().modified;
+            assert!(modified.is_some());
+            assert_eq!(entry.header().mtime().unwrap(), modified.unwrap() as u64);
+            if entry.header().entry_type().is_dir() {
+                dirs.push(path);
+            } else {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                files.insert(path, data);
+            }
+        }
+
+        dirs.sort();
+        assert_eq!(
+            dirs,
+            vec![
+                path::PathBuf::from("sub"),
+                path::PathBuf::from("sub/nested")
+            ]
+        );
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[path::Path::new("a.txt")], b"Top level file");
+        assert_eq!(files[path::Path::new("sub/nested/b.txt")], b"Nested file");
+    }
+
+    #[test]
+    pub fn test_zip_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/nested")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"Top level file").unwrap();
+        std::fs::write(dir.path().join("sub/nested/b.txt"), b"Nested file").unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "high-deflate".parse().unwrap(), false).unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+
+        let mut zip = io::Cursor::new(Vec::new());
+        bar.to_zip(&mut zip).unwrap();
+        zip.set_position(0);
+
+        let mut bar = Bar::from_zip(zip).unwrap();
+        assert!(bar.dir("sub/nested").is_some());
+        assert_eq!(
+            bar.file("a.txt").unwrap().compression().1,
+            CompressMethod::Deflate
+        );
+
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        let mut bar = Bar::unpack_reader(saved).unwrap();
+        for (path, data) in [
+            ("a.txt", b"Top level file" as &[u8]),
+            ("sub/nested/b.txt", b"Nested file"),
+        ] {
+            let file = bar.file(path).unwrap().clone();
+            let mut out = Vec::new();
+            bar.file_data(file, &mut out, true, false).unwrap();
+            assert_eq!(out.as_slice(), data);
+        }
+    }
+}
@@ -1,67 +1,729 @@
+/// Log a message at the given level with the `log` crate if the `log` feature is enabled. Without the
+/// feature, the arguments are still type checked but nothing is formatted or logged
+macro_rules! log {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::$level!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        {
+            let _ = || format!($($arg)+);
+        }
+    }};
+}
+
+#[cfg(feature = "async")]
+mod async_bar;
 pub mod bar;
 pub mod entry;
+mod inode;
+mod interop;
+mod reader;
+mod shared;
+mod transaction;
+mod writer;
 
-use bar::{ser_header, Header};
-pub use bar::{Bar, BarErr, BarResult};
-use byteorder::{LittleEndian, WriteBytesExt};
+#[cfg(feature = "async")]
+pub use async_bar::AsyncBar;
+use bar::Endian;
+use bar::{front_header, write_header, Header, Solid};
+pub use bar::{Bar, BarErr, BarResult, EntryCounts, RawLayout};
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt};
 use indicatif::{ProgressBar, ProgressStyle};
+pub use inode::Inodes;
+pub use reader::FileReader;
+use sha2::{Digest, Sha256};
+pub use shared::{SharedBar, SharedReader};
+pub use transaction::Transaction;
+pub use writer::AppendWriter;
 
-use entry::{CompressType, Entry, Meta};
+use entry::{CompressMethod, CompressType, Entry, Meta};
+use flate2::write::{DeflateEncoder, GzEncoder};
 use std::cell::RefCell;
-use std::io::{self, Seek, SeekFrom, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// The order and compression that file data is written in when an archive is saved
+#[derive(Clone, Copy)]
+enum Layout {
+    /// Files are written in directory tree order and compressed individually
+    Tree,
+
+    /// Files are written ordered by extension and then name and compressed individually
+    Sorted,
+
+    /// Files are written ordered like [Sorted](Layout::Sorted) and compressed together as one stream
+    Solid(CompressType),
+}
+
+/// A writer that hashes everything written to it with SHA-256
+struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: io::Write> io::Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer that counts the bytes written to it
+struct CountWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: io::Write> io::Write for CountWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The number of bytes at the start of a file that are checked for null bytes to detect binary files
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Check if the start of a file's data contains a null byte, which text files don't contain
+fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// Compare the remaining bytes of two readers in fixed size chunks without reading either fully into memory
+fn streams_eq(a: &mut impl io::Read, b: &mut impl io::Read) -> io::Result<bool> {
+    /// Read into `buf` until it is full or the reader ends, returning the number of bytes read
+    fn fill(reader: &mut impl io::Read, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            match reader.read(&mut buf[len..])? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        Ok(len)
+    }
+
+    let (mut buf_a, mut buf_b) = ([0u8; 8192], [0u8; 8192]);
+    loop {
+        let (len_a, len_b) = (fill(a, &mut buf_a)?, fill(b, &mut buf_b)?);
+        if buf_a[..len_a] != buf_b[..len_b] {
+            return Ok(false);
+        }
+        if len_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Create a temporary file in `temp_dir`, or in the system's temporary directory if it is `None`
+fn spool(temp_dir: Option<&std::path::Path>) -> io::Result<std::fs::File> {
+    match temp_dir {
+        Some(dir) => tempfile::tempfile_in(dir),
+        None => tempfile::tempfile(),
+    }
+}
+
+/// Write the data section, header, and hash trailer of an archive to `writer`, reading file data from
+/// `data` and updating the header with the location of file data in the output. The file data is spooled
+/// to a temporary file in `temp_dir` when a redundant header is written
+#[allow(clippy::too_many_arguments)]
+fn write_archive<R: io::Read + io::Seek, W: io::Write>(
+    header: &mut Header,
+    data: &mut R,
+    writer: &mut W,
+    compress_header: bool,
+    redundant_header: bool,
+    temp_dir: Option<&std::path::Path>,
+    prog: &ProgressBar,
+    layout: Layout,
+) -> BarResult<[u8; 32]> {
+    //Everything before the trailer is counted to check that the header ends up where the trailer says
+    let mut writer = CountWriter {
+        inner: HashWriter {
+            inner: writer,
+            hasher: Sha256::new(),
+        },
+        count: 0,
+    };
+    let writer = &mut writer;
+    data.seek(SeekFrom::Start(0))?;
+    let mut data_size = 0u64;
+    if redundant_header {
+        //The front header must be written before the file data, but file offsets aren't known until
+        //the file data is written, so write it to temporary storage first
+        let mut section = spool(temp_dir)?;
+        write_data(header, &mut data_size, &mut section, data, prog, layout)?;
+        let (shifted, front) = front_header(header, compress_header)?;
+        *header = shifted;
+
+        writer.write_all(bar::FRONT_HEADER_MAGIC)?;
+        writer.write_u64::<Endian>(front.len() as u64)?;
+        writer.write_all(&front)?;
+        section.seek(SeekFrom::Start(0))?;
+        io::copy(&mut section, writer)?;
+        data_size += (bar::FRONT_HEADER_MAGIC.len() + 8 + front.len()) as u64;
+    } else {
+        write_data(header, &mut data_size, writer, data, prog, layout)?;
+    }
+    let mut counted = CountWriter {
+        inner: &mut *writer,
+        count: 0,
+    };
+    write_header(header, compress_header, &mut counted)?; //Write the header to the output
+    header.counts = Some(EntryCounts::count(&header.root));
+    log!(
+        info,
+        "Saved archive with {} bytes of file data and a {} byte header",
+        data_size,
+        counted.count
+    );
+    let header_size = counted.count;
+    if writer.count != data_size + header_size {
+        return Err(BarErr::InvalidHeaderFormat(format!(
+            "Wrote {} bytes before the trailer, but the file data and header take up {} bytes",
+            writer.count,
+            data_size + header_size
+        )));
+    }
+    //Write the file data size to the output
+    let mut size = [0u8; 8];
+    Endian::write_u64(&mut size, data_size);
+    debug_assert_eq!(Endian::read_u64(&size), data_size);
+    writer.write_all(&size)?;
+
+    let hash = writer.inner.hasher.finalize_reset();
+    writer.inner.inner.write_all(&hash)?;
+    writer.inner.inner.write_all(bar::HASH_TRAILER_MAGIC)?;
+    writer.flush()?;
+    Ok(hash.into())
+}
+
+/// Collect every file in a directory and its subdirectories along with their paths
+fn files_mut<'a>(
+    dir: &'a mut entry::Dir,
+    path: &std::path::Path,
+    files: &mut Vec<(std::path::PathBuf, &'a mut entry::File)>,
+) {
+    for (name, entry) in dir.data.iter_mut() {
+        match entry {
+            Entry::File(file) => files.push((path.join(name), file)),
+            Entry::Dir(dir) => files_mut(dir, &path.join(name), files),
+        }
+    }
+}
+
+/// Collect the path and entry of every file in `dir` and its subdirectories, like [files_mut]
+fn files<'a>(
+    dir: &'a entry::Dir,
+    path: &std::path::Path,
+    found: &mut Vec<(std::path::PathBuf, &'a entry::File)>,
+) {
+    for (name, entry) in dir.data.iter() {
+        match entry {
+            Entry::File(file) => found.push((path.join(name), file)),
+            Entry::Dir(dir) => files(dir, &path.join(name), found),
+        }
+    }
+}
+
+/// Estimate how long writing the data of every file in a directory in the given layout takes, see
+/// [cost](fn@CompressType::cost). Files in a solid layout are compressed with the layout's compression
+fn save_cost(dir: &entry::Dir, layout: Layout) -> u64 {
+    dir.entries()
+        .map(|entry| match entry {
+            Entry::File(file) if file.inherited => 0,
+            Entry::File(file) => match layout {
+                Layout::Solid(compression) => compression.cost(file.size as u64),
+                Layout::Tree | Layout::Sorted => file.compression.cost(file.size as u64),
+            },
+            Entry::Dir(dir) => save_cost(dir, layout),
+        })
+        .sum()
+}
+
+/// Write the data of every file in an archive to a writer in the given layout, updating the header with
+/// offsets into the written data
+fn write_data<R: io::Read + io::Seek, W: io::Write>(
+    header: &mut Header,
+    off: &mut u64,
+    writer: &mut W,
+    data: &mut R,
+    prog: &ProgressBar,
+    layout: Layout,
+) -> BarResult<()> {
+    let solid = match layout {
+        Layout::Tree => {
+            header.root = match header.root.write_data(off, writer, data, prog)? {
+                Entry::Dir(dir) => dir,
+                _ => unreachable!(),
+            };
+            header.solid = None;
+            return Ok(());
+        }
+        Layout::Sorted => None,
+        Layout::Solid(compression) => Some(compression),
+    };
+
+    let mut files = vec![];
+    files_mut(&mut header.root, std::path::Path::new(""), &mut files);
+    files.retain(|(_, file)| !file.inherited); //Data of inherited files stays in the base archive
+    files.sort_by(|(a, _), (b, _)| {
+        (a.extension(), a.file_name(), a).cmp(&(b.extension(), b.file_name(), b))
+    });
+
+    header.solid = match solid {
+        None => {
+            for (_, file) in files {
+                *file = match file.write_data(off, writer, data, prog)? {
+                    Entry::File(file) => file,
+                    _ => unreachable!(),
+                };
+            }
+            None
+        }
+        Some(compression) => {
+            let mut stream = CountWriter {
+                inner: writer,
+                count: 0,
+            };
+            let mut pos = 0u64; //The current offset into the decompressed stream
+            let mut write_files = |w: &mut dyn io::Write| -> io::Result<()> {
+                for (path, file) in files.iter_mut() {
+                    prog.set_message(format!("Saving file {}", path.display()));
+                    data.seek(SeekFrom::Start(file.off))?;
+                    io::copy(&mut (&mut *data).take(file.size as u64), w)?;
+                    file.off = pos;
+                    file.compression =
+                        CompressType(flate2::Compression::none(), CompressMethod::None);
+                    pos += file.size as u64;
+                    prog.inc(compression.cost(file.size as u64));
+                }
+                Ok(())
+            };
+            match compression.1 {
+                CompressMethod::Deflate => {
+                    let mut encoder = DeflateEncoder::new(&mut stream, compression.0);
+                    write_files(&mut encoder)?;
+                    encoder.finish()?;
+                }
+                CompressMethod::Gzip => {
+                    let mut encoder = GzEncoder::new(&mut stream, compression.0);
+                    write_files(&mut encoder)?;
+                    encoder.finish()?;
+                }
+                CompressMethod::None => write_files(&mut stream)?,
+            }
+
+            let solid = Solid {
+                compression,
+                off: *off,
+                size: stream.count,
+            };
+            *off += stream.count;
+            Some(solid)
+        }
+    };
+    Ok(())
+}
 
 impl<S: io::Read + io::Write + io::Seek> Bar<S> {
     /// Pack an entire directory into a `Bar` struct using a given compression method for every file
     /// This function takes an absolute or relative path to a directory that will be packed, the directory
     /// name will be used as the archive's name
+    #[inline]
     pub fn pack(
         dir: impl AsRef<std::path::Path>,
-        mut backend: S,
+        backend: S,
         compression: CompressType,
         prog: bool,
+    ) -> BarResult<Self> {
+        Self::pack_with(dir, backend, prog, move |_, _| compression)
+    }
+
+    /// Pack a directory like [pack](fn@Bar::pack), choosing the compression method of each file with
+    /// `choose`. It is called with the path of each file relative to the packed directory and the file's
+    /// size in bytes
+    pub fn pack_with(
+        dir: impl AsRef<std::path::Path>,
+        backend: S,
+        prog: bool,
+        choose: impl Fn(&std::path::Path, u64) -> CompressType,
+    ) -> BarResult<Self> {
+        Self::pack_dir(
+            dir.as_ref(),
+            backend,
+            &|path, size, _| Ok(choose(path, size)),
+            prog,
+            &|_, _| true,
+        )
+    }
+
+    /// Pack a directory like [pack](fn@Bar::pack), stopping with [Cancelled](BarErr::Cancelled) before the
+    /// next file is read if `cancel` is set, for example from a Ctrl-C handler running on another thread.
+    /// Nothing is written outside of `backend`, so a cancelled pack leaves no partial archive
+    pub fn pack_cancellable(
+        dir: impl AsRef<std::path::Path>,
+        backend: S,
+        compression: CompressType,
+        prog: bool,
+        cancel: &std::sync::atomic::AtomicBool,
+    ) -> BarResult<Self> {
+        Self::pack_dir(
+            dir.as_ref(),
+            backend,
+            &|_, _, _| match cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                true => Err(BarErr::Cancelled),
+                false => Ok(compression),
+            },
+            prog,
+            &|_, _| true,
+        )
+    }
+
+    /// Pack a directory like [pack_filtered](fn@Bar::pack_filtered), choosing the compression of each file
+    /// automatically by compressing its first block at different levels. Files that don't compress well are
+    /// stored uncompressed, and the others are compressed with DEFLATE at a fast or high level depending on
+    /// how much the higher level helps
+    pub fn pack_auto(
+        dir: impl AsRef<std::path::Path>,
+        backend: S,
+        prog: bool,
+        filter: impl Fn(&std::path::Path, bool) -> bool,
+    ) -> BarResult<Self> {
+        Self::pack_dir(
+            dir.as_ref(),
+            backend,
+            &|_, _, data| bar::auto_compression(data),
+            prog,
+            &filter,
+        )
+    }
+
+    /// Pack a directory like [pack](fn@Bar::pack), but only pack entries that `filter` returns `true` for.
+    /// The filter is called with the path of each file and directory relative to the packed directory and
+    /// if the entry is a directory. Directories that the filter rejects are skipped without being read.
+    /// If `store_incompressible` is `true`, files whose first block doesn't compress well are stored
+    /// uncompressed instead of with `compression`
+    pub fn pack_filtered(
+        dir: impl AsRef<std::path::Path>,
+        backend: S,
+        compression: CompressType,
+        store_incompressible: bool,
+        prog: bool,
+        filter: impl Fn(&std::path::Path, bool) -> bool,
+    ) -> BarResult<Self> {
+        Self::pack_dir(
+            dir.as_ref(),
+            backend,
+            &|_, _, data| match store_incompressible {
+                true => bar::probe_compression(data, compression),
+                false => Ok(compression),
+            },
+            prog,
+            &filter,
+        )
+    }
+
+    /// Pack a directory, choosing the compression method of each file with `choose` and only packing
+    /// entries that `filter` returns `true` for
+    fn pack_dir(
+        dir: &std::path::Path,
+        mut backend: S,
+        choose: &bar::ChooseCompression,
+        prog: bool,
+        filter: &dyn Fn(&std::path::Path, bool) -> bool,
     ) -> BarResult<Self> {
         let prog = match prog {
             true => ProgressBar::new_spinner()
                 .with_style(ProgressStyle::default_spinner().tick_chars(".,'`*@*`',")),
             false => ProgressBar::hidden(),
         };
-        let dir = dir.as_ref();
         let mut off = 0u64; //The current offset into the backing storage
 
-        let meta = Self::read_all_entry_metadata(dir.join(Self::ROOT_METADATA_FILE))?;
-        let root_meta = if let Some(meta) = meta.get("/") {
+        let meta = Self::read_all_entry_metadata(dir)?;
+        let root_meta = if let Some(meta) = meta.get(std::path::Path::new("")) {
             meta.clone()
         } else {
-            Meta {
-                name: dir.file_name().unwrap().to_str().unwrap().to_owned(),
-                ..Default::default()
-            }
+            let name = match dir.file_name() {
+                Some(name) => name
+                    .to_str()
+                    .ok_or_else(|| BarErr::NonUtf8Name(dir.to_path_buf()))?,
+                None => "root",
+            };
+            Meta::new(name)
         };
 
+        let mut skipped = vec![];
+        //Writes are buffered so that packing many small files doesn't write to the backend once per file
+        let mut writer = io::BufWriter::with_capacity(bar::DEFAULT_IO_BUFFER_SIZE, &mut backend);
+        let entries = Self::pack_read_dir(
+            dir,
+            std::path::Path::new(""),
+            &mut off,
+            &mut writer,
+            &meta,
+            choose,
+            &prog,
+            filter,
+            &mut skipped,
+        )?;
+        writer.flush()?;
+        drop(writer);
+
         Ok(Self {
             header: Header {
                 meta: root_meta,
+                description: None,
+                counts: None,
+                source_path: Some(std::fs::canonicalize(dir)?.to_string_lossy().into_owned()),
+                created: None,
                 root: entry::Dir {
-                    meta: RefCell::new(Meta {
-                        name: "root".to_owned(),
-                        ..Default::default()
-                    }),
-                    data: Self::pack_read_dir(
-                        dir,
-                        &mut off,
-                        &mut backend,
-                        &meta,
-                        compression,
-                        &prog,
-                    )?
-                    .into_iter()
-                    .map(|entry| (entry.name(), entry))
-                    .collect(),
+                    meta: RefCell::new(Meta::new("root")),
+                    data: entries
+                        .into_iter()
+                        .map(|entry| (entry.name(), entry))
+                        .collect(),
                 },
+                solid: None,
             },
             data: backend,
+            compress_header: false,
+            redundant_header: false,
+            io_buffer_size: bar::DEFAULT_IO_BUFFER_SIZE,
+            skipped,
+            decompression_limit: Some(bar::DEFAULT_DECOMPRESSION_LIMIT),
+            hash_updates: false,
+            temp_dir: None,
         })
     }
+
+    /// Pack a list of files into a `Bar` struct using a given compression method for every file.
+    /// Each item in the list is a pair of the path to a file on the filesystem and the path to store the
+    /// file at in the archive, any directories in the destination path that don't exist yet are created.
+    /// See [pack_filtered](fn@Bar::pack_filtered) for `store_incompressible`
+    pub fn pack_files(
+        name: impl ToString,
+        files: &[(std::path::PathBuf, std::path::PathBuf)],
+        backend: S,
+        compression: CompressType,
+        store_incompressible: bool,
+        prog: bool,
+    ) -> BarResult<Self> {
+        Self::pack_list(
+            name.to_string(),
+            files,
+            backend,
+            &|_, _, data| match store_incompressible {
+                true => bar::probe_compression(data, compression),
+                false => Ok(compression),
+            },
+            prog,
+        )
+    }
+
+    /// Pack a list of files like [pack_files](fn@Bar::pack_files), choosing the compression of each file
+    /// automatically like [pack_auto](fn@Bar::pack_auto)
+    pub fn pack_files_auto(
+        name: impl ToString,
+        files: &[(std::path::PathBuf, std::path::PathBuf)],
+        backend: S,
+        prog: bool,
+    ) -> BarResult<Self> {
+        Self::pack_list(
+            name.to_string(),
+            files,
+            backend,
+            &|_, _, data| bar::auto_compression(data),
+            prog,
+        )
+    }
+
+    /// Pack a list of files, choosing the compression method of each file with `choose`
+    fn pack_list(
+        name: String,
+        files: &[(std::path::PathBuf, std::path::PathBuf)],
+        mut backend: S,
+        choose: &bar::ChooseCompression,
+        prog: bool,
+    ) -> BarResult<Self> {
+        let prog = match prog {
+            true => ProgressBar::new_spinner()
+                .with_style(ProgressStyle::default_spinner().tick_chars(".,'`*@*`',")),
+            false => ProgressBar::hidden(),
+        };
+        let mut off = 0u64; //The current offset into the backing storage
+        let mut skipped = vec![];
+        let mut root = entry::Dir {
+            meta: RefCell::new(Meta::new("root")),
+            data: Default::default(),
+        };
+
+        for (src, dest) in files {
+            prog.set_message(format!("Writing file {} to archive", src.display()));
+            let names = bar::path_names(dest);
+            let (name, parents) = names
+                .split_last()
+                .ok_or_else(|| BarErr::NoEntry(dest.display().to_string()))?;
+            let dir = bar::create_dirs(&mut root, parents)?;
+            if dir.data.contains_key(name) {
+                return Err(BarErr::EntryExists(dest.display().to_string()));
+            }
+
+            if !std::fs::metadata(src)?.is_file() {
+                prog.println(format!(
+                    "Skipping {}, it is not a regular file",
+                    src.display()
+                ));
+                skipped.push(src.clone());
+                continue;
+            }
+            let mut data = std::fs::File::open(src)?;
+            let mut meta = Meta::new(name.clone());
+            if let Ok(modified) = data.metadata()?.modified() {
+                meta = meta.with_modified(modified);
+            }
+            let file_compression = choose(dest, data.metadata()?.len(), &mut data)?;
+            let size = io::copy(&mut data, &mut backend)?;
+            dir.add_entry(Entry::File(entry::File {
+                inherited: false,
+                normalized: false,
+                meta: RefCell::new(meta),
+                compression: file_compression,
+                off,
+                size: size as u32,
+            }))?;
+            off += size;
+            prog.tick();
+        }
+        prog.finish_and_clear();
+
+        Ok(Self {
+            header: Header {
+                meta: Meta::new(name),
+                description: None,
+                counts: None,
+                source_path: None,
+                created: None,
+                root,
+                solid: None,
+            },
+            data: backend,
+            compress_header: false,
+            redundant_header: false,
+            io_buffer_size: bar::DEFAULT_IO_BUFFER_SIZE,
+            skipped,
+            decompression_limit: Some(bar::DEFAULT_DECOMPRESSION_LIMIT),
+            hash_updates: false,
+            temp_dir: None,
+        })
+    }
+
+    /// Convert the CRLF line endings of every text file in this archive to LF and mark the files as
+    /// normalized, so that extracting them restores the platform's line endings: CRLF on Windows and LF
+    /// everywhere else. Files are text if there is no null byte near their start, like in
+    /// [grep](fn@Bar::grep). Converted data is appended to the backing storage, so this must be called on a
+    /// packed archive before it is saved, while the backing storage holds uncompressed file data. Returns the
+    /// sorted paths of every normalized file
+    pub fn normalize_line_endings(&mut self) -> BarResult<Vec<std::path::PathBuf>> {
+        let mut files = vec![];
+        files_mut(&mut self.header.root, std::path::Path::new(""), &mut files);
+        files.retain(|(_, file)| !file.inherited); //Data of inherited files stays in the base archive
+
+        let mut end = self.data.seek(SeekFrom::End(0))?;
+        let mut normalized = vec![];
+        for (path, file) in files {
+            let mut data = vec![0u8; file.size as usize];
+            self.data.seek(SeekFrom::Start(file.off))?;
+            self.data.read_exact(&mut data)?;
+            if is_binary(&data) {
+                continue;
+            }
+
+            //Files that already use LF line endings are only marked as normalized
+            let converted = bar::crlf_to_lf(&data);
+            if converted.len() != data.len() {
+                self.data.seek(SeekFrom::Start(end))?;
+                self.data.write_all(&converted)?;
+                file.off = end;
+                file.size = converted.len() as u32;
+                end += converted.len() as u64;
+            }
+            file.normalized = true;
+            normalized.push(path);
+        }
+        normalized.sort();
+        Ok(normalized)
+    }
+
+    /// Pack a directory like [pack](fn@Bar::pack) into a differential archive that only stores files that
+    /// changed since `base` was packed, see [inherit_unchanged](fn@Bar::inherit_unchanged)
+    pub fn pack_diff<B: io::Read + io::Seek>(
+        dir: impl AsRef<std::path::Path>,
+        base: &mut Bar<B>,
+        backend: S,
+        compression: CompressType,
+        prog: bool,
+    ) -> BarResult<Self> {
+        let mut bar = Self::pack(dir, backend, compression, prog)?;
+        bar.inherit_unchanged(base)?;
+        Ok(bar)
+    }
+
+    /// Compare every file in this newly packed archive to the file at the same path in `base`, and mark files
+    /// with the same contents as inherited from `base`. Inherited files point to their data in `base`
+    /// instead of being stored again when this archive is saved, so `base` is needed to read them later, see
+    /// [save_unpacked_with_base](fn@Bar::save_unpacked_with_base). Returns the paths of inherited files
+    pub fn inherit_unchanged<B: io::Read + io::Seek>(
+        &mut self,
+        base: &mut Bar<B>,
+    ) -> BarResult<Vec<std::path::PathBuf>> {
+        let mut files = vec![];
+        files_mut(&mut self.header.root, std::path::Path::new(""), &mut files);
+
+        let mut inherited = vec![];
+        for (path, file) in files {
+            let base_file = match base.file(&path) {
+                Some(base_file) if !base_file.inherited => base_file.clone(),
+                _ => continue,
+            };
+            let mut old = Vec::new();
+            Bar::save_file(
+                &base_file,
+                &mut old,
+                &mut base.data,
+                base.header.solid.as_ref(),
+                true,
+                false,
+                base.decompression_limit,
+            )?;
+            if old.len() != file.size as usize {
+                continue;
+            }
+
+            let mut new = vec![0u8; file.size as usize];
+            self.data.seek(SeekFrom::Start(file.off))?;
+            self.data.read_exact(&mut new)?;
+            if old == new {
+                *file = entry::File {
+                    meta: file.meta.clone(),
+                    inherited: true,
+                    ..base_file
+                };
+                inherited.push(path);
+            }
+        }
+        inherited.sort();
+        Ok(inherited)
+    }
 }
 
 impl<S: io::Read + io::Seek> Bar<S> {
@@ -70,17 +732,258 @@ impl<S: io::Read + io::Seek> Bar<S> {
         &self.header.meta
     }
 
+    /// Convert the backing storage of this archive with `f`, keeping the header and settings. `f` must
+    /// return storage with the same contents, like an in-memory copy of an archive file, because file
+    /// offsets in the header aren't changed
+    pub fn map_backend<T: io::Read + io::Seek>(self, f: impl FnOnce(S) -> T) -> Bar<T> {
+        Bar {
+            data: f(self.data),
+            header: self.header,
+            compress_header: self.compress_header,
+            redundant_header: self.redundant_header,
+            io_buffer_size: self.io_buffer_size,
+            skipped: self.skipped,
+            decompression_limit: self.decompression_limit,
+            hash_updates: self.hash_updates,
+            temp_dir: self.temp_dir,
+        }
+    }
+
+    /// Get the description of the whole archive, if one was set with
+    /// [set_description](fn@Bar::set_description)
+    #[inline]
+    pub fn description(&self) -> Option<&str> {
+        self.header.description.as_deref()
+    }
+
+    /// Set or remove the description of the whole archive, which is stored in the header
+    #[inline]
+    pub fn set_description(&mut self, description: Option<String>) {
+        self.header.description = description;
+    }
+
+    /// Get the absolute path of the directory that the archive was packed from, if it was recorded
+    #[inline]
+    pub fn source_path(&self) -> Option<&str> {
+        self.header.source_path.as_deref()
+    }
+
+    /// Get when the archive was packed in seconds since the unix epoch, if it was recorded with
+    /// [record_created](fn@Bar::record_created)
+    #[inline]
+    pub fn created(&self) -> Option<i64> {
+        self.header.created
+    }
+
+    /// Record the current time as when the archive was created. Packing doesn't record the time itself so
+    /// that packing the same directory twice gives identical archives; if the `SOURCE_DATE_EPOCH` environment
+    /// variable holds a time in seconds since the unix epoch, that time is recorded instead so that archives
+    /// with a creation time can be reproducible too
+    pub fn record_created(&mut self) {
+        self.header.created = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|epoch| epoch.trim().parse().ok())
+            .or_else(bar::now);
+    }
+
+    /// Get the number of files and directories in the archive. The counts stored in the header are used if
+    /// the archive has been saved or was read from a header that contains them, so they reflect the archive as
+    /// of its last save; otherwise every directory is walked to count its entries
+    pub fn entry_counts(&self) -> EntryCounts {
+        self.header
+            .counts
+            .unwrap_or_else(|| EntryCounts::count(&self.header.root))
+    }
+
     /// Unpack a packed archive from a file or other storage, like an in-memory byte array.
     /// See also [unpack](fn@Bar::unpack)
     pub fn unpack_reader(mut storage: S) -> BarResult<Self> {
-        let header = Self::read_header(&mut storage)?;
+        let (header, compress_header) = Self::read_header(&mut storage)?;
+        let redundant_header = Self::has_front_header(&mut storage)?;
 
         Ok(Self {
             header,
             data: storage,
+            compress_header,
+            redundant_header,
+            io_buffer_size: bar::DEFAULT_IO_BUFFER_SIZE,
+            skipped: Vec::new(),
+            decompression_limit: Some(bar::DEFAULT_DECOMPRESSION_LIMIT),
+            hash_updates: false,
+            temp_dir: None,
+        })
+    }
+
+    /// Recover an archive whose header or trailing header offset is damaged, using the redundant copy of
+    /// the header at the front of the archive if the header at the end can't be read. Unlike
+    /// [unpack_reader](fn@Bar::unpack_reader), files whose data was cut off are removed from the recovered
+    /// archive. See [recover_lost](fn@Bar::recover_lost)
+    pub fn recover(storage: S) -> BarResult<Self> {
+        Self::recover_lost(storage).map(|(bar, _)| bar)
+    }
+
+    /// Recover an archive like [recover](fn@Bar::recover), also returning the paths of every file that
+    /// was removed from the archive because its data was lost
+    pub fn recover_lost(mut storage: S) -> BarResult<(Self, Vec<String>)> {
+        let (mut header, compress_header) = Self::read_header(&mut storage)?;
+        let redundant_header = Self::has_front_header(&mut storage)?;
+
+        let len = storage.seek(SeekFrom::End(0))?;
+        let mut lost = vec![];
+        //Offsets of files in solid archives are into the decompressed stream, so they can't be checked
+        if header.solid.is_none() {
+            bar::remove_lost(&mut header.root, std::path::Path::new(""), len, &mut lost);
+        }
+
+        Ok((
+            Self {
+                header,
+                data: storage,
+                compress_header,
+                redundant_header,
+                io_buffer_size: bar::DEFAULT_IO_BUFFER_SIZE,
+                skipped: Vec::new(),
+                decompression_limit: Some(bar::DEFAULT_DECOMPRESSION_LIMIT),
+                hash_updates: false,
+                temp_dir: None,
+            },
+            lost,
+        ))
+    }
+
+    /// Get the size of everything before the header in the backing storage, which is the file data and the
+    /// redundant copy of the header at the front of the archive if there is one. This is the offset of the
+    /// header in the archive
+    pub fn data_size(&mut self) -> BarResult<u64> {
+        Ok(Self::get_header_pos(&mut self.data)?.0)
+    }
+
+    /// Get the size of the header at the end of the archive as it is stored in the backing storage
+    pub fn header_size(&mut self) -> BarResult<u64> {
+        Ok(Self::get_header_pos(&mut self.data)?.1)
+    }
+
+    /// Get the byte range of every section of the archive in the backing storage, for tools that inspect
+    /// archive files. See `doc/FORMAT.md` for the contents of each section
+    pub fn raw_layout(&mut self) -> BarResult<RawLayout> {
+        let (data_end, header_size) = Self::get_header_pos(&mut self.data)?;
+        let len = self.data.seek(SeekFrom::End(0))?;
+        let header_end = data_end + header_size;
+
+        //The front header is followed by its size, which doesn't include the magic bytes and the size itself
+        let front_header = match Self::read_front_header(&mut self.data) {
+            Ok(Some(_)) => {
+                self.data
+                    .seek(SeekFrom::Start(bar::FRONT_HEADER_MAGIC.len() as u64))?;
+                let size = self.data.read_u64::<Endian>()?;
+                Some(0..bar::FRONT_HEADER_MAGIC.len() as u64 + 8 + size)
+            }
+            _ => None,
+        };
+        let data_start = front_header.as_ref().map_or(0, |front| front.end);
+        Ok(RawLayout {
+            front_header,
+            data: data_start..data_end,
+            header: data_end..header_end,
+            data_size: header_end..header_end + 8,
+            hash: match Self::trailer_len(&mut self.data)? {
+                8 => None,
+                _ => Some(header_end + 8..len),
+            },
         })
     }
 
+    /// Check the SHA-256 hash at the end of the archive against the hash of the rest of the archive,
+    /// returning an error if the archive has no hash or the hashes don't match
+    pub fn verify_archive_hash(&mut self) -> BarResult<()> {
+        let len = self.data.seek(SeekFrom::End(0))?;
+        let trailer = Self::trailer_len(&mut self.data)?;
+        if trailer == 8 {
+            return Err(BarErr::NoArchiveHash);
+        }
+
+        let hashed = len - (trailer - 8);
+        self.data.seek(SeekFrom::Start(hashed))?;
+        let mut expected = [0u8; 32];
+        self.data.read_exact(&mut expected)?;
+        match Self::hash_range(&mut self.data, hashed)?[..] == expected[..] {
+            true => Ok(()),
+            false => {
+                log!(
+                    warn,
+                    "The SHA-256 hash of the archive does not match its trailer"
+                );
+                Err(BarErr::ArchiveHashMismatch)
+            }
+        }
+    }
+
+    /// Check if the archive in `storage` begins with a redundant copy of its header
+    fn has_front_header(storage: &mut S) -> BarResult<bool> {
+        storage.seek(SeekFrom::Start(0))?;
+        let mut magic = [0u8; bar::FRONT_HEADER_MAGIC.len()];
+        Ok(matches!(storage.read_exact(&mut magic), Ok(()) if &magic == bar::FRONT_HEADER_MAGIC))
+    }
+
+    /// Select whether the header will be compressed with DEFLATE when this archive is saved, which can
+    /// shrink the header of archives with many entries considerably
+    #[inline]
+    pub fn set_compress_header(&mut self, compress: bool) {
+        self.compress_header = compress;
+    }
+
+    /// Select whether a redundant copy of the header will be written at the front of the archive when it
+    /// is saved, allowing the archive to be recovered with [recover](fn@Bar::recover) if the end of the
+    /// archive is damaged
+    #[inline]
+    pub fn set_redundant_header(&mut self, redundant: bool) {
+        self.redundant_header = redundant;
+    }
+
+    /// Get the paths of special files like FIFOs, sockets, and devices that were skipped when this archive
+    /// was packed because they aren't regular files. Paths are relative to the packed directory
+    #[inline]
+    pub fn skipped(&self) -> &[std::path::PathBuf] {
+        &self.skipped
+    }
+
+    /// Set the size of the buffers used when saving the archive and unpacking files, which is
+    /// [DEFAULT_IO_BUFFER_SIZE](bar::DEFAULT_IO_BUFFER_SIZE) by default. Larger buffers make fewer, larger
+    /// writes, which can be faster on fast storage
+    #[must_use]
+    pub fn with_io_buffer_size(mut self, size: usize) -> Self {
+        self.io_buffer_size = size;
+        self
+    }
+
+    /// Set the largest number of bytes that the data of a single file may decompress to when it is
+    /// extracted or read with [open](fn@Bar::open), or `None` to remove the limit. The limit is
+    /// [DEFAULT_DECOMPRESSION_LIMIT](bar::DEFAULT_DECOMPRESSION_LIMIT) by default. Reading a file whose data decompresses to more than the limit fails with
+    /// [DecompressionLimitExceeded](BarErr::DecompressionLimitExceeded) instead of filling memory or disk
+    /// with the output of a maliciously crafted archive
+    #[inline]
+    pub fn set_decompression_limit(&mut self, limit: Option<u64>) {
+        self.decompression_limit = limit;
+    }
+
+    /// Select whether the SHA-256 hash trailer is written again when the archive file is updated in place
+    /// by [save_updated](fn@Bar::save_updated), [Transaction]s,
+    /// [AppendWriter]s, and [copy_from](fn@Bar::copy_from). Hashing re-reads the whole
+    /// archive file on every update, so it is off by default and updated archives have no hash, making
+    /// [verify_archive_hash](fn@Bar::verify_archive_hash) return [NoArchiveHash](BarErr::NoArchiveHash)
+    #[inline]
+    pub fn set_hash_updates(&mut self, hash: bool) {
+        self.hash_updates = hash;
+    }
+
+    /// Set the directory that temporary files are created in while saving, or `None` to use the system's
+    /// temporary directory. Saving spools the file data to a temporary file when a redundant header is
+    /// written and when a solid archive is saved again
+    #[inline]
+    pub fn set_temp_dir(&mut self, dir: Option<std::path::PathBuf>) {
+        self.temp_dir = dir;
+    }
+
     /// Get a reference to an entry in the Bar archive. This should
     /// NOT contain a root symbol like '/' on linux or
     /// 'C:\\' on windows
@@ -89,12 +992,284 @@ impl<S: io::Read + io::Seek> Bar<S> {
         self.header.root.entry(path)
     }
 
+    /// Get whether the entry at `path` is a file or a directory, or `None` if it doesn't exist
+    #[inline]
+    pub fn entry_type(&self, path: impl AsRef<std::path::Path>) -> Option<entry::EntryType> {
+        self.entry(path).map(Entry::entry_type)
+    }
+
+    /// Get the names and types of the entries directly in the directory at `path` sorted by name, without
+    /// the contents of its subdirectories. An empty path lists the root directory
+    pub fn list(
+        &self,
+        path: impl AsRef<std::path::Path>,
+    ) -> BarResult<Vec<(String, entry::EntryType)>> {
+        let path = path.as_ref();
+        let dir = match path.components().next() {
+            None => &self.header.root,
+            Some(_) => match self.entry(path) {
+                Some(Entry::Dir(dir)) => dir,
+                Some(Entry::File(_)) => {
+                    return Err(BarErr::NotADirectory(path.display().to_string()))
+                }
+                None => return Err(BarErr::NoEntry(path.display().to_string())),
+            },
+        };
+        Ok(dir
+            .entries_sorted()
+            .map(|entry| (entry.name(), entry.entry_type()))
+            .collect())
+    }
+
+    /// Get a reference to an entry in the Bar archive, comparing path components to entry names
+    /// case-insensitively. See [entry_ci](fn@entry::Dir::entry_ci)
+    #[inline]
+    pub fn entry_ci(&self, path: impl AsRef<std::path::Path>) -> Option<&Entry> {
+        self.header.root.entry_ci(path)
+    }
+
     /// See [entry](fn@Bar::entry)
     #[inline]
     pub fn entry_mut(&mut self, path: impl AsRef<std::path::Path>) -> Option<&mut Entry> {
         self.header.root.entry_mut(path)
     }
 
+    /// Mark the entry at `path` as used, incrementing its access count and updating its last access time.
+    /// See [touch](fn@Meta::touch)
+    pub fn touch(&mut self, path: impl AsRef<std::path::Path>) -> BarResult<()> {
+        let path = path.as_ref();
+        self.entry(path)
+            .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))?
+            .meta_mut()
+            .touch();
+        Ok(())
+    }
+
+    /// Set the thumbnail of the entry at `path`, which is stored in the header with the entry's metadata
+    /// so thumbnails should be kept small
+    pub fn set_thumbnail(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        thumbnail: Vec<u8>,
+    ) -> BarResult<()> {
+        let path = path.as_ref();
+        self.entry(path)
+            .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))?
+            .meta_mut()
+            .thumbnail = Some(thumbnail);
+        Ok(())
+    }
+
+    /// Get a copy of the thumbnail of the entry at `path`, returning `None` if the entry doesn't exist or
+    /// has no thumbnail
+    pub fn thumbnail(&self, path: impl AsRef<std::path::Path>) -> Option<Vec<u8>> {
+        self.entry(path)?.meta().thumbnail.clone()
+    }
+
+    /// Duplicate the file at `src` to the path `dest`, giving the copy the name at the end of `dest` and a
+    /// copy of the original's metadata. The copy's parent directory must already exist.
+    ///
+    /// The copy points to the same offset in the backing storage as the original, so no file data is
+    /// copied. Both entries share data until the archive is saved with [save](fn@Bar::save), which writes
+    /// the data of every entry separately
+    pub fn clone_entry(
+        &mut self,
+        src: impl AsRef<std::path::Path>,
+        dest: impl AsRef<std::path::Path>,
+    ) -> BarResult<()> {
+        let (src, dest) = (src.as_ref(), dest.as_ref());
+        let file = self
+            .file(src)
+            .ok_or_else(|| BarErr::NoEntry(src.display().to_string()))?;
+        let name = dest
+            .file_name()
+            .ok_or_else(|| BarErr::NoEntry(dest.display().to_string()))?
+            .to_str()
+            .ok_or_else(|| BarErr::NonUtf8Name(dest.to_path_buf()))?
+            .to_owned();
+
+        let copy = entry::File {
+            meta: RefCell::new(Meta {
+                name: name.clone(),
+                ..file.meta.borrow().clone()
+            }),
+            ..file.clone()
+        };
+
+        let parent = match dest.parent() {
+            Some(parent) if parent.components().next().is_some() => self
+                .dir_mut(parent)
+                .ok_or_else(|| BarErr::NoEntry(parent.display().to_string()))?,
+            _ => self.root_mut(),
+        };
+        if parent.data.contains_key(&name) {
+            return Err(BarErr::EntryExists(dest.display().to_string()));
+        }
+        parent.add_entry(Entry::File(copy))?;
+        Ok(())
+    }
+
+    /// Remove every file that has not been marked as used, along with any directories that are left empty
+    /// by removing them, and return the paths of the removed files. Only the header is changed, so saving
+    /// with [save_updated](fn@Bar::save_updated) leaves the removed files' data in the archive
+    pub fn prune_unused(&mut self) -> BarResult<Vec<std::path::PathBuf>> {
+        /// Remove unused files from `dir` and its subdirectories, returning `true` if `dir` was emptied
+        fn prune(
+            dir: &mut entry::Dir,
+            path: &std::path::Path,
+            removed: &mut Vec<std::path::PathBuf>,
+        ) -> bool {
+            if dir.data.is_empty() {
+                return false;
+            }
+            dir.data.retain(|name, entry| match entry {
+                Entry::File(file) if !file.meta.borrow().used => {
+                    removed.push(path.join(name));
+                    false
+                }
+                Entry::File(_) => true,
+                Entry::Dir(sub) => !prune(sub, &path.join(name), removed),
+            });
+            dir.data.is_empty()
+        }
+
+        let mut removed = vec![];
+        prune(
+            &mut self.header.root,
+            std::path::Path::new(""),
+            &mut removed,
+        );
+        removed.sort();
+        Ok(removed)
+    }
+
+    /// Clear metadata that could be personal from the archive and every file and directory in it before
+    /// sharing it. `notes` clears notes, `used` clears the used flag and access count, and `timestamps` clears
+    /// modification and access times along with the time the archive was created. These apply to the
+    /// archive's own metadata as well as every entry's. `provenance` clears the path of the directory that the
+    /// archive was packed from, which usually contains the user's home directory. Names, thumbnails, and the
+    /// directory tree are left unchanged, as thumbnails only preview data that is in the archive anyway
+    pub fn strip_metadata(&mut self, notes: bool, used: bool, timestamps: bool, provenance: bool) {
+        let clear = |meta: &mut entry::Meta| {
+            if notes {
+                meta.note = None;
+            }
+            if used {
+                meta.used = false;
+                meta.access_count = 0;
+            }
+            if timestamps {
+                meta.modified = None;
+                meta.last_access = None;
+            }
+        };
+
+        /// Clear the selected metadata of `dir` and everything in it
+        fn strip(dir: &entry::Dir, clear: &impl Fn(&mut entry::Meta)) {
+            clear(&mut dir.meta.borrow_mut());
+            for entry in dir.data.values() {
+                match entry {
+                    Entry::File(file) => clear(&mut file.meta.borrow_mut()),
+                    Entry::Dir(sub) => strip(sub, clear),
+                }
+            }
+        }
+
+        strip(&self.header.root, &clear);
+        clear(&mut self.header.meta);
+        if timestamps {
+            self.header.created = None;
+        }
+        if provenance {
+            self.header.source_path = None;
+        }
+    }
+
+    /// Re-key every directory's entries by the entries' current names. Renaming an entry by editing its
+    /// metadata directly leaves it stored under its old name, so it can't be found by its new name until the
+    /// index is rebuilt. If two entries in a directory have the same name, an
+    /// [EntryExists](BarErr::EntryExists) error is returned and that directory is left unchanged
+    pub fn rebuild_index(&mut self) -> BarResult<()> {
+        /// Re-key the entries of `dir` and its subdirectories
+        fn rebuild(dir: &mut entry::Dir, path: &std::path::Path) -> BarResult<()> {
+            let mut names = std::collections::HashSet::new();
+            if let Some(name) = dir
+                .data
+                .values()
+                .map(Entry::name)
+                .find(|n| !names.insert(n.clone()))
+            {
+                return Err(BarErr::EntryExists(path.join(name).display().to_string()));
+            }
+            dir.data = std::mem::take(&mut dir.data)
+                .into_values()
+                .map(|entry| (entry.name(), entry))
+                .collect();
+            for entry in dir.data.values_mut() {
+                if let Entry::Dir(sub) = entry {
+                    let path = path.join(sub.meta.borrow().name.as_str());
+                    rebuild(sub, &path)?;
+                }
+            }
+            Ok(())
+        }
+
+        rebuild(&mut self.header.root, std::path::Path::new(""))
+    }
+
+    /// Get the sorted paths of every file that has not been marked as used, which are the files that
+    /// [prune_unused](fn@Bar::prune_unused) would remove
+    pub fn unused_files(&self) -> Vec<std::path::PathBuf> {
+        /// Collect the paths of unused files in `dir` and its subdirectories
+        fn unused(dir: &entry::Dir, path: &std::path::Path, found: &mut Vec<std::path::PathBuf>) {
+            for (name, entry) in dir.data.iter() {
+                match entry {
+                    Entry::File(file) if !file.meta.borrow().used => found.push(path.join(name)),
+                    Entry::File(_) => (),
+                    Entry::Dir(sub) => unused(sub, &path.join(name), found),
+                }
+            }
+        }
+
+        let mut found = vec![];
+        unused(&self.header.root, std::path::Path::new(""), &mut found);
+        found.sort();
+        found
+    }
+
+    /// Get the path and entry of every file in the archive sorted by size from largest to smallest, with
+    /// files of the same size sorted by path. Sizes are of the data stored in the archive, like
+    /// [size_recursive](fn@Entry::size_recursive)
+    pub fn files_sorted_by_size(&self) -> Vec<(std::path::PathBuf, &entry::File)> {
+        let mut found = vec![];
+        files(&self.header.root, std::path::Path::new(""), &mut found);
+        found.sort_by(|(a_path, a), (b_path, b)| {
+            b.size.cmp(&a.size).then_with(|| a_path.cmp(b_path))
+        });
+        found
+    }
+
+    /// Get the path and entry of every file that was last modified after `ts`, in seconds since the unix
+    /// epoch, before it was packed. Files are sorted by path, and files without a stored modification time
+    /// are left out
+    pub fn entries_modified_since(
+        &self,
+        ts: i64,
+    ) -> impl Iterator<Item = (std::path::PathBuf, &entry::File)> {
+        let mut found = vec![];
+        files(&self.header.root, std::path::Path::new(""), &mut found);
+        found.sort_by(|(a, _), (b, _)| a.cmp(b));
+        found.into_iter().filter(
+            move |(_, file)| matches!(file.meta.borrow().modified, Some(modified) if modified > ts),
+        )
+    }
+
+    /// Remove the file or directory at `path` from the archive and return it. Only the header is changed, so
+    /// saving with [save_updated](fn@Bar::save_updated) leaves the removed entry's data in the archive
+    pub fn remove_entry(&mut self, path: impl AsRef<std::path::Path>) -> BarResult<Entry> {
+        bar::remove_entry(&mut self.header.root, path.as_ref())
+    }
+
     /// Get a mutable reference to the root directory
     #[inline]
     pub fn root_mut(&mut self) -> &mut entry::Dir {
@@ -108,8 +1283,7 @@ impl<S: io::Read + io::Seek> Bar<S> {
         self.header
             .root
             .entry_mut(path)
-            .map(|e| e.as_file_mut())
-            .flatten()
+            .and_then(|e| e.as_file_mut())
     }
 
     #[inline]
@@ -117,13 +1291,21 @@ impl<S: io::Read + io::Seek> Bar<S> {
         self.header
             .root
             .entry_mut(path)
-            .map(|e| e.as_dir_mut())
-            .flatten()
+            .and_then(|e| e.as_dir_mut())
+    }
+
+    /// Get a mutable reference to the directory at `path`, creating it and any missing parent directories.
+    /// Returns an error if a component of the path is a file
+    pub fn get_or_create_dir(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> BarResult<&mut entry::Dir> {
+        bar::create_dirs(&mut self.header.root, &bar::path_names(path.as_ref()))
     }
 
     #[inline]
     pub fn dir(&self, path: impl AsRef<std::path::Path>) -> Option<&entry::Dir> {
-        self.header.root.entry(path).map(|e| e.as_dir()).flatten()
+        self.header.root.entry(path).and_then(|e| e.as_dir())
     }
 
     /// Save this archive to a directory, decompressing all contained files
@@ -142,45 +1324,234 @@ impl<S: io::Read + io::Seek> Bar<S> {
         rmpv::encode::write_value(&mut metafile, &metadata)?;
 
         for (_, entry) in self.header.root.data.iter() {
-            Self::save_entry(dir.as_ref(), entry, &mut self.data, prog, true, true)?;
+            Self::save_entry(
+                dir.as_ref(),
+                entry,
+                &mut self.data,
+                self.header.solid.as_ref(),
+                prog,
+                true,
+                true,
+                self.io_buffer_size,
+                self.decompression_limit,
+            )?;
         }
 
         Ok(())
     }
 
+    /// Save this differential archive to a directory like [save_unpacked](fn@Bar::save_unpacked), reading the
+    /// data of inherited files from `base`, the archive that this archive was packed against
+    pub fn save_unpacked_with_base<B: io::Read + io::Seek>(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        base: &mut Bar<B>,
+        prog: bool,
+    ) -> BarResult<()> {
+        /// Save a directory's files, reading inherited files from the base archive
+        fn save_dir<S: io::Read + io::Seek, B: io::Read + io::Seek>(
+            dir: &entry::Dir,
+            path: &std::path::Path,
+            bar: &mut Bar<S>,
+            base: &mut Bar<B>,
+            prog: bool,
+        ) -> BarResult<()> {
+            std::fs::create_dir_all(path)?;
+            for entry in dir.entries() {
+                let path = path.join(entry.name());
+                match entry {
+                    Entry::Dir(dir) => save_dir(dir, &path, bar, base, prog)?,
+                    Entry::File(file) => {
+                        let mut out = io::BufWriter::with_capacity(
+                            bar.io_buffer_size,
+                            std::fs::File::create(path)?,
+                        );
+                        match file.inherited {
+                            true => Bar::save_file(
+                                &entry::File {
+                                    inherited: false,
+                                    ..file.clone()
+                                },
+                                &mut out,
+                                &mut base.data,
+                                base.header.solid.as_ref(),
+                                true,
+                                prog,
+                                base.decompression_limit,
+                            )?,
+                            false => Bar::save_file(
+                                file,
+                                &mut out,
+                                &mut bar.data,
+                                bar.header.solid.as_ref(),
+                                true,
+                                prog,
+                                bar.decompression_limit,
+                            )?,
+                        }
+                        out.flush()?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        let dir = path.as_ref().join(self.header.meta.name.clone());
+        std::fs::create_dir_all(&dir)?;
+        let metadata = self.all_entry_metadata(&dir);
+        let mut metafile = std::fs::File::create(dir.join(Self::ROOT_METADATA_FILE))?;
+        rmpv::encode::write_value(&mut metafile, &metadata)?;
+
+        let root = self.header.root.clone();
+        save_dir(&root, &dir, self, base, prog)
+    }
+
+    /// Search the decompressed contents of every file in the archive for lines matching a regular expression,
+    /// calling `on_match` with the file's path, the line number starting from 1, and the line for each
+    /// match. Files are decompressed and searched one at a time, and files that contain a null byte near
+    /// their start are treated as binary and skipped
+    pub fn grep(
+        &mut self,
+        pattern: &regex::Regex,
+        mut on_match: impl FnMut(&std::path::Path, usize, &str),
+    ) -> BarResult<()> {
+        /// Collect the paths of every file in a directory and its subdirectories
+        fn file_paths(
+            dir: &entry::Dir,
+            path: &std::path::Path,
+            paths: &mut Vec<std::path::PathBuf>,
+        ) {
+            for entry in dir.entries() {
+                let path = path.join(entry.name());
+                match entry {
+                    Entry::File(_) => paths.push(path),
+                    Entry::Dir(dir) => file_paths(dir, &path, paths),
+                }
+            }
+        }
+
+        let mut paths = vec![];
+        file_paths(&self.header.root, std::path::Path::new(""), &mut paths);
+        paths.sort();
+
+        for path in paths {
+            let mut reader = self.open(&path)?;
+            let mut start = Vec::new();
+            reader
+                .by_ref()
+                .take(BINARY_CHECK_LEN as u64)
+                .read_to_end(&mut start)?;
+            if is_binary(&start) {
+                continue;
+            }
+
+            let reader = io::BufReader::new(io::Cursor::new(start).chain(reader));
+            for (num, line) in io::BufRead::split(reader, b'\n').enumerate() {
+                let line = line?;
+                let line = String::from_utf8_lossy(&line);
+                let line = line.strip_suffix('\r').unwrap_or(&line);
+                if pattern.is_match(line) {
+                    on_match(&path, num + 1, line);
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Get a reference to a file contained in this archive if the file exists
     #[inline]
     pub fn file(&self, path: impl AsRef<std::path::Path>) -> Option<&entry::File> {
-        self.header.root.entry(path).map(|e| e.as_file()).flatten()
+        self.header.root.entry(path).and_then(|e| e.as_file())
     }
 
     /// Save this archive to any type implementing `Write`, compressing files as needed
+    #[inline]
     pub fn save<W: io::Write>(&mut self, writer: &mut W, prog: bool) -> BarResult<()> {
+        self.save_inner(writer, prog, Layout::Tree).map(|_| ())
+    }
+
+    /// Save this archive like [save](fn@Bar::save) and return the SHA-256 hash of the saved archive, which
+    /// is computed from the written bytes as they are saved. The hash covers everything except the hash
+    /// trailer itself, so archives with the same contents, metadata, and compression have the same hash and
+    /// it can be used as a cache key. See [verify_archive_hash](fn@Bar::verify_archive_hash)
+    #[inline]
+    pub fn save_hashed<W: io::Write>(&mut self, writer: &mut W, prog: bool) -> BarResult<[u8; 32]> {
+        self.save_inner(writer, prog, Layout::Tree)
+    }
+
+    /// Save this archive like [save](fn@Bar::save), but write file data ordered by file extension and then
+    /// file name instead of by position in the directory tree. Grouping similar files together in the data
+    /// section keeps related data close, which later compression of the data section can take advantage of
+    #[inline]
+    pub fn save_sorted<W: io::Write>(&mut self, writer: &mut W, prog: bool) -> BarResult<()> {
+        self.save_inner(writer, prog, Layout::Sorted).map(|_| ())
+    }
+
+    /// Save this archive as a solid archive, concatenating the data of every file and compressing it as one
+    /// stream with `compression` instead of compressing files individually. This usually compresses many
+    /// small, similar files much better, but reading a file requires decompressing the stream up to the
+    /// end of that file. Files are ordered like [save_sorted](fn@Bar::save_sorted) so similar files are
+    /// next to each other in the stream
+    #[inline]
+    pub fn save_solid<W: io::Write>(
+        &mut self,
+        writer: &mut W,
+        compression: CompressType,
+        prog: bool,
+    ) -> BarResult<()> {
+        self.save_inner(writer, prog, Layout::Solid(compression))
+            .map(|_| ())
+    }
+
+    /// Save this archive, writing file data in the given layout and returning the hash of the saved archive
+    fn save_inner<W: io::Write>(
+        &mut self,
+        writer: &mut W,
+        prog: bool,
+        layout: Layout,
+    ) -> BarResult<[u8; 32]> {
+        //Progress is weighted by how long each file takes to compress, so that slow compression levels don't
+        //leave the bar stalled near the end
         let prog = match prog {
-            true => ProgressBar::new_spinner()
-                .with_style(ProgressStyle::default_spinner().tick_chars(".,'`*`',")),
+            true => ProgressBar::new(save_cost(&self.header.root, layout)).with_style(
+                ProgressStyle::default_bar()
+                    .template("[{bar}] {percent}% {msg}")
+                    .progress_chars("=>-"),
+            ),
             false => ProgressBar::hidden(),
         };
         prog.enable_steady_tick(33);
+        let mut writer = io::BufWriter::with_capacity(self.io_buffer_size, writer);
+        let writer = &mut writer;
 
-        self.data.seek(SeekFrom::Start(0))?;
-        let mut data_size = 0u64;
-        let root =
-            match self
-                .header
-                .root
-                .write_data(&mut data_size, writer, &mut self.data, &prog)?
-            {
-                Entry::Dir(dir) => dir,
-                _ => unreachable!(),
-            };
-        self.header.root = root;
-        let header = ser_header(&self.header);
-        rmpv::encode::write_value(writer, &header)?; //Write the header to the output
-        writer.write_u64::<LittleEndian>(data_size)?; //Write the file data size to the output
-
-        writer.flush()?;
-        Ok(())
+        match self.header.solid {
+            //File offsets in a solid archive are into the decompressed stream, so decompress it first
+            Some(solid) => {
+                let mut data = spool(self.temp_dir.as_deref())?;
+                self.data.seek(SeekFrom::Start(solid.off))?;
+                io::copy(&mut bar::solid_stream(&solid, &mut self.data), &mut data)?;
+                write_archive(
+                    &mut self.header,
+                    &mut data,
+                    writer,
+                    self.compress_header,
+                    self.redundant_header,
+                    self.temp_dir.as_deref(),
+                    &prog,
+                    layout,
+                )
+            }
+            None => write_archive(
+                &mut self.header,
+                &mut self.data,
+                writer,
+                self.compress_header,
+                self.redundant_header,
+                self.temp_dir.as_deref(),
+                &prog,
+                layout,
+            ),
+        }
     }
 
     /// Return the root folder of the archive that contains all subfolders and files
@@ -195,6 +1566,20 @@ impl<S: io::Read + io::Seek> Bar<S> {
         self.header.root.entries()
     }
 
+    /// Return an iterator over all entries in this archive sorted by name.
+    /// See [entries_sorted](fn@entry::Dir::entries_sorted)
+    #[inline]
+    pub fn entries_sorted(&self) -> impl Iterator<Item = &Entry> {
+        self.header.root.entries_sorted()
+    }
+
+    /// Write a listing of every entry in the archive to a writer as JSON lines.
+    /// See [write_manifest_jsonl](fn@entry::Dir::write_manifest_jsonl)
+    #[inline]
+    pub fn write_manifest_jsonl(&self, w: &mut impl Write) -> BarResult<()> {
+        Ok(self.header.root.write_manifest_jsonl(w)?)
+    }
+
     /// Return a mutable iterator over all entries in the archive
     #[inline]
     pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut Entry> {
@@ -209,7 +1594,224 @@ impl<S: io::Read + io::Seek> Bar<S> {
         decompress: bool,
         prog: bool,
     ) -> BarResult<()> {
-        Self::save_file(&file, w, &mut self.data, decompress, prog)
+        Self::save_file(
+            &file,
+            w,
+            &mut self.data,
+            self.header.solid.as_ref(),
+            decompress,
+            prog,
+            self.decompression_limit,
+        )
+    }
+
+    /// Check if this archive and `other` contain the same directories and files, and if every file's
+    /// decompressed data is the same. Metadata like notes is ignored, see
+    /// [content_eq_strict](fn@Bar::content_eq_strict) to compare it as well
+    pub fn content_eq<S2: io::Read + io::Seek>(&mut self, other: &mut Bar<S2>) -> BarResult<bool> {
+        self.compare(other, false)
+    }
+
+    /// Check if this archive and `other` are equal like [content_eq](fn@Bar::content_eq), and also have the
+    /// same metadata for the archive and every entry in it
+    pub fn content_eq_strict<S2: io::Read + io::Seek>(
+        &mut self,
+        other: &mut Bar<S2>,
+    ) -> BarResult<bool> {
+        self.compare(other, true)
+    }
+
+    /// Compare the structure and file contents of two archives, and their metadata if `strict` is `true`
+    fn compare<S2: io::Read + io::Seek>(
+        &mut self,
+        other: &mut Bar<S2>,
+        strict: bool,
+    ) -> BarResult<bool> {
+        /// Check that two directories contain entries with the same names and kinds, collecting the pairs
+        /// of files to compare the data of
+        fn same_tree(
+            a: &entry::Dir,
+            b: &entry::Dir,
+            path: &std::path::Path,
+            strict: bool,
+            files: &mut Vec<std::path::PathBuf>,
+        ) -> bool {
+            if a.data.len() != b.data.len() || (strict && *a.meta.borrow() != *b.meta.borrow()) {
+                return false;
+            }
+            a.data
+                .iter()
+                .all(|(name, entry)| match (entry, b.data.get(name)) {
+                    (Entry::Dir(a), Some(Entry::Dir(b))) => {
+                        same_tree(a, b, &path.join(name), strict, files)
+                    }
+                    (Entry::File(a), Some(Entry::File(b))) => {
+                        files.push(path.join(name));
+                        !strict || *a.meta.borrow() == *b.meta.borrow()
+                    }
+                    _ => false,
+                })
+        }
+
+        if strict
+            && (self.header.meta != other.header.meta
+                || self.header.description != other.header.description)
+        {
+            return Ok(false);
+        }
+        let mut files = vec![];
+        if !same_tree(
+            &self.header.root,
+            &other.header.root,
+            std::path::Path::new(""),
+            strict,
+            &mut files,
+        ) {
+            return Ok(false);
+        }
+        for path in files {
+            if !streams_eq(&mut self.open(&path)?, &mut other.open(&path)?)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Read the data of a file as it is stored in the archive without decompressing it, returning the
+    /// file's compression method and its stored bytes
+    pub fn read_file_raw(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> BarResult<(CompressType, Vec<u8>)> {
+        let path = path.as_ref();
+        let file = self
+            .header
+            .root
+            .entry(path)
+            .and_then(|e| e.as_file())
+            .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))?;
+        let mut data = vec![0u8; file.size as usize];
+        bar::read_stored(file, &mut self.data, self.header.solid.as_ref(), &mut data)?;
+        Ok((file.compression, data))
+    }
+
+    /// Estimate how many bytes the data of the file at `path` would take up if it was compressed with
+    /// `target`, by decompressing the file and compressing it again while only counting the compressed
+    /// bytes. The archive is not changed
+    pub fn estimate_recompressed_size(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        target: CompressType,
+    ) -> BarResult<u64> {
+        let mut reader = self.open(path)?;
+        let mut counter = CountWriter {
+            inner: io::sink(),
+            count: 0,
+        };
+        match target.1 {
+            CompressMethod::Deflate => {
+                let mut encoder = DeflateEncoder::new(&mut counter, target.0);
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?;
+            }
+            CompressMethod::Gzip => {
+                let mut encoder = GzEncoder::new(&mut counter, target.0);
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?;
+            }
+            CompressMethod::None => {
+                io::copy(&mut reader, &mut counter)?;
+            }
+        }
+        Ok(counter.count)
+    }
+
+    /// Decompress the data of every file in this archive into `backend` and return a new archive that
+    /// compresses every file with `compression` when it is saved. All metadata is kept, and files inherited
+    /// from a base archive stay inherited
+    pub fn repack<B: io::Read + io::Write + io::Seek>(
+        &mut self,
+        mut backend: B,
+        compression: CompressType,
+        prog: bool,
+    ) -> BarResult<Bar<B>> {
+        let prog = match prog {
+            true => ProgressBar::new_spinner()
+                .with_style(ProgressStyle::default_spinner().tick_chars(".,'`*@*`',")),
+            false => ProgressBar::hidden(),
+        };
+        let mut header = Header {
+            solid: None,
+            ..self.header.clone()
+        };
+        let mut files = vec![];
+        files_mut(&mut header.root, std::path::Path::new(""), &mut files);
+        files.retain(|(_, file)| !file.inherited); //Data of inherited files stays in the base archive
+
+        let mut off = 0u64; //The current offset into the new backing storage
+        for (path, file) in files {
+            prog.set_message(format!("Decompressing file {}", path.display()));
+            let mut reader = FileReader::new(
+                &mut self.data,
+                file.clone(),
+                self.header.solid.as_ref(),
+                self.decompression_limit,
+            )?;
+            let size = io::copy(&mut reader, &mut backend)?;
+            file.off = off;
+            file.size = size as u32;
+            file.compression = compression;
+            off += size;
+            prog.tick();
+        }
+        prog.finish_and_clear();
+
+        Ok(Bar {
+            data: backend,
+            header,
+            compress_header: self.compress_header,
+            redundant_header: self.redundant_header,
+            io_buffer_size: self.io_buffer_size,
+            skipped: Vec::new(),
+            decompression_limit: self.decompression_limit,
+            hash_updates: self.hash_updates,
+            temp_dir: self.temp_dir.clone(),
+        })
+    }
+
+    /// Open a file in the archive for reading, returning a [FileReader] that decompresses the file's data
+    /// as it is read and supports seeking within the decompressed data
+    pub fn open(&mut self, path: impl AsRef<std::path::Path>) -> BarResult<FileReader<'_, S>> {
+        let path = path.as_ref();
+        let file = self
+            .file(path)
+            .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))?
+            .clone();
+        if file.inherited {
+            return Err(BarErr::InheritedFile(path.display().to_string()));
+        }
+        Ok(FileReader::new(
+            &mut self.data,
+            file,
+            self.header.solid.as_ref(),
+            self.decompression_limit,
+        )?)
+    }
+
+    /// Read `len` bytes of a file's decompressed data starting at `start`, stopping early at the end of the
+    /// file. Uncompressed files are read directly from the range, but compressed files must be decompressed
+    /// from their start up to `start`, so reading a range near the end of a large compressed file is slow
+    pub fn read_file_range(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        start: u64,
+        len: u64,
+    ) -> BarResult<Vec<u8>> {
+        let mut reader = self.open(path)?;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut data = Vec::new();
+        reader.take(len).read_to_end(&mut data)?;
+        Ok(data)
     }
 
     /// Save a file entry to a file, or a folder to a real folder, if the recurse parameter is
@@ -239,32 +1841,128 @@ impl<S: io::Read + io::Seek> Bar<S> {
                         path.as_ref(),
                         file,
                         &mut self.data,
+                        self.header.solid.as_ref(),
                         prog,
                         decompress,
                         recurse,
+                        self.io_buffer_size,
+                        self.decompression_limit,
                     )?;
                     dirprog.inc(1);
                 }
                 dirprog.finish_and_clear();
             }
             Entry::File(ref file) => {
-                let mut file_data = std::fs::File::create(path)?;
-                Self::save_file(file, &mut file_data, &mut self.data, decompress, prog)?;
+                let mut file_data =
+                    io::BufWriter::with_capacity(self.io_buffer_size, std::fs::File::create(path)?);
+                Self::save_file(
+                    file,
+                    &mut file_data,
+                    &mut self.data,
+                    self.header.solid.as_ref(),
+                    decompress,
+                    prog,
+                    self.decompression_limit,
+                )?;
+                file_data.flush()?;
             }
         }
         Ok(())
     }
 }
 
+impl<T: AsRef<[u8]>> Bar<io::Cursor<T>> {
+    /// Unpack an archive that is already in memory like [unpack_reader](fn@Bar::unpack_reader), but parse the
+    /// header directly from `data` instead of copying its bytes out first. `data` can be any byte container,
+    /// like a memory map of an archive file, so that opening a large archive only touches the pages that hold
+    /// its header
+    pub fn unpack_bytes(data: T) -> BarResult<Self> {
+        let mut storage = io::Cursor::new(data);
+        let (header, compress_header) = Self::get_header_pos(&mut storage)
+            .and_then(|(start, size)| {
+                let bytes = storage.get_ref().as_ref();
+                Self::parse_header(&bytes[start as usize..(start + size) as usize])
+            })
+            .or_else(|e| match Self::read_front_header(&mut storage) {
+                Ok(Some(header)) => Ok(header),
+                _ => Err(e),
+            })?;
+        let redundant_header = Self::has_front_header(&mut storage)?;
+
+        Ok(Self {
+            header,
+            data: storage,
+            compress_header,
+            redundant_header,
+            io_buffer_size: bar::DEFAULT_IO_BUFFER_SIZE,
+            skipped: Vec::new(),
+            decompression_limit: Some(bar::DEFAULT_DECOMPRESSION_LIMIT),
+            hash_updates: false,
+            temp_dir: None,
+        })
+    }
+
+    /// Write the in-memory backing storage of this archive to a new file at `path`, replacing any file that is
+    /// there, and return an archive backed by that file with a copy of this archive's header and settings. The
+    /// file holds the same bytes as the backing storage, so a packed archive is still packed and must be saved
+    /// with [save](fn@Bar::save), while an unpacked archive can be updated in place with
+    /// [save_updated](fn@Bar::save_updated)
+    pub fn persist_to(&self, path: impl AsRef<std::path::Path>) -> BarResult<Bar<std::fs::File>> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(self.data.get_ref().as_ref())?;
+        file.flush()?;
+
+        Ok(Bar {
+            data: file,
+            header: self.header.clone(),
+            compress_header: self.compress_header,
+            redundant_header: self.redundant_header,
+            io_buffer_size: self.io_buffer_size,
+            skipped: self.skipped.clone(),
+            decompression_limit: self.decompression_limit,
+            hash_updates: self.hash_updates,
+            temp_dir: self.temp_dir.clone(),
+        })
+    }
+}
+
+impl<'a, S: io::Read + io::Seek> IntoIterator for &'a Bar<S> {
+    type Item = &'a Entry;
+    type IntoIter = std::collections::hash_map::Values<'a, String, Entry>;
+
+    /// Iterate over the entries in the root directory of the archive. See [entries](fn@Bar::entries)
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.header.root.data.values()
+    }
+}
+
+impl<'a, S: io::Read + io::Seek> IntoIterator for &'a mut Bar<S> {
+    type Item = &'a mut Entry;
+    type IntoIter = std::collections::hash_map::ValuesMut<'a, String, Entry>;
+
+    /// Mutably iterate over the entries in the root directory of the archive. See
+    /// [entries_mut](fn@Bar::entries_mut)
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.header.root.data.values_mut()
+    }
+}
+
 impl Bar<std::fs::File> {
     /// Unpack an archive file into a `Bar` struct, returning `Self` if the archive is valid.
     /// Note that this function performs very little, as it does not read archive file data, only
     /// header entries.
     /// ## Example
     /// ```no_run
-    /// # use ::bar::Bar;
+    /// # use ::bar::ar::Bar;
     /// # fn main() {
-    /// let archive = Bar::unpack("./archive.bar", true).unwrap();
+    /// let archive = Bar::unpack("./archive.bar").unwrap();
     /// # }
     /// ```
     pub fn unpack(file: impl AsRef<std::path::Path>) -> BarResult<Self> {
@@ -276,12 +1974,103 @@ impl Bar<std::fs::File> {
         Self::unpack_reader(file)
     }
 
-    /// Re-save a bar file with updated metadata
+    /// Re-save a bar file with updated metadata. The archive's hash trailer is removed unless hashing
+    /// updates was enabled with [set_hash_updates](fn@Bar::set_hash_updates)
     pub fn save_updated(mut self, prog: bool) -> BarResult<()> {
         let (header_pos, _) = Self::get_header_pos(&mut self.data)?;
-        self.data.set_len(header_pos)?; //Truncate the underlying file to erase the file data size and header data
+        self.rewrite_header(header_pos, prog)
+    }
+
+    /// Copy the file at `src_path` in `src` to `dest_path` in this archive without decompressing it. The
+    /// file's stored data is appended to this archive's file data and the entry keeps its compression method
+    /// and metadata, then the header is saved like [save_updated](fn@Bar::save_updated). Missing directories
+    /// in `dest_path` are created
+    pub fn copy_from<S2: io::Read + io::Seek>(
+        &mut self,
+        src: &mut Bar<S2>,
+        src_path: impl AsRef<std::path::Path>,
+        dest_path: impl AsRef<std::path::Path>,
+    ) -> BarResult<()> {
+        let (src_path, dest_path) = (src_path.as_ref(), dest_path.as_ref());
+        if self.header.solid.is_some() {
+            return Err(BarErr::SolidArchive);
+        }
+        let file = src
+            .file(src_path)
+            .ok_or_else(|| BarErr::NoEntry(src_path.display().to_string()))?;
+        if file.inherited {
+            return Err(BarErr::InheritedFile(src_path.display().to_string()));
+        }
+        let meta = Meta {
+            name: transaction::file_name(dest_path)?,
+            ..file.meta.borrow().clone()
+        };
+        let normalized = file.normalized;
+        let (compression, data) = src.read_file_raw(src_path)?;
+
+        //The entry is added to a copy of the header so that the archive is unchanged if the path is taken
+        let (data_end, _) = Self::get_header_pos(&mut self.data)?;
+        let mut header = self.header.clone();
+        let copy = entry::File {
+            meta: RefCell::new(meta),
+            compression,
+            off: data_end,
+            size: data.len() as u32,
+            inherited: false,
+            normalized,
+        };
+        transaction::insert(&mut header.root, dest_path, Entry::File(copy))?;
+
+        self.data.seek(SeekFrom::Start(data_end))?;
+        self.data.write_all(&data)?;
+        self.header = header;
+        self.rewrite_header(data_end + data.len() as u64, false)
+    }
+
+    /// Update the redundant copy of the header at the front of the archive file to match the current header.
+    /// The copy can't grow past the space that was reserved for it when the archive was saved, so if the
+    /// header no longer fits, the copy's magic bytes are erased instead so that an outdated tree is never
+    /// recovered from it
+    fn rewrite_front_header(&mut self) -> BarResult<()> {
+        self.data.seek(SeekFrom::Start(0))?;
+        let mut magic = [0u8; bar::FRONT_HEADER_MAGIC.len()];
+        self.data.read_exact(&mut magic)?;
+        if &magic != bar::FRONT_HEADER_MAGIC {
+            self.redundant_header = false;
+            return Ok(());
+        }
+
+        let reserved = self.data.read_u64::<Endian>()?;
+        let mut front = Vec::new();
+        write_header(&self.header, self.compress_header, &mut front)?;
+        match front.len() as u64 <= reserved {
+            true => {
+                front.resize(reserved as usize, 0);
+                self.data.write_all(&front)?;
+            }
+            false => {
+                log!(
+                    warn,
+                    "The header no longer fits in the {} bytes reserved at the front of the archive, removing the redundant copy",
+                    reserved
+                );
+                self.data.seek(SeekFrom::Start(0))?;
+                self.data.write_all(&[0u8; bar::FRONT_HEADER_MAGIC.len()])?;
+                self.redundant_header = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Truncate the archive file to the `data_end` bytes of file data and write the header and file data size
+    /// after it, followed by the hash trailer if hashing updates is enabled. The redundant copy of the header at the front of the archive is updated too,
+    /// see [rewrite_front_header](fn@Bar::rewrite_front_header)
+    fn rewrite_header(&mut self, data_end: u64, prog: bool) -> BarResult<()> {
+        if self.redundant_header {
+            self.rewrite_front_header()?;
+        }
+        self.data.set_len(data_end)?; //Truncate the underlying file to erase the file data size and header data
         self.data.seek(io::SeekFrom::End(0))?;
-        let val = bar::ser_header(&self.header); //Serialize our header with updated metadata
 
         let prog = match prog {
             true => ProgressBar::new(0).with_style(
@@ -292,9 +2081,21 @@ impl Bar<std::fs::File> {
         };
 
         prog.set_message("Re-writing updated header values to file");
-        rmpv::encode::write_value(&mut prog.wrap_write(&mut self.data), &val)?;
+        write_header(
+            &self.header,
+            self.compress_header,
+            &mut prog.wrap_write(&mut self.data),
+        )?; //Serialize our header with updated metadata
         prog.finish_and_clear();
-        self.data.write_u64::<LittleEndian>(header_pos)?;
+        self.header.counts = Some(EntryCounts::count(&self.header.root));
+        self.data.write_u64::<Endian>(data_end)?;
+
+        if self.hash_updates {
+            let end = self.data.stream_position()?;
+            let hash = Self::hash_range(&mut self.data, end)?;
+            self.data.write_all(&hash)?;
+            self.data.write_all(bar::HASH_TRAILER_MAGIC)?;
+        }
         self.data.flush()?;
         Ok(())
     }
@@ -0,0 +1,242 @@
+//! The `reader` module contains the [FileReader] struct, used to stream a single file's data out of an archive
+
+use super::bar::{read_stored, Solid};
+use super::entry::{self, CompressMethod, CompressType};
+use super::BarErr;
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// The source that a [FileReader] reads decompressed bytes from
+enum Inner<'a, S: Read + Seek> {
+    /// Uncompressed data read directly from the backing storage
+    Raw(&'a mut S),
+
+    /// DEFLATE compressed data
+    Deflate(DeflateDecoder<io::Take<&'a mut S>>),
+
+    /// Gzip compressed data, which may be made of multiple concatenated gzip members
+    Gzip(MultiGzDecoder<io::Take<&'a mut S>>),
+
+    /// Data that was read into memory, used for files in solid archives because their data can only be
+    /// reached by decompressing the archive's stream from the start
+    Buffered(io::Cursor<Vec<u8>>),
+}
+
+/// A `FileReader` streams one file's data out of an archive, decompressing it while it is read.
+///
+/// Seeking is cheap for uncompressed files, as it only requires offset arithmetic. Compressed data can't
+/// be seeked directly, so seeking backwards in a compressed file restarts decompression from the start of
+/// the file and seeking forwards decompresses and discards bytes until the target position is reached.
+/// Seeking relative to the end of a compressed file decompresses the whole file to find its length.
+/// Files in solid archives are read into memory when the reader is created
+pub struct FileReader<'a, S: Read + Seek> {
+    /// The reader that decompressed bytes are read from, this is only `None` while restarting decompression
+    inner: Option<Inner<'a, S>>,
+
+    /// The file that is being read
+    file: entry::File,
+
+    /// The position in the decompressed file data
+    pos: u64,
+
+    /// The decompressed size of the file, if it is known
+    len: Option<u64>,
+
+    /// The largest number of bytes that may be read from the file before reading fails
+    limit: Option<u64>,
+}
+
+impl<'a, S: Read + Seek> FileReader<'a, S> {
+    /// Create a new reader over a file's data in the given backing storage, which fails to read past `limit`
+    /// bytes of decompressed data
+    pub(super) fn new(
+        back: &'a mut S,
+        file: entry::File,
+        solid: Option<&Solid>,
+        limit: Option<u64>,
+    ) -> io::Result<Self> {
+        let len = match file.compression {
+            CompressType(_, CompressMethod::None) => Some(file.size as u64),
+            _ => None,
+        };
+        let inner = match solid {
+            Some(solid) => {
+                let mut data = vec![0u8; file.size as usize];
+                read_stored(&file, back, Some(solid), &mut data)?;
+                Inner::Buffered(io::Cursor::new(data))
+            }
+            None => Self::start(back, &file)?,
+        };
+        Ok(Self {
+            inner: Some(inner),
+            file,
+            pos: 0,
+            len,
+            limit,
+        })
+    }
+
+    /// Seek the backing storage to the start of the file and create a reader for its data
+    fn start(back: &'a mut S, file: &entry::File) -> io::Result<Inner<'a, S>> {
+        back.seek(SeekFrom::Start(file.off))?;
+        Ok(match file.compression {
+            CompressType(_, CompressMethod::None) => Inner::Raw(back),
+            CompressType(_, CompressMethod::Deflate) => {
+                Inner::Deflate(DeflateDecoder::new(back.take(file.size as u64)))
+            }
+            CompressType(_, CompressMethod::Gzip) => {
+                Inner::Gzip(MultiGzDecoder::new(back.take(file.size as u64)))
+            }
+        })
+    }
+
+    /// Restart decompression from the beginning of the file
+    fn restart(&mut self) -> io::Result<()> {
+        let back = match self.inner.take() {
+            Some(Inner::Raw(back)) => back,
+            Some(Inner::Deflate(decoder)) => decoder.into_inner().into_inner(),
+            Some(Inner::Gzip(decoder)) => decoder.into_inner().into_inner(),
+            Some(Inner::Buffered(_)) | None => unreachable!(),
+        };
+        self.inner = Some(Self::start(back, &self.file)?);
+        self.pos = 0;
+        Ok(())
+    }
+
+    /// Get the file that this reader is reading the data of
+    #[inline]
+    pub fn file(&self) -> &entry::File {
+        &self.file
+    }
+}
+
+impl<'a, S: Read + Seek> Read for FileReader<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.inner.as_mut().unwrap() {
+            Inner::Raw(back) => {
+                let remaining = (self.file.size as u64).saturating_sub(self.pos);
+                let max = remaining.min(buf.len() as u64) as usize;
+                back.seek(SeekFrom::Start(self.file.off + self.pos))?;
+                back.read(&mut buf[..max])?
+            }
+            Inner::Deflate(decoder) => decoder.read(buf)?,
+            Inner::Gzip(decoder) => decoder.read(buf)?,
+            Inner::Buffered(data) => data.read(buf)?,
+        };
+        self.pos += read as u64;
+        if let Some(limit) = self.limit.filter(|&limit| self.pos > limit) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                BarErr::DecompressionLimitExceeded(self.file.meta.borrow().name.clone(), limit),
+            ));
+        }
+        if read == 0 && !buf.is_empty() {
+            self.len = Some(self.pos);
+        }
+        Ok(read)
+    }
+}
+
+impl<'a, S: Read + Seek> Seek for FileReader<'a, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(off) => Some(off),
+            SeekFrom::Current(off) => self.pos.checked_add_signed(off),
+            SeekFrom::End(off) => {
+                let len = match self.len {
+                    Some(len) => len,
+                    None => {
+                        //Decompress the rest of the file to find its length
+                        io::copy(self, &mut io::sink())?;
+                        self.pos
+                    }
+                };
+                len.checked_add_signed(off)
+            }
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Attempted to seek before the start of a file",
+            )
+        })?;
+
+        match self.inner {
+            Some(Inner::Raw(_)) => self.pos = target,
+            Some(Inner::Buffered(ref mut data)) => {
+                data.set_position(target);
+                self.pos = target;
+            }
+            _ => {
+                if target < self.pos {
+                    self.restart()?;
+                }
+                //Seeking past the end of compressed data leaves us at the end of the data
+                let skip = target - self.pos;
+                io::copy(&mut Read::by_ref(self).take(skip), &mut io::sink())?;
+            }
+        }
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Bar;
+    use super::*;
+
+    /// Pack a single file with the given contents and compression into an in-memory archive
+    fn packed(contents: &[u8], compression: &str) -> Bar<io::Cursor<Vec<u8>>> {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file.bin"), contents).unwrap();
+        let mut bar = Bar::pack(
+            dir.path(),
+            io::Cursor::new(Vec::new()),
+            compression.parse().unwrap(),
+            false,
+        )
+        .unwrap();
+        let mut saved = io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+        Bar::unpack_reader(saved).unwrap()
+    }
+
+    #[test]
+    pub fn test_seek_uncompressed() {
+        let contents = (0..=255u8).cycle().take(4096).collect::<Vec<_>>();
+        let mut bar = packed(&contents, "none");
+        let mut reader = bar.open("file.bin").unwrap();
+
+        let mut buf = [0u8; 16];
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, contents[1000..1016]);
+
+        reader.seek(SeekFrom::Current(-516)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, contents[500..516]);
+
+        reader.seek(SeekFrom::End(-16)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, contents[4080..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn test_seek_compressed() {
+        let contents = (0..=255u8).cycle().take(4096).collect::<Vec<_>>();
+        let mut bar = packed(&contents, "high-gzip");
+        let mut reader = bar.open("file.bin").unwrap();
+
+        let mut buf = [0u8; 16];
+        reader.seek(SeekFrom::Start(3000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, contents[3000..3016]);
+
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, contents[10..26]);
+
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 4096);
+    }
+}
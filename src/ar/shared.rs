@@ -0,0 +1,163 @@
+//! The `shared` module contains the [SharedBar] struct, used to read an archive from multiple threads at once
+
+use super::bar::Header;
+use super::entry;
+use super::{Bar, BarResult};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Where a [SharedBar] opens new handles to its backing storage from
+#[derive(Debug, Clone)]
+enum Source {
+    /// The archive is a file that is reopened for every handle
+    File(PathBuf),
+
+    /// The archive is in memory and every handle shares the same buffer
+    Memory(Arc<[u8]>),
+}
+
+/// An independent handle to the backing storage of a [SharedBar], each handle has its own position
+/// so handles can be used from different threads without affecting each other
+#[derive(Debug)]
+pub enum SharedReader {
+    /// A handle to a reopened archive file
+    File(std::fs::File),
+
+    /// A cursor over an in-memory archive
+    Memory(io::Cursor<Arc<[u8]>>),
+}
+
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.read(buf),
+            Self::Memory(data) => data.read(buf),
+        }
+    }
+}
+
+impl Seek for SharedReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(file) => file.seek(pos),
+            Self::Memory(data) => data.seek(pos),
+        }
+    }
+}
+
+/// A `SharedBar` is an archive that can open any number of independent readers over its data.
+/// `Bar` seeks its single backing storage for every read, so it can't be read from multiple threads;
+/// instead, clone a `SharedBar` into each thread and call [reader](fn@SharedBar::reader) to get a `Bar`
+/// with its own handle to the archive
+#[derive(Debug, Clone)]
+pub struct SharedBar {
+    /// The header of the archive, copied into every reader
+    header: Header,
+
+    /// The backing storage that readers open handles to
+    source: Source,
+
+    /// The decompression limit of the archive, copied into every reader
+    decompression_limit: Option<u64>,
+}
+
+impl SharedBar {
+    /// Open a new handle to the archive and return a `Bar` that reads from it
+    pub fn reader(&self) -> BarResult<Bar<SharedReader>> {
+        let data = match &self.source {
+            Source::File(path) => SharedReader::File(std::fs::File::open(path)?),
+            Source::Memory(data) => SharedReader::Memory(io::Cursor::new(data.clone())),
+        };
+        Ok(Bar {
+            data,
+            header: self.header.clone(),
+            compress_header: false,
+            redundant_header: false,
+            io_buffer_size: super::bar::DEFAULT_IO_BUFFER_SIZE,
+            skipped: Vec::new(),
+            decompression_limit: self.decompression_limit,
+            hash_updates: false,
+            temp_dir: None,
+        })
+    }
+
+    /// Get the root directory of the archive
+    #[inline]
+    pub fn root(&self) -> &entry::Dir {
+        &self.header.root
+    }
+
+    /// Get a file in the archive, returning `None` if the entry does not exist or is not a file
+    #[inline]
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<&entry::File> {
+        self.header.root.entry(path).and_then(|e| e.as_file())
+    }
+}
+
+impl Bar<io::Cursor<Vec<u8>>> {
+    /// Convert this archive into a [SharedBar] that shares the in-memory archive data between all
+    /// of its readers. This archive must have been read from saved archive data, not packed
+    pub fn into_shared(self) -> SharedBar {
+        SharedBar {
+            header: self.header,
+            source: Source::Memory(self.data.into_inner().into()),
+            decompression_limit: self.decompression_limit,
+        }
+    }
+}
+
+impl Bar<std::fs::File> {
+    /// Convert this archive into a [SharedBar] that reopens the archive file at `path` for every
+    /// reader. `path` must be the path that this archive was unpacked from
+    pub fn into_shared(self, path: impl AsRef<Path>) -> SharedBar {
+        SharedBar {
+            header: self.header,
+            source: Source::File(path.as_ref().to_path_buf()),
+            decompression_limit: self.decompression_limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_shared_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "First file ".repeat(1000)).unwrap();
+        std::fs::write(dir.path().join("b.txt"), "Second file ".repeat(1000)).unwrap();
+
+        let back = io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "medium-gzip".parse().unwrap(), false).unwrap();
+        let mut saved = Vec::new();
+        bar.save(&mut saved, false).unwrap();
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("shared.bar");
+        std::fs::write(&path, &saved).unwrap();
+
+        let memory = Bar::unpack_reader(io::Cursor::new(saved)).unwrap();
+        let file = Bar::unpack(&path).unwrap();
+        for shared in [memory.into_shared(), file.into_shared(&path)] {
+            let threads = [("a.txt", "First file "), ("b.txt", "Second file ")]
+                .iter()
+                .map(|&(name, contents)| {
+                    let shared = shared.clone();
+                    std::thread::spawn(move || {
+                        let mut bar = shared.reader().unwrap();
+                        for _ in 0..10 {
+                            let mut data = String::new();
+                            bar.open(name).unwrap().read_to_string(&mut data).unwrap();
+                            assert_eq!(data, contents.repeat(1000));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! The `transaction` module contains the [Transaction] struct, used to batch edits to an archive file and
+//! save them all at once
+
+use super::bar::{create_dirs, path_names, remove_entry};
+use super::entry::{self, CompressType, Entry, Meta};
+use super::{Bar, BarErr, BarResult};
+use indicatif::ProgressBar;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// An edit to an archive that is applied when a [Transaction] is committed
+#[derive(Debug)]
+enum Op {
+    /// Add a new file with uncompressed data
+    Add {
+        path: PathBuf,
+        data: Vec<u8>,
+        compression: CompressType,
+    },
+
+    /// Remove a file or directory
+    Remove(PathBuf),
+
+    /// Move a file or directory to a new path, which may also change its name
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+/// A `Transaction` collects edits to an archive file and applies them together when it is committed, writing
+/// the archive's header once instead of once per edit with [save_updated](fn@Bar::save_updated). Create one
+/// with [begin](fn@Bar::begin). The archive is not changed until [commit](fn@Transaction::commit) is called,
+/// and if any edit fails then none of them are applied. Committing is not crash-safe though, see
+/// [commit](fn@Transaction::commit). Dropping a transaction without committing it discards its edits like
+/// [rollback](fn@Transaction::rollback)
+#[must_use = "the edits of a transaction are discarded unless it is committed"]
+pub struct Transaction<'a> {
+    /// The archive that edits are applied to
+    bar: &'a mut Bar<std::fs::File>,
+
+    /// Edits in the order they were made
+    ops: Vec<Op>,
+}
+
+impl Bar<std::fs::File> {
+    /// Begin a [Transaction] that batches edits to this archive and saves them all at once when committed
+    pub fn begin(&mut self) -> Transaction<'_> {
+        Transaction {
+            bar: self,
+            ops: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// Add a file with the given uncompressed data at `path`, creating any directories in the path that don't
+    /// exist. The data is compressed with `compression` when the transaction is committed
+    pub fn add_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        data: impl Into<Vec<u8>>,
+        compression: CompressType,
+    ) -> &mut Self {
+        self.ops.push(Op::Add {
+            path: path.as_ref().to_path_buf(),
+            data: data.into(),
+            compression,
+        });
+        self
+    }
+
+    /// Remove the file or directory at `path`. The data of removed files stays in the archive file
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.ops.push(Op::Remove(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Move the file or directory at `from` to `to`, creating any directories in `to` that don't exist
+    pub fn rename(&mut self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> &mut Self {
+        self.ops.push(Op::Rename {
+            from: from.as_ref().to_path_buf(),
+            to: to.as_ref().to_path_buf(),
+        });
+        self
+    }
+
+    /// Get the number of edits that will be applied when this transaction is committed
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Check if no edits have been made in this transaction
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Apply every edit in order and save the archive's header once. Every edit is checked before the
+    /// archive file is written to, so if an edit is invalid the archive is left unchanged.
+    ///
+    /// The archive file is updated in place: data of added files is written over the old header before the
+    /// new header is written after it, like [save_updated](fn@Bar::save_updated). This is not atomic, so an
+    /// I/O error or crash while writing can leave the archive unreadable. Callers that need a crash-safe
+    /// save should copy the archive file, commit to the copy, and rename it over the original
+    pub fn commit(self, prog: bool) -> BarResult<()> {
+        let (data_end, _) = Bar::get_header_pos(&mut self.bar.data)?;
+        let mut header = self.bar.header.clone();
+
+        //Data of added files is compressed into memory and written after the existing file data once every
+        //edit has succeeded, so offsets start at the current end of the file data
+        let mut appended = Vec::new();
+        let mut off = data_end;
+        for op in self.ops {
+            match op {
+                Op::Add {
+                    path,
+                    data,
+                    compression,
+                } => {
+                    let file = entry::File::unwritten(
+                        Meta::new(file_name(&path)?),
+                        compression,
+                        data.len() as u32,
+                    );
+                    let file = file.write_data(
+                        &mut off,
+                        &mut appended,
+                        &mut io::Cursor::new(data),
+                        &ProgressBar::hidden(),
+                    )?;
+                    insert(&mut header.root, &path, file)?;
+                }
+                Op::Remove(path) => {
+                    remove_entry(&mut header.root, &path)?;
+                }
+                Op::Rename { from, to } => {
+                    let entry = remove_entry(&mut header.root, &from)?;
+                    entry.meta_mut().name = file_name(&to)?;
+                    insert(&mut header.root, &to, entry)?;
+                }
+            }
+        }
+
+        self.bar.data.seek(SeekFrom::Start(data_end))?;
+        self.bar.data.write_all(&appended)?;
+        self.bar.header = header;
+        self.bar.rewrite_header(off, prog)
+    }
+
+    /// Discard every edit made in this transaction, leaving the archive unchanged
+    pub fn rollback(self) {}
+}
+
+/// Get the last component of an entry path as a string
+pub(super) fn file_name(path: &Path) -> BarResult<String> {
+    path_names(path)
+        .pop()
+        .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))
+}
+
+/// Add `entry` to `root` at `path`, creating missing parent directories
+pub(super) fn insert(root: &mut entry::Dir, path: &Path, entry: Entry) -> BarResult<()> {
+    let names = path_names(path);
+    let parent = create_dirs(root, &names[..names.len().saturating_sub(1)])?;
+    if parent.data.contains_key(&entry.name()) {
+        return Err(BarErr::EntryExists(path.display().to_string()));
+    }
+    parent.add_entry(entry)?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// Pack a small directory tree into an archive file in `dir` and return its path
+    fn archive(dir: &Path) -> PathBuf {
+        let input = dir.join("input");
+        std::fs::create_dir_all(input.join("sub")).unwrap();
+        std::fs::write(input.join("a.txt"), "First file").unwrap();
+        std::fs::write(input.join("sub/b.txt"), "Second file ".repeat(100)).unwrap();
+
+        let path = dir.join("archive.bar");
+        let back = tempfile::tempfile().unwrap();
+        let mut bar = Bar::pack(&input, back, "fast-gzip".parse().unwrap(), false).unwrap();
+        bar.save(&mut std::fs::File::create(&path).unwrap(), false)
+            .unwrap();
+        path
+    }
+
+    /// Read the file at `path` in `bar` to a string
+    pub(in crate::ar) fn read(bar: &mut Bar<std::fs::File>, path: &str) -> String {
+        let mut data = String::new();
+        bar.open(path).unwrap().read_to_string(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    pub fn test_transaction_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = archive(dir.path());
+
+        let mut bar = Bar::unpack(&path).unwrap();
+        let mut transaction = bar.begin();
+        transaction
+            .add_file(
+                "new/c.txt",
+                "Added file ".repeat(50),
+                "high-gzip".parse().unwrap(),
+            )
+            .remove("a.txt")
+            .rename("sub/b.txt", "moved/renamed.txt");
+        assert_eq!(transaction.len(), 3);
+        transaction.commit(false).unwrap();
+        drop(bar);
+
+        //Updates aren't hashed unless hashing them is enabled
+        let mut bar = Bar::unpack(&path).unwrap();
+        assert!(matches!(
+            bar.verify_archive_hash(),
+            Err(BarErr::NoArchiveHash)
+        ));
+        assert!(bar.entry("a.txt").is_none());
+        assert!(bar.entry("sub/b.txt").is_none());
+        assert!(bar.dir("sub").is_some());
+        assert_eq!(read(&mut bar, "new/c.txt"), "Added file ".repeat(50));
+        assert_eq!(
+            read(&mut bar, "moved/renamed.txt"),
+            "Second file ".repeat(100)
+        );
+        assert_eq!(
+            bar.file("moved/renamed.txt").unwrap().meta.borrow().name,
+            "renamed.txt"
+        );
+    }
+
+    #[test]
+    pub fn test_transaction_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = archive(dir.path());
+        let before = std::fs::read(&path).unwrap();
+
+        let mut bar = Bar::unpack(&path).unwrap();
+        let mut transaction = bar.begin();
+        transaction.remove("a.txt");
+        transaction.rollback();
+
+        //A failing edit leaves the archive unchanged even if earlier edits succeeded
+        let mut transaction = bar.begin();
+        transaction
+            .add_file("c.txt", "Added", "none".parse().unwrap())
+            .remove("missing.txt");
+        assert!(matches!(transaction.commit(false), Err(BarErr::NoEntry(_))));
+        let mut transaction = bar.begin();
+        transaction.rename("a.txt", "sub/b.txt");
+        assert!(matches!(
+            transaction.commit(false),
+            Err(BarErr::EntryExists(_))
+        ));
+        drop(bar);
+
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        let mut bar = Bar::unpack(&path).unwrap();
+        assert_eq!(read(&mut bar, "a.txt"), "First file");
+        assert!(bar.entry("c.txt").is_none());
+    }
+}
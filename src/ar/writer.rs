@@ -0,0 +1,246 @@
+//! The `writer` module contains the [AppendWriter] struct, used to stream data onto the end of a single file
+//! in an archive
+
+use super::bar::{now, remove_entry};
+use super::entry::{self, CompressMethod, CompressType, Entry, Meta};
+use super::transaction::{file_name, insert};
+use super::{Bar, BarErr, BarResult};
+use indicatif::ProgressBar;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// An `AppendWriter` appends bytes written to it to one file in an archive, creating the file if it doesn't
+/// exist. Written bytes are buffered and added to the archive when the writer is flushed or dropped, which
+/// saves the archive's header like [save_updated](fn@Bar::save_updated). Create one with
+/// [open_append_writer](fn@Bar::open_append_writer).
+///
+/// Uncompressed and gzip compressed files that end at the end of the archive's file data are extended in
+/// place, since gzip data can be made of multiple concatenated members. Any other file is decompressed and
+/// written again after the archive's file data with the new bytes added, leaving its old data in the archive
+/// file like removed files. Errors when dropping the writer are ignored, so call
+/// [flush](fn@Write::flush) to handle them
+pub struct AppendWriter<'a> {
+    /// The archive that the file is in
+    bar: &'a mut Bar<std::fs::File>,
+
+    /// The path of the file in the archive
+    path: PathBuf,
+
+    /// The compression used for data added to the file
+    compression: CompressType,
+
+    /// Bytes that were written but not yet added to the archive
+    buf: Vec<u8>,
+}
+
+impl Bar<std::fs::File> {
+    /// Open a writer that appends to the file at `path`, creating it and any missing directories in its path
+    /// if it doesn't exist. Data added to the file is compressed with `compression`, and existing files that
+    /// use another compression method are recompressed with it the first time the writer is flushed
+    pub fn open_append_writer(
+        &mut self,
+        path: impl AsRef<Path>,
+        compression: CompressType,
+    ) -> BarResult<AppendWriter<'_>> {
+        let path = path.as_ref();
+        if self.header.solid.is_some() {
+            return Err(BarErr::SolidArchive);
+        }
+        match self.header.root.entry(path) {
+            Some(Entry::Dir(_)) => return Err(BarErr::EntryExists(path.display().to_string())),
+            Some(Entry::File(file)) if file.inherited => {
+                return Err(BarErr::InheritedFile(path.display().to_string()))
+            }
+            _ => file_name(path)?,
+        };
+
+        Ok(AppendWriter {
+            bar: self,
+            path: path.to_path_buf(),
+            compression,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl<'a> AppendWriter<'a> {
+    /// Add the buffered bytes to the file and save the archive's header
+    fn append(&mut self) -> BarResult<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let (data_end, _) = Bar::get_header_pos(&mut self.bar.data)?;
+        let mut header = self.bar.header.clone();
+        let existing = match header.root.entry(&self.path) {
+            Some(Entry::File(file)) => Some(file.clone()),
+            _ => None,
+        };
+
+        let mut off = data_end;
+        let mut appended = Vec::new();
+        let file = match existing {
+            Some(file)
+                if file.off + file.size as u64 == data_end
+                    && file.compression.1 == self.compression.1
+                    && matches!(
+                        self.compression.1,
+                        CompressMethod::None | CompressMethod::Gzip
+                    ) =>
+            {
+                let chunk = entry::File::unwritten(
+                    Meta::default(),
+                    self.compression,
+                    self.buf.len() as u32,
+                );
+                let chunk = match chunk.write_data(
+                    &mut off,
+                    &mut appended,
+                    &mut io::Cursor::new(&self.buf),
+                    &ProgressBar::hidden(),
+                )? {
+                    Entry::File(chunk) => chunk,
+                    Entry::Dir(_) => unreachable!(),
+                };
+                let size = file
+                    .size
+                    .checked_add(chunk.size)
+                    .ok_or_else(|| BarErr::FileTooLarge(self.path.display().to_string()))?;
+                entry::File { size, ..file }
+            }
+            existing => {
+                let mut data = Vec::new();
+                let meta = match existing {
+                    Some(file) => {
+                        Bar::save_file(
+                            &file,
+                            &mut data,
+                            &mut self.bar.data,
+                            None,
+                            true,
+                            false,
+                            self.bar.decompression_limit,
+                        )?;
+                        file.meta.into_inner()
+                    }
+                    None => Meta::new(file_name(&self.path)?),
+                };
+                data.extend_from_slice(&self.buf);
+                let file = entry::File::unwritten(meta, self.compression, data.len() as u32)
+                    .write_data(
+                        &mut off,
+                        &mut appended,
+                        &mut io::Cursor::new(data),
+                        &ProgressBar::hidden(),
+                    )?;
+                match file {
+                    Entry::File(file) => file,
+                    Entry::Dir(_) => unreachable!(),
+                }
+            }
+        };
+        file.meta.borrow_mut().modified = now();
+
+        if header.root.entry(&self.path).is_some() {
+            remove_entry(&mut header.root, &self.path)?;
+        }
+        insert(&mut header.root, &self.path, Entry::File(file))?;
+
+        self.bar.data.seek(SeekFrom::Start(data_end))?;
+        self.bar.data.write_all(&appended)?;
+        self.bar.header = header;
+        self.bar.rewrite_header(off, false)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<'a> Write for AppendWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.append().map_err(|e| match e {
+            BarErr::Io(e) => e,
+            e => io::Error::other(e),
+        })
+    }
+}
+
+impl<'a> Drop for AppendWriter<'a> {
+    fn drop(&mut self) {
+        let _ = self.append();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::transaction::tests::read;
+    use super::*;
+
+    #[test]
+    pub fn test_append_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        std::fs::write(input.join("a.txt"), "First file").unwrap();
+        let path = dir.path().join("archive.bar");
+        let back = tempfile::tempfile().unwrap();
+        let mut bar = Bar::pack(&input, back, "fast-gzip".parse().unwrap(), false).unwrap();
+        bar.save(&mut std::fs::File::create(&path).unwrap(), false)
+            .unwrap();
+
+        for compression in ["medium-gzip", "fast-deflate", "none"] {
+            let log = format!("logs/{}.log", compression);
+            let mut bar = Bar::unpack(&path).unwrap();
+            bar.set_hash_updates(true);
+            let mut writer = bar
+                .open_append_writer(&log, compression.parse().unwrap())
+                .unwrap();
+            writer.write_all(b"First line\n").unwrap();
+            writer.flush().unwrap();
+            writer.write_all(b"Second line\n").unwrap();
+            writer.flush().unwrap();
+            drop(writer);
+            assert_eq!(read(&mut bar, &log), "First line\nSecond line\n");
+            drop(bar);
+
+            //Appending after the archive is unpacked again extends the same file
+            let mut bar = Bar::unpack(&path).unwrap();
+            bar.verify_archive_hash().unwrap();
+            bar.set_hash_updates(true);
+            writeln!(
+                bar.open_append_writer(&log, compression.parse().unwrap())
+                    .unwrap(),
+                "Third line"
+            )
+            .unwrap();
+            assert_eq!(
+                read(&mut bar, &log),
+                "First line\nSecond line\nThird line\n"
+            );
+        }
+
+        let mut bar = Bar::unpack(&path).unwrap();
+        bar.verify_archive_hash().unwrap();
+        assert_eq!(read(&mut bar, "a.txt"), "First file");
+        assert!(matches!(
+            bar.open_append_writer("logs", "none".parse().unwrap()),
+            Err(BarErr::EntryExists(_))
+        ));
+        //Extending a file that isn't at the end of the file data moves it to the end
+        let mut writer = bar
+            .open_append_writer("a.txt", "none".parse().unwrap())
+            .unwrap();
+        writer.write_all(b", extended").unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(read(&mut bar, "a.txt"), "First file, extended");
+        assert_eq!(
+            read(&mut bar, "logs/none.log"),
+            "First line\nSecond line\nThird line\n"
+        );
+    }
+}
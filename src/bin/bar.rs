@@ -1,5 +1,6 @@
 use bar::{
     ar::{
+        bar::DEFAULT_DECOMPRESSION_LIMIT,
         entry::{self, Entry},
         Bar, BarErr, BarResult,
     },
@@ -11,6 +12,7 @@ use dialoguer::theme::ColorfulTheme;
 use indicatif::HumanBytes;
 use std::{
     fs,
+    io::Seek,
     path::{self, Path},
 };
 use sublime_fuzzy::best_match;
@@ -34,6 +36,13 @@ fn file_exists(s: &str) -> Result<(), String> {
     }
 }
 
+/// Validator for glob patterns
+fn is_glob(s: &str) -> Result<(), String> {
+    globset::Glob::new(s)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid glob pattern {}: {}", s, e))
+}
+
 /// Output directory positional argument
 fn output_dir_arg() -> Arg<'static> {
     Arg::new("output-dir")
@@ -44,11 +53,111 @@ fn output_dir_arg() -> Arg<'static> {
         .validator(file_exists)
 }
 
+/// Flag to match entry paths case-insensitively
+fn ignore_case_arg() -> Arg<'static> {
+    Arg::new("ignore-case")
+        .about("Match entry paths without regard to upper and lower case")
+        .long("ignore-case")
+        .short('i')
+        .takes_value(false)
+}
+
+/// An option with the name "base" that takes the path of the base archive of a differential archive
+fn base_archive_arg() -> Arg<'static> {
+    Arg::new("base")
+        .long("base")
+        .takes_value(true)
+        .validator(file_exists)
+}
+
+/// A flag to show timestamps in UTC instead of the local time zone
+fn utc_arg() -> Arg<'static> {
+    Arg::new("utc")
+        .about("Show timestamps in UTC instead of the local time zone")
+        .long("utc")
+        .takes_value(false)
+}
+
+/// Option to limit how large a single file may decompress to, read with [max_file_size]
+fn max_file_size_arg() -> Arg<'static> {
+    Arg::new("max-file-size")
+        .about("Fail instead of reading a file that decompresses to more than this many bytes, like 500M or 2G, or none for no limit")
+        .long_about("Fail instead of reading a file that decompresses to more than this many bytes, protecting against archives crafted to fill the disk or memory. The size is a number of bytes with an optional K, M, or G suffix for KiB, MiB, or GiB, or none to read files of any size. Files may decompress to at most 16G by default")
+        .long("max-file-size")
+        .takes_value(true)
+        .validator(|s| match s {
+            "none" => Ok(()),
+            s => parse_size(s).map(|_| ()),
+        })
+}
+
+/// Parse a number of bytes with an optional K, M, or G binary suffix
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (num, shift) = match s.to_ascii_uppercase().chars().last() {
+        Some('K') => (&s[..s.len() - 1], 10),
+        Some('M') => (&s[..s.len() - 1], 20),
+        Some('G') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    num.parse::<u64>()
+        .ok()
+        .and_then(|num| num.checked_mul(1 << shift))
+        .ok_or_else(|| format!("{} is not a size in bytes", s))
+}
+
+/// Get the decompression limit given with the `max-file-size` option, which is
+/// [DEFAULT_DECOMPRESSION_LIMIT] if the option isn't given
+fn max_file_size(args: &ArgMatches) -> Option<u64> {
+    match args.value_of("max-file-size") {
+        Some("none") => None,
+        Some(size) => Some(parse_size(size).unwrap()),
+        None => Some(DEFAULT_DECOMPRESSION_LIMIT),
+    }
+}
+
+/// The `compression`, `method`, and `level` arguments that select the compression of files, read with
+/// [compression]
+fn compression_args() -> [Arg<'static>; 3] {
+    [
+        Arg::new("compression")
+            .takes_value(true)
+            .long("compression")
+            .short('c')
+            .about("Select a compression method and quality")
+            .possible_values(&[
+                "high-gzip",
+                "high-deflate",
+                "medium-gzip",
+                "medium-deflate",
+                "fast-gzip",
+                "fast-deflate",
+                "none",
+            ])
+            .default_value("none"),
+        Arg::new("method")
+            .takes_value(true)
+            .long("method")
+            .short('m')
+            .about("Select a compression method, overriding the method and quality of --compression")
+            .possible_values(&["gzip", "deflate", "none"]),
+        Arg::new("level")
+            .takes_value(true)
+            .long("level")
+            .short('l')
+            .requires("method")
+            .about("Select a compression level from 0 (fastest) to 9 (smallest) for --method, defaults to 6")
+            .validator(|s| match s.parse::<u32>() {
+                Ok(level) if level <= 9 => Ok(()),
+                _ => Err("The compression level must be a number from 0 to 9".to_owned()),
+            }),
+    ]
+}
+
 /// Create the `pack` subcommand
 fn pack_subcommand() -> App<'static> {
     App::new("pack")
         .about("Pack a directory into an archive")
-        .long_about("Pack a directory into a bar formatted archive. If the folder contains a metadata file (.__barmeta.msgpack), then metadata will be preserved")
+        .long_about("Pack a directory into a bar formatted archive. If the folder contains a metadata file (.__barmeta.msgpack), then metadata will be preserved. Entries matching the glob patterns listed in a .barignore file are left out of the directory containing it and its subdirectories")
         .visible_alias("p")
         .arg(Arg::new("input-dir")
             .required(true)
@@ -62,21 +171,83 @@ fn pack_subcommand() -> App<'static> {
             .multiple_occurrences(false)
             .about("Path to the finished output archive file (careful, if a file already exists, it will be deleted)")
         )
-        .arg(Arg::new("compression")
+        .args({
+            //Only pack can choose the compression of each file
+            let [compression, method, level] = compression_args();
+            [
+                compression
+                    .possible_value("auto")
+                    .about("Select a compression method and quality, or auto to choose one for each file by compressing a sample of it. Solid archives packed with auto are compressed with high-deflate"),
+                method,
+                level,
+            ]
+        })
+        .arg(Arg::new("compress-header")
+            .long("compress-header")
+            .takes_value(false)
+            .about("Compress the archive's header, useful for archives with many files")
+        )
+        .arg(Arg::new("solid")
+            .long("solid")
+            .takes_value(false)
+            .about("Compress all files together as one stream, which is better for many small, similar files")
+        )
+        .arg(Arg::new("store-incompressible")
+            .long("store-incompressible")
+            .takes_value(false)
+            .about("Store files that are already compressed, like images and videos, without compressing them again")
+        )
+        .arg(Arg::new("timestamp")
+            .long("timestamp")
+            .takes_value(false)
+            .about("Record when the archive was packed, using SOURCE_DATE_EPOCH if it is set so the archive stays reproducible")
+        )
+        .arg(Arg::new("text-normalize")
+            .long("text-normalize")
+            .takes_value(false)
+            .about("Store text files with LF line endings, and extract them with the platform's line endings")
+        )
+        .arg(Arg::new("stats")
+            .long("stats")
+            .alias("compression-stats")
+            .takes_value(false)
+            .about("Print the number of files, their total size before and after compression, and the time taken after packing")
+        )
+        .arg(Arg::new("redundant-header")
+            .long("redundant-header")
+            .takes_value(false)
+            .about("Write a copy of the archive's header at the start of the archive so it can be recovered if the end is damaged")
+        )
+        .arg(Arg::new("exclude")
+            .long("exclude")
             .takes_value(true)
-            .long("compression")
-            .short('c')
-            .about("Select a compression method and quality")
-            .possible_values(&[
-                "high-gzip",
-                "high-deflate",
-                "medium-gzip",
-                "medium-deflate",
-                "fast-gzip",
-                "fast-deflate",
-                "none",
-            ])
-            .default_value("none")
+            .multiple_occurrences(true)
+            .validator(is_glob)
+            .about("Skip files and directories whose path in the input directory matches a glob pattern, can be used multiple times")
+        )
+        .arg(Arg::new("include")
+            .long("include")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .validator(is_glob)
+            .about("Only pack files whose path in the input directory matches a glob pattern, can be used multiple times")
+        )
+        .arg(Arg::new("files-from")
+            .long("files-from")
+            .takes_value(true)
+            .validator(file_exists)
+            .about("Pack only the files listed in a text file instead of the whole input directory")
+            .long_about("Pack only the files listed in a text file instead of the whole input directory. Each line of the list is a path to a file, optionally followed by a tab and the path to store the file at in the archive. Relative file paths are resolved from the input directory, blank lines and lines starting with '#' are ignored")
+        )
+        .arg(base_archive_arg()
+            .about("Create a differential archive that only stores files that changed since the base archive was packed")
+        )
+        .arg(Arg::new("temp-dir")
+            .long("temp-dir")
+            .takes_value(true)
+            .validator(file_exists)
+            .about("Directory to store packed files in before they are compressed, instead of the system's temporary directory")
+            .long_about("Directory to store packed files in before they are compressed, instead of the system's temporary directory. Every packed file is copied there uncompressed, so it needs as much free space as the input directory. Compressed file data is also spooled there when writing a redundant header")
         )
 }
 
@@ -87,6 +258,35 @@ fn unpack_subcommand() -> App<'static> {
         .long_about("Unpack a packed .bar archive into a directory. A folder in the output-dir argument will be created with the name of the archive")
         .arg(input_archive_arg())
         .arg(output_dir_arg())
+        .arg(max_file_size_arg())
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .about("Check the archive's hash before unpacking and fail if the archive is corrupted")
+                .takes_value(false),
+        )
+        .arg(base_archive_arg()
+            .about("The base archive that a differential archive was packed against, used to read files that didn't change")
+        )
+}
+
+fn repack_subcommand() -> App<'static> {
+    App::new("repack")
+        .about("Write a copy of an archive with every file compressed again using new settings")
+        .long_about("Decompress every file in an archive and write a new archive that compresses them with the selected compression, keeping all metadata. The output can be the same file as the input")
+        .arg(input_archive_arg())
+        .arg(Arg::new("output-file")
+            .required(true)
+            .takes_value(true)
+            .about("Path to the repacked output archive file (careful, if a file already exists, it will be replaced)")
+        )
+        .args(compression_args())
+        .arg(Arg::new("encrypt")
+            .long("encrypt")
+            .takes_value(true)
+            .value_name("PASSWORD")
+            .about("Encrypt the repacked archive with a password, see the enc subcommand")
+        )
 }
 
 fn meta_subcommand() -> App<'static> {
@@ -94,6 +294,7 @@ fn meta_subcommand() -> App<'static> {
         .about("View metadata of one/many files or directories")
         .visible_alias("m")
         .visible_alias("meta")
+        .visible_alias("info")
         .arg(input_archive_arg())
         .arg(
             Arg::new("entry-paths")
@@ -101,6 +302,19 @@ fn meta_subcommand() -> App<'static> {
                 .multiple_values(true)
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("top")
+                .about("List the given number of largest files in the archive")
+                .long("top")
+                .takes_value(true)
+                .conflicts_with("entry-paths")
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err("The number of files to list must be a number".to_owned()),
+                }),
+        )
+        .arg(ignore_case_arg())
+        .arg(utc_arg())
 }
 
 fn tree_subcommand() -> App<'static> {
@@ -122,6 +336,28 @@ fn tree_subcommand() -> App<'static> {
                 .short('r')
                 .long("recursive")
         )
+        .arg(
+            Arg::new("format")
+                .about("Select the output format, jsonl prints one JSON object per entry of all subdirectories")
+                .long("format")
+                .short('f')
+                .takes_value(true)
+                .possible_values(&["text", "jsonl"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::new("times")
+                .about("Show when each file was last modified before it was packed")
+                .long("times")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("icons")
+                .about("Color files by the kind of data they hold and show an icon before each one")
+                .long("icons")
+                .takes_value(false),
+        )
+        .arg(utc_arg())
 }
 
 fn extract_subcommand() -> App<'static> {
@@ -129,6 +365,7 @@ fn extract_subcommand() -> App<'static> {
         .about("Extract a file from a packed archive")
         .arg(input_archive_arg())
         .arg(output_dir_arg())
+        .arg(max_file_size_arg())
         .visible_alias("e")
         .arg(Arg::new("decompress")
             .short('d')
@@ -158,6 +395,22 @@ fn extract_subcommand() -> App<'static> {
             .short('r')
             .takes_value(false)
         )
+        .arg(ignore_case_arg())
+}
+
+fn extract_unused_subcommand() -> App<'static> {
+    App::new("extract-unused")
+        .about("Extract every file that has not been marked as used")
+        .long_about("Extract every file that has not been marked as used to the output directory, keeping the directories that contain them")
+        .arg(input_archive_arg())
+        .arg(output_dir_arg())
+        .arg(max_file_size_arg())
+        .arg(Arg::new("update-as-used")
+            .about("Mark the extracted files as used")
+            .takes_value(false)
+            .long("consume")
+            .short('c')
+        )
 }
 
 fn edit_subcommand() -> App<'static> {
@@ -173,6 +426,60 @@ fn edit_subcommand() -> App<'static> {
         )
 }
 
+fn shell_subcommand() -> App<'static> {
+    App::new("shell")
+        .visible_alias("sh")
+        .about("Open an interactive shell to browse and edit an archive")
+        .long_about("Open an interactive shell to browse and edit an archive with commands like ls, cd, cat, rm, add, and note. Enter help in the shell to list every command. Edits are written to the archive with the save command")
+        .arg(input_archive_arg())
+        .arg(max_file_size_arg())
+}
+
+fn prune_subcommand() -> App<'static> {
+    App::new("prune")
+        .about("Remove every file that has not been marked as used")
+        .long_about("Remove every file that has not been marked as used from the archive, along with directories that are left empty")
+        .arg(input_archive_arg())
+        .arg(
+            Arg::new("dry-run")
+                .about("Only print the files that would be removed without changing the archive")
+                .long("dry-run")
+                .short('n')
+                .takes_value(false),
+        )
+}
+
+fn scrub_subcommand() -> App<'static> {
+    App::new("scrub")
+        .about("Clear notes, usage, timestamps, and the packed directory from an archive before sharing it")
+        .long_about("Clear metadata that could be personal from the archive and every file and directory in it while keeping their names. Choose what to clear with --notes, --used, --timestamps, and --provenance, or give none of them to clear everything")
+        .arg(input_archive_arg())
+        .arg(
+            Arg::new("notes")
+                .about("Clear the notes of every entry")
+                .long("notes")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("used")
+                .about("Clear the used flag and access count of every entry")
+                .long("used")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("timestamps")
+                .about("Clear the modification and access times of every entry and when the archive was created")
+                .long("timestamps")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("provenance")
+                .about("Clear the path of the directory that the archive was packed from")
+                .long("provenance")
+                .takes_value(false),
+        )
+}
+
 fn search_subcommand() -> App<'static> {
     App::new("search")
         .visible_alias("find")
@@ -218,6 +525,49 @@ fn search_subcommand() -> App<'static> {
                 .default_value("0")
                 .allow_hyphen_values(true),
         )
+        .arg(utc_arg())
+        .arg(
+            Arg::new("output")
+                .about("Select the output format, json prints an array of results with their path, score, size, and note")
+                .long("output")
+                .alias("output-format")
+                .short('o')
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
+}
+
+fn grep_subcommand() -> App<'static> {
+    App::new("grep")
+        .about("Search the contents of files in the archive for lines matching a regular expression")
+        .long_about("Search the decompressed contents of every file in the archive for lines matching a regular expression. Binary files are skipped")
+        .arg(input_archive_arg())
+        .arg(
+            Arg::new("pattern")
+                .allow_hyphen_values(true)
+                .required(true)
+                .takes_value(true)
+                .about("Regular expression to search file contents with")
+                .validator(|s| regex::Regex::new(s).map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("ignore-case")
+                .about("Match the pattern without regard to upper and lower case")
+                .long("ignore-case")
+                .short('i')
+                .takes_value(false),
+        )
+}
+
+/// Key file option for the enc and dec subcommands, used instead of a password
+fn keyfile_arg() -> Arg<'static> {
+    Arg::new("keyfile")
+        .long("keyfile")
+        .takes_value(true)
+        .validator(file_exists)
+        .conflicts_with("password")
+        .about("A file to read the key from instead of a password, its contents are hashed so it can be any length")
 }
 
 fn enc_subcommand() -> App<'static> {
@@ -239,9 +589,10 @@ fn enc_subcommand() -> App<'static> {
         )
         .arg(Arg::new("password")
             .about("A password for the encrypted file, this will be trimmed to 16 bytes if it is longer and padded if it is shorter")
-            .required(true)
+            .required_unless_present("keyfile")
             .allow_hyphen_values(true)
         )
+        .arg(keyfile_arg())
         .arg(Arg::new("keep-file")
             .takes_value(false)
             .short('k')
@@ -269,9 +620,10 @@ fn dec_subcommand() -> App<'static> {
         )
         .arg(Arg::new("password")
             .about("A password for the encrypted file file, this will be trimmed to 16 bytes if it is longer and padded if it is shorter")
-            .required(true)
+            .required_unless_present("keyfile")
             .allow_hyphen_values(true)
         )
+        .arg(keyfile_arg())
         .arg(Arg::new("keep-file")
             .takes_value(false)
             .short('k')
@@ -280,8 +632,62 @@ fn dec_subcommand() -> App<'static> {
         )
 }
 
-/// Print an entry's metadata
-fn print_entry(entry: &Entry) {
+/// How much output subcommands print, selected with the global `--quiet` and `--verbose` flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// Print nothing except errors
+    Quiet,
+    /// Print the output of subcommands as usual
+    Normal,
+    /// Also print every file as it is processed
+    Verbose,
+}
+
+impl Verbosity {
+    /// Get the verbosity level selected in a subcommand's arguments
+    fn from_args(args: &ArgMatches) -> Self {
+        match (args.is_present("quiet"), args.is_present("verbose")) {
+            (true, _) => Self::Quiet,
+            (_, true) => Self::Verbose,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// Check if progress bars should be shown, they are disabled by both `--no-prog` and `--quiet`
+fn show_prog(args: &ArgMatches) -> bool {
+    !args.is_present("no-prog") && Verbosity::from_args(args) != Verbosity::Quiet
+}
+
+/// Print the path of every file in a directory and its subdirectories
+fn print_paths(dir: &entry::Dir, path: &Path) {
+    for entry in dir.entries_sorted() {
+        let path = path.join(entry.name());
+        match entry {
+            Entry::File(_) => println!("{}", path.display()),
+            Entry::Dir(d) => print_paths(d, &path),
+        }
+    }
+}
+
+/// Format a unix timestamp as an ISO-8601 date and time in UTC or the local time zone
+fn format_time(secs: i64, utc: bool) -> String {
+    use chrono::{Local, SecondsFormat, TimeZone, Utc};
+    let formatted = match utc {
+        true => Utc
+            .timestamp_opt(secs, 0)
+            .single()
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        false => Local
+            .timestamp_opt(secs, 0)
+            .single()
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, false)),
+    };
+    formatted.unwrap_or_else(|| secs.to_string())
+}
+
+/// Print an entry's metadata, showing timestamps in UTC if `utc` is `true`
+fn print_entry(entry: &Entry, utc: bool) {
     let meta = match entry {
         Entry::File(file) => {
             println!(
@@ -302,7 +708,7 @@ fn print_entry(entry: &Entry) {
 
             println!(
                 "{}",
-                style(format!("compression: {}", file.compression().to_string())).italic()
+                style(format!("compression: {}", file.compression())).italic()
             );
 
             //Guess the file type from extension
@@ -331,10 +737,20 @@ fn print_entry(entry: &Entry) {
             false => style("This file has not been used").color256(7),
         }
     );
+    if meta.access_count > 0 {
+        println!("Accessed {} times", meta.access_count);
+    }
+    if let Some(last_access) = meta.last_access {
+        println!("Last accessed: {}", format_time(last_access, utc));
+    }
+    if let Some(modified) = meta.modified {
+        println!("Modified: {}", format_time(modified, utc));
+    }
 }
 
-fn main() {
-    let app = App::new("bar")
+/// Create the `bar` command line application with all subcommands
+fn app() -> App<'static> {
+    App::new("bar")
         .about("A utility to pack, unpack, and manipulate .bar archives")
         .global_setting(AppSettings::ColorAuto)
         .global_setting(AppSettings::ColoredHelp)
@@ -348,25 +764,56 @@ fn main() {
                 .takes_value(false)
                 .global(true),
         )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .about("Suppress all output except for errors")
+                .takes_value(false)
+                .global(true)
+                .conflicts_with("verbose"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .about("Print every file as it is processed")
+                .takes_value(false)
+                .global(true),
+        )
         .subcommand(pack_subcommand())
         .subcommand(unpack_subcommand())
+        .subcommand(repack_subcommand())
         .subcommand(meta_subcommand())
         .subcommand(tree_subcommand())
         .subcommand(extract_subcommand())
+        .subcommand(extract_unused_subcommand())
         .subcommand(edit_subcommand())
+        .subcommand(prune_subcommand())
+        .subcommand(scrub_subcommand())
+        .subcommand(shell_subcommand())
         .subcommand(search_subcommand())
+        .subcommand(grep_subcommand())
         .subcommand(enc_subcommand())
-        .subcommand(dec_subcommand());
+        .subcommand(dec_subcommand())
+}
 
-    let matches = app.get_matches();
+fn main() {
+    let matches = app().get_matches();
     match match matches.subcommand() {
         Some(("pack", args)) => pack(args),
         Some(("unpack", args)) => unpack(args),
+        Some(("repack", args)) => repack(args),
         Some(("view", args)) => meta(args),
         Some(("tree", args)) => tree(args),
         Some(("extract", args)) => extract(args),
+        Some(("extract-unused", args)) => extract_unused(args),
         Some(("edit", args)) => edit(args),
+        Some(("prune", args)) => prune(args),
+        Some(("scrub", args)) => scrub(args),
+        Some(("shell", args)) => shell(args),
         Some(("search", args)) => search(args),
+        Some(("grep", args)) => grep(args),
         Some(("enc", args)) => enc(args),
         Some(("dec", args)) => dec(args),
         _ => unreachable!(),
@@ -387,12 +834,19 @@ fn main() {
     }
 }
 
-/// Encrypt any file using the given password
+/// Get the encryption key from the key file or password given to the enc or dec subcommands
+fn key(args: &ArgMatches) -> BarResult<[u8; 16]> {
+    match args.value_of("keyfile") {
+        Some(keyfile) => Ok(enc::file_key(&fs::read(keyfile)?)),
+        None => Ok(enc::password_key(args.value_of("password").unwrap())),
+    }
+}
+
+/// Encrypt any file using the given password or key file
 fn enc(args: &ArgMatches) -> BarResult<()> {
     let filename = args.value_of("input-file").unwrap();
     let output = args.value_of("output-file").unwrap();
-    let mut password = args.value_of("password").unwrap().to_owned();
-    password.push_str("\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+    let key = key(args)?;
 
     let keep = args.is_present("keep-file");
 
@@ -403,12 +857,10 @@ fn enc(args: &ArgMatches) -> BarResult<()> {
         .truncate(true)
         .open(output)?;
 
-    enc::encrypt(
-        &mut file,
-        &mut output,
-        &password.as_bytes()[0..16],
-        !args.is_present("no-prog"),
-    )?;
+    enc::encrypt(&mut file, &mut output, &key, show_prog(args))?;
+    if Verbosity::from_args(args) == Verbosity::Verbose {
+        println!("Encrypted {}", filename);
+    }
     if !keep {
         drop(file);
         fs::remove_file(filename)?;
@@ -417,12 +869,11 @@ fn enc(args: &ArgMatches) -> BarResult<()> {
     Ok(())
 }
 
-/// Decrypt any file using the given password
+/// Decrypt any file using the given password or key file
 fn dec(args: &ArgMatches) -> BarResult<()> {
     let filename = args.value_of("input-file").unwrap();
     let output = args.value_of("output-file").unwrap();
-    let mut password = args.value_of("password").unwrap().to_owned();
-    password.push_str("\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+    let key = key(args)?;
 
     let keep = args.is_present("keep-file");
 
@@ -433,12 +884,10 @@ fn dec(args: &ArgMatches) -> BarResult<()> {
         .truncate(true)
         .open(output)?;
 
-    enc::decrypt(
-        &mut file,
-        &mut output,
-        &password.as_bytes()[0..16],
-        !args.is_present("no-prog"),
-    )?;
+    enc::decrypt(&mut file, &mut output, &key, show_prog(args))?;
+    if Verbosity::from_args(args) == Verbosity::Verbose {
+        println!("Decrypted {}", filename);
+    }
     if !keep {
         drop(file);
         fs::remove_file(filename)?;
@@ -449,30 +898,265 @@ fn dec(args: &ArgMatches) -> BarResult<()> {
 
 /// Pack a directory into a file
 fn pack(args: &ArgMatches) -> BarResult<()> {
+    let start = std::time::Instant::now();
     let input_dir = args.value_of("input-dir").unwrap();
     let output_file = args.value_of("output-file").unwrap();
-    let compression = args.value_of("compression").unwrap().parse().unwrap();
+    //Auto compression is chosen for each file while packing
+    let auto = args.value_of("method").is_none() && args.value_of("compression") == Some("auto");
+    let compression = match auto {
+        true => "high-deflate".parse().unwrap(),
+        false => compression(args),
+    };
 
-    //Open the output file
-    let mut output = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_file)?;
-    let back = tempfile::tempfile().unwrap();
+    let back = backing_file(args)?;
+
+    let mut barchiver = match args.value_of("files-from") {
+        Some(list) => {
+            let files = read_file_list(Path::new(input_dir), list)?;
+            let name = Path::new(input_dir)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "root".to_owned());
+            match auto {
+                true => Bar::pack_files_auto(name, &files, back, show_prog(args))?,
+                false => Bar::pack_files(
+                    name,
+                    &files,
+                    back,
+                    compression,
+                    args.is_present("store-incompressible"),
+                    show_prog(args),
+                )?,
+            }
+        }
+        //Pack the directory into a main file
+        None if auto => Bar::pack_auto(input_dir, back, show_prog(args), pack_filter(args))?,
+        None => Bar::pack_filtered(
+            input_dir,
+            back,
+            compression,
+            args.is_present("store-incompressible"),
+            show_prog(args),
+            pack_filter(args),
+        )?,
+    };
+    if Verbosity::from_args(args) != Verbosity::Quiet {
+        for path in barchiver.skipped() {
+            eprintln!(
+                "{}",
+                style(format!(
+                    "Skipped {}, it is not a regular file or directory",
+                    path.display()
+                ))
+                .yellow()
+            );
+        }
+    }
+    if args.is_present("text-normalize") {
+        barchiver.normalize_line_endings()?;
+    }
+    if let Some(base) = args.value_of("base") {
+        let mut base = Bar::unpack(base)?;
+        barchiver.inherit_unchanged(&mut base)?;
+    }
+    if args.is_present("timestamp") {
+        barchiver.record_created();
+    }
+    barchiver.set_compress_header(args.is_present("compress-header"));
+    barchiver.set_redundant_header(args.is_present("redundant-header"));
+    barchiver.set_temp_dir(args.value_of("temp-dir").map(path::PathBuf::from));
+    //Files are compressed while saving, so these are the sizes of the uncompressed data
+    let before = FileTotals::of(barchiver.root());
+    //Write to a temporary file next to the output and replace the output with it when finished, so a pack
+    //that fails or is interrupted doesn't leave a partial archive
+    let mut output = tempfile::NamedTempFile::new_in(output_dir(Path::new(output_file)))?;
+    match args.is_present("solid") {
+        true => barchiver.save_solid(output.as_file_mut(), compression, show_prog(args))?,
+        false => barchiver.save(output.as_file_mut(), show_prog(args))?,
+    }
+    output.persist(output_file).map_err(|e| e.error)?;
+    if Verbosity::from_args(args) == Verbosity::Verbose {
+        print_paths(barchiver.root(), Path::new(""));
+    }
+    if args.is_present("stats") && Verbosity::from_args(args) != Verbosity::Quiet {
+        let mut saved = Bar::unpack(output_file)?;
+        //File sizes in a solid archive are into the decompressed stream, so use the size of the stream
+        let bytes_out = match args.is_present("solid") {
+            true => saved.data_size()?,
+            false => FileTotals::of(saved.root()).bytes,
+        };
+        println!(
+            "Packed {} files in {:.2}s: {} -> {} ({:.1}% of original size)",
+            before.files,
+            start.elapsed().as_secs_f64(),
+            HumanBytes(before.bytes),
+            HumanBytes(bytes_out),
+            before.ratio(bytes_out) * 100.,
+        );
+    }
 
-    let mut barchiver = Bar::pack(input_dir, back, compression, !args.is_present("no-prog"))?; //Pack the directory into a main file
-    barchiver.save(&mut output, !args.is_present("no-prog"))?;
+    Ok(())
+}
+
+/// The number of files in a directory and the total size of their data, printed by `pack --stats`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct FileTotals {
+    files: u64,
+    bytes: u64,
+}
 
+impl FileTotals {
+    /// Total the files in `dir` and all of its subdirectories. Files inherited from a base archive are not
+    /// counted because their data isn't stored in the archive
+    fn of(dir: &entry::Dir) -> Self {
+        dir.entries().fold(Self::default(), |totals, entry| {
+            let other = match entry {
+                Entry::File(f) if f.inherited() => Self::default(),
+                Entry::File(f) => Self {
+                    files: 1,
+                    bytes: f.size() as u64,
+                },
+                Entry::Dir(d) => Self::of(d),
+            };
+            Self {
+                files: totals.files + other.files,
+                bytes: totals.bytes + other.bytes,
+            }
+        })
+    }
+
+    /// Get the fraction of the original size that `compressed` bytes take up, or 1 if there was no data
+    fn ratio(&self, compressed: u64) -> f64 {
+        match self.bytes {
+            0 => 1.,
+            bytes => compressed as f64 / bytes as f64,
+        }
+    }
+}
+
+/// Create the temporary file that files are packed into, in the directory chosen with `--temp-dir` or the
+/// system's temporary directory
+fn backing_file(args: &ArgMatches) -> std::io::Result<fs::File> {
+    match args.value_of("temp-dir") {
+        Some(dir) => tempfile::tempfile_in(dir),
+        None => tempfile::tempfile(),
+    }
+}
+
+/// Get the directory that an output file is written to, which is the current directory for bare file names
+fn output_dir(output_file: &Path) -> &Path {
+    match output_file.parent() {
+        Some(dir) if dir.components().next().is_some() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Decompress an archive and save it again with new compression, optionally encrypting the output
+fn repack(args: &ArgMatches) -> BarResult<()> {
+    let output_file = Path::new(args.value_of("output-file").unwrap());
+    let mut bar = Bar::unpack(args.value_of("input-file").unwrap())?;
+    let back = tempfile::tempfile()?;
+    let mut repacked = bar.repack(back, compression(args), show_prog(args))?;
+    drop(bar);
+
+    //Write to a temporary file next to the output and replace the output with it when finished, so the
+    //output can be the input archive
+    let mut output = tempfile::NamedTempFile::new_in(output_dir(output_file))?;
+    match args.value_of("encrypt") {
+        Some(password) => {
+            let mut saved = tempfile::tempfile()?;
+            repacked.save(&mut saved, show_prog(args))?;
+            saved.seek(std::io::SeekFrom::Start(0))?;
+
+            enc::encrypt(
+                &mut std::io::BufReader::new(saved),
+                output.as_file_mut(),
+                &enc::password_key(password),
+                show_prog(args),
+            )?;
+        }
+        None => repacked.save(output.as_file_mut(), show_prog(args))?,
+    }
+    output.persist(output_file).map_err(|e| e.error)?;
+
+    if Verbosity::from_args(args) == Verbosity::Verbose {
+        print_paths(repacked.root(), Path::new(""));
+    }
     Ok(())
 }
 
+/// Get the compression to pack files with, using `method` and `level` if they are given and the combined
+/// `compression` value if not
+fn compression(args: &ArgMatches) -> entry::CompressType {
+    let method = match args.value_of("method") {
+        Some("gzip") => entry::CompressMethod::Gzip,
+        Some("deflate") => entry::CompressMethod::Deflate,
+        Some(_) => return "none".parse().unwrap(),
+        None => return args.value_of("compression").unwrap().parse().unwrap(),
+    };
+    let level = args
+        .value_of("level")
+        .map_or(6, |level| level.parse().unwrap());
+    entry::CompressType(flate2::Compression::new(level), method)
+}
+
+/// Build a filter for packed files and directories from the `exclude` and `include` glob patterns. Excluded
+/// entries are never packed, and if any include patterns are given, only files matching one are packed
+fn pack_filter(args: &ArgMatches) -> impl Fn(&Path, bool) -> bool {
+    let globs = |name| {
+        let mut set = globset::GlobSetBuilder::new();
+        for pattern in args.values_of(name).into_iter().flatten() {
+            set.add(globset::Glob::new(pattern).unwrap());
+            //Exclude the directory itself for patterns matching everything in a directory, so it isn't
+            //packed empty
+            if let Some(dir) = pattern.strip_suffix("/**") {
+                set.add(globset::Glob::new(dir).unwrap());
+            }
+        }
+        set.build().unwrap()
+    };
+    let exclude = globs("exclude");
+    let include = globs("include");
+
+    move |path, is_dir| {
+        !exclude.is_match(path) && (is_dir || include.is_empty() || include.is_match(path))
+    }
+}
+
+/// Read a list of files to pack, returning pairs of source file paths and destination paths in the archive.
+/// Each line is either a path to a file or a path to a file and a destination path separated by a tab
+fn read_file_list(base: &Path, list: &str) -> BarResult<Vec<(path::PathBuf, path::PathBuf)>> {
+    let list = fs::read_to_string(list)?;
+    Ok(list
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (src, dest) = line.split_once('\t').unwrap_or((line, line));
+            (base.join(src), path::PathBuf::from(dest))
+        })
+        .collect())
+}
+
 /// Unpack an archive to a directory
 fn unpack(args: &ArgMatches) -> BarResult<()> {
     let input_file = args.value_of("input-file").unwrap();
     let output_dir = args.value_of("output-dir").unwrap();
     let mut barchiver = Bar::unpack(input_file)?; //Pack the directory into a main file
-    barchiver.save_unpacked(output_dir, !args.is_present("no-prog"))?;
+    barchiver.set_decompression_limit(max_file_size(args));
+    if args.is_present("verify") {
+        barchiver.verify_archive_hash()?;
+    }
+    match args.value_of("base") {
+        Some(base) => {
+            let mut base = Bar::unpack(base)?;
+            barchiver.save_unpacked_with_base(output_dir, &mut base, show_prog(args))?
+        }
+        None => barchiver.save_unpacked(output_dir, show_prog(args))?,
+    }
+    if Verbosity::from_args(args) == Verbosity::Verbose {
+        print_paths(barchiver.root(), Path::new(output_dir));
+    }
 
     Ok(())
 }
@@ -481,24 +1165,126 @@ fn unpack(args: &ArgMatches) -> BarResult<()> {
 fn meta(args: &ArgMatches) -> BarResult<()> {
     let bar = Bar::unpack(args.value_of("input-file").unwrap())?;
     let cols = console::Term::stdout().size().1;
+    if Verbosity::from_args(args) == Verbosity::Quiet {
+        return Ok(());
+    }
 
     if !args.is_present("entry-paths") {
         println!("{}", style(format!("Archive {}", bar.meta().name)).bold());
+        if let Some(description) = bar.description() {
+            println!("{}", description);
+        }
+        let counts = bar.entry_counts();
+        println!("{} files, {} directories", counts.files, counts.dirs);
+        if let Some(source_path) = bar.source_path() {
+            println!("{}{}", style("packed from: ").italic(), source_path);
+        }
+        if let Some(created) = bar.created() {
+            println!(
+                "{}{}",
+                style("created: ").italic(),
+                format_time(created, args.is_present("utc"))
+            );
+        }
         if let Some(ref note) = bar.meta().note {
             println!("{}{}", style("note: ").italic(), note);
         }
+        if let Some(top) = args.value_of("top") {
+            let top: usize = top.parse().unwrap();
+            println!("{}", style(format!("Largest {} files:", top)).bold());
+            for (path, file) in bar.files_sorted_by_size().into_iter().take(top) {
+                println!(
+                    "{:>12}  {}",
+                    HumanBytes(file.size() as u64).to_string(),
+                    style(path.display()).green()
+                );
+            }
+        }
     } else {
         for arg in args.values_of("entry-paths").unwrap() {
             println!("{}", "=".repeat(cols as usize));
 
-            let entry = get_entry_or_search(bar.root(), arg);
-            print_entry(entry);
+            let entry = get_entry_or_search(bar.root(), arg, args.is_present("ignore-case"));
+            print_entry(entry, args.is_present("utc"));
         }
     }
 
     Ok(())
 }
 
+/// The kind of data a file holds, guessed from its name to color files in `tree --icons`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileCategory {
+    /// Images, audio, and video
+    Media,
+    /// Plain text, source code, and documents stored as text
+    Text,
+    /// Compressed files and other archives
+    Archive,
+    /// Programs and scripts
+    Executable,
+    /// Anything that isn't recognized
+    Other,
+}
+
+impl FileCategory {
+    /// Extensions of archives and compressed files, many of which MIME types don't cover
+    const ARCHIVES: &'static [&'static str] = &[
+        "zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "bar",
+    ];
+
+    /// Extensions of programs and scripts
+    const EXECUTABLES: &'static [&'static str] = &[
+        "exe", "msi", "com", "bat", "cmd", "ps1", "sh", "bash", "app", "appimage", "elf", "so",
+        "dll",
+    ];
+
+    /// Guess the category of a file from its extension and the MIME type it implies
+    fn of(name: &str) -> Self {
+        let ext = Path::new(name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if Self::ARCHIVES.contains(&ext.as_str()) {
+            return Self::Archive;
+        }
+        if Self::EXECUTABLES.contains(&ext.as_str()) {
+            return Self::Executable;
+        }
+
+        match mime_guess::from_ext(&ext).first() {
+            Some(mime) => match (mime.type_().as_str(), mime.subtype().as_str()) {
+                ("image" | "audio" | "video", _) => Self::Media,
+                ("text", _) | ("application", "json" | "xml" | "javascript" | "toml") => Self::Text,
+                _ => Self::Other,
+            },
+            None => Self::Other,
+        }
+    }
+
+    /// Get the color that files in this category are printed in
+    fn color(self) -> Color {
+        match self {
+            Self::Media => Color::Magenta,
+            Self::Text => Color::Green,
+            Self::Archive => Color::Red,
+            Self::Executable => Color::Yellow,
+            Self::Other => Color::Cyan,
+        }
+    }
+
+    /// Get the glyph that is printed before the names of files in this category
+    fn icon(self) -> char {
+        match self {
+            Self::Media => '\u{25b6}',      //Play button
+            Self::Text => '\u{2261}',       //Lines of text
+            Self::Archive => '\u{25a3}',    //Filled box
+            Self::Executable => '\u{2699}', //Gear
+            Self::Other => '\u{2022}',      //Bullet
+        }
+    }
+}
+
 /// Show a directory tree with metadata
 fn tree(args: &ArgMatches) -> BarResult<()> {
     fn print_tabs(num: u16, dir: bool) {
@@ -511,17 +1297,35 @@ fn tree(args: &ArgMatches) -> BarResult<()> {
             false => print!("- "),
         }
     }
-    fn walk_dir(dir: &entry::Dir, nested: u16) {
+    /// Print a file's name and its modified time if `times` is `Some`, holding if the time is in UTC. If
+    /// `icons` is set, the name is colored by its [FileCategory] and starts with the category's icon
+    fn print_file(file: &entry::File, times: Option<bool>, icons: bool) {
+        let meta = file.meta.borrow();
+        let name = match icons {
+            true => {
+                let category = FileCategory::of(&meta.name);
+                style(format!("{} {}", category.icon(), meta.name)).fg(category.color())
+            }
+            false => style(meta.name.clone()).green(),
+        };
+        match (times, meta.modified) {
+            (Some(utc), Some(modified)) => {
+                println!("{}  {}", name, style(format_time(modified, utc)).italic())
+            }
+            _ => println!("{}", name),
+        }
+    }
+    fn walk_dir(dir: &entry::Dir, nested: u16, times: Option<bool>, icons: bool) {
         print_tabs(nested, true);
         println!("{}", style(&dir.meta.borrow().name).bold().blue());
-        for entry in dir.entries() {
+        for entry in dir.entries_sorted() {
             match entry {
                 entry::Entry::File(file) => {
                     print_tabs(nested + 1, false);
-                    println!("{}", style(&file.meta.borrow().name).green());
+                    print_file(file, times, icons);
                 }
                 entry::Entry::Dir(d) => {
-                    walk_dir(d, nested + 1);
+                    walk_dir(d, nested + 1, times, icons);
                 }
             }
         }
@@ -536,15 +1340,30 @@ fn tree(args: &ArgMatches) -> BarResult<()> {
         },
         None => bar.root(),
     };
-    for entry in dir.entries() {
+    if Verbosity::from_args(args) == Verbosity::Quiet {
+        return Ok(());
+    }
+    if args.value_of("format") == Some("jsonl") {
+        let stdout = std::io::stdout();
+        let mut stdout = std::io::BufWriter::new(stdout.lock());
+        dir.write_manifest_jsonl(&mut stdout)?;
+        return Ok(());
+    }
+
+    let times = match args.is_present("times") {
+        true => Some(args.is_present("utc")),
+        false => None,
+    };
+    let icons = args.is_present("icons");
+    for entry in dir.entries_sorted() {
         match entry {
             entry::Entry::File(file) => {
                 print_tabs(1, false);
-                println!("{}", style(&file.meta.borrow().name).green());
+                print_file(file, times, icons);
             }
             entry::Entry::Dir(d) => {
                 if args.is_present("recursive") {
-                    walk_dir(d, 1);
+                    walk_dir(d, 1, times, icons);
                 } else {
                     print_tabs(1, false);
                     println!("{}", style(&d.meta.borrow().name).blue());
@@ -560,33 +1379,67 @@ fn tree(args: &ArgMatches) -> BarResult<()> {
 fn extract(args: &ArgMatches) -> BarResult<()> {
     let input = args.value_of("input-file").unwrap();
     let mut ar = Bar::unpack(input)?;
+    ar.set_decompression_limit(max_file_size(args));
     let output = path::PathBuf::from(args.value_of("output-dir").unwrap());
 
     for item in args.values_of("extracted-files").unwrap() {
-        let item = get_entry_or_search(ar.root(), item);
+        let item = get_entry_or_search(ar.root(), item, args.is_present("ignore-case"));
         if args.is_present("update-as-used") {
-            item.meta_mut().used = true;
+            item.meta_mut().touch();
         }
 
         let item = item.clone();
+        if Verbosity::from_args(args) == Verbosity::Verbose {
+            println!("{}", output.join(item.name()).display());
+        }
 
         ar.entry_data(
             &output,
             item,
             matches!(args.value_of("decompress").unwrap(), "on" | "true"),
-            !args.is_present("no-prog"),
+            show_prog(args),
             args.is_present("recursive"),
         )?;
     }
 
-    ar.save_updated(!args.is_present("no-prog"))?;
+    ar.save_updated(show_prog(args))?;
+    Ok(())
+}
+
+/// Extract every unused file from an archive, optionally marking them as used
+fn extract_unused(args: &ArgMatches) -> BarResult<()> {
+    let mut ar = Bar::unpack(args.value_of("input-file").unwrap())?;
+    ar.set_decompression_limit(max_file_size(args));
+    let output = path::PathBuf::from(args.value_of("output-dir").unwrap());
+
+    for path in ar.unused_files() {
+        let dir = match path.parent() {
+            Some(parent) => output.join(parent),
+            None => output.clone(),
+        };
+        fs::create_dir_all(&dir)?;
+        if Verbosity::from_args(args) == Verbosity::Verbose {
+            println!("{}", output.join(&path).display());
+        }
+
+        let file = ar.file(&path).unwrap();
+        if args.is_present("update-as-used") {
+            file.meta.borrow_mut().touch();
+        }
+        let file = Entry::File(file.clone());
+        ar.entry_data(&dir, file, true, show_prog(args), false)?;
+    }
+
+    if args.is_present("update-as-used") {
+        ar.save_updated(show_prog(args))?;
+    }
     Ok(())
 }
 
 /// Edit a specific entry's metadata
 fn edit(args: &ArgMatches) -> BarResult<()> {
-    let bar = Bar::unpack(args.value_of("input-file").unwrap())?;
-    let entry = get_entry_or_search(bar.root(), args.value_of("entry").unwrap());
+    let mut bar = Bar::unpack(args.value_of("input-file").unwrap())?;
+    let entry = get_entry_or_search(bar.root(), args.value_of("entry").unwrap(), false);
 
     let choice = dialoguer::Select::with_theme(&ColorfulTheme {
         active_item_prefix: style(">>".to_owned()).green().bold(),
@@ -648,27 +1501,22 @@ fn edit(args: &ArgMatches) -> BarResult<()> {
                     Ok(e) => e,
                 };
 
-                if edit.contains(|c| {
-                    matches!(c, '/' | '\\' | '<' | '>' | ':' | '\"' | '|' | '?' | '*')
-                }) | edit.ends_with('.')
-                    | edit.ends_with(' ')
-                {
+                if let Err(e) = entry::validate_name(&edit) {
+                    eprintln!("{}", style(e).red());
+                    continue;
+                }
+                if entry::NameRules::Windows.validate(&edit).is_err() {
                     eprintln!(
                         "{}",
                         style(format!("Name {} is not valid on Windows", edit)).yellow()
                     );
-                    #[cfg(target_os = "windows")]
-                    continue;
-                    #[cfg(not(target_os = "windows"))]
-                    {
-                        //Display a prompt that the file name is invalid, but allow it on windows
-                        let choice = dialoguer::Confirm::new()
-                            .with_prompt("Are you sure you want to enter this file name?")
-                            .interact()?;
-                        match choice {
-                            true => break edit,
-                            false => continue,
-                        }
+                    //Display a prompt that the file name is invalid on Windows, but allow it on other platforms
+                    let choice = dialoguer::Confirm::new()
+                        .with_prompt("Are you sure you want to enter this file name?")
+                        .interact()?;
+                    match choice {
+                        true => break edit,
+                        false => continue,
                     }
                 } else {
                     break edit;
@@ -680,7 +1528,9 @@ fn edit(args: &ArgMatches) -> BarResult<()> {
         _ => unreachable!(),
     }
 
-    bar.save_updated(!args.is_present("no-prog"))?;
+    //A renamed entry is still stored under its old name until the index is rebuilt
+    bar.rebuild_index()?;
+    bar.save_updated(show_prog(args))?;
     Ok(())
 }
 
@@ -709,21 +1559,430 @@ fn search(args: &ArgMatches) -> BarResult<()> {
         path::PathBuf::from(name),
     );
     let cols = console::Term::stdout().size().1;
+    if Verbosity::from_args(args) == Verbosity::Quiet {
+        return Ok(());
+    }
+    if args.value_of("output") == Some("json") {
+        println!("{}", search_results_json(&scores));
+        return Ok(());
+    }
 
     for (entry, score, path) in scores {
         println!("{}", "=".repeat(cols as usize));
         println!("{}", style(format!("score: {}", score)).italic());
         println!("{}", style(path.display()).italic());
-        print_entry(entry);
+        print_entry(entry, args.is_present("utc"));
     }
 
     Ok(())
 }
 
+/// Convert search results to a JSON array of objects with the path, score, size, and note of each entry.
+/// The size of directories is `null`
+fn search_results_json(scores: &[(&Entry, isize, path::PathBuf)]) -> serde_json::Value {
+    scores
+        .iter()
+        .map(|(entry, score, path)| {
+            serde_json::json!({
+                "path": path,
+                "score": score,
+                "size": entry.as_file().map(|file| file.size()),
+                "note": entry.meta().note.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Remove all unused files from an archive
+fn prune(args: &ArgMatches) -> BarResult<()> {
+    let mut bar = Bar::unpack(args.value_of("input-file").unwrap())?;
+    let removed = bar.prune_unused()?;
+    if Verbosity::from_args(args) != Verbosity::Quiet {
+        for path in removed.iter() {
+            println!("{}", path.display());
+        }
+    }
+
+    if !args.is_present("dry-run") {
+        bar.save_updated(show_prog(args))?;
+    }
+    Ok(())
+}
+
+/// Clear personal metadata from an archive and every entry in it
+fn scrub(args: &ArgMatches) -> BarResult<()> {
+    let mut bar = Bar::unpack(args.value_of("input-file").unwrap())?;
+    let (notes, used, timestamps, provenance) = match (
+        args.is_present("notes"),
+        args.is_present("used"),
+        args.is_present("timestamps"),
+        args.is_present("provenance"),
+    ) {
+        (false, false, false, false) => (true, true, true, true),
+        selected => selected,
+    };
+    bar.strip_metadata(notes, used, timestamps, provenance);
+    bar.save_updated(show_prog(args))
+}
+
+/// Search the contents of files in an archive and print matching lines
+fn grep(args: &ArgMatches) -> BarResult<()> {
+    let mut ar = Bar::unpack(args.value_of("input-file").unwrap())?;
+    let pattern = regex::RegexBuilder::new(args.value_of("pattern").unwrap())
+        .case_insensitive(args.is_present("ignore-case"))
+        .build()
+        .unwrap();
+    let quiet = Verbosity::from_args(args) == Verbosity::Quiet;
+
+    ar.grep(&pattern, |path, num, line| {
+        if !quiet {
+            println!(
+                "{}:{}: {}",
+                style(path.display()).green(),
+                style(num).cyan(),
+                line
+            );
+        }
+    })
+}
+
+/// Open an interactive shell over an archive, see [Shell]
+fn shell(args: &ArgMatches) -> BarResult<()> {
+    let mut bar = Bar::unpack(args.value_of("input-file").unwrap())?;
+    bar.set_decompression_limit(max_file_size(args));
+    let mut shell = Shell::new(bar);
+    let mut editor = rustyline::Editor::<()>::new();
+    loop {
+        let line = match editor.readline(&shell.prompt()) {
+            Ok(line) => line,
+            Err(rustyline::error::ReadlineError::Io(io)) => return Err(BarErr::Io(io)),
+            Err(_) => break,
+        };
+        editor.add_history_entry(line.as_str());
+        match shell.run_line(&line, &mut std::io::stdout()) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => eprintln!("{}", style(e).red()),
+        }
+    }
+
+    if shell.dirty {
+        let save = dialoguer::Confirm::new()
+            .with_prompt("The archive has unsaved changes, save them before exiting?")
+            .default(true)
+            .interact()?;
+        if save {
+            shell.save()?;
+        }
+    }
+    Ok(())
+}
+
+/// An error from running a command in a [Shell]
+#[derive(Debug, thiserror::Error)]
+enum ShellErr {
+    #[error("{0}")]
+    Bar(#[from] BarErr),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    /// A command was unknown or was given the wrong arguments
+    #[error("{0}")]
+    Usage(String),
+}
+
+/// The value passed from one command to the next in a shell pipeline
+enum Piped {
+    /// The command didn't select anything
+    Nothing,
+
+    /// Paths from the root of the archive that were selected by a command like `search`
+    Paths(Vec<path::PathBuf>),
+}
+
+/// Split a line of shell input into the arguments of each command in a pipeline. Arguments are separated by
+/// whitespace unless they are in double quotes, and commands are separated by `|`
+fn split_pipeline(line: &str) -> Result<Vec<Vec<String>>, ShellErr> {
+    let mut stages = vec![vec![]];
+    let mut arg: Option<String> = None;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            '|' if !quoted => {
+                stages.last_mut().unwrap().extend(arg.take());
+                stages.push(vec![]);
+            }
+            c if c.is_whitespace() && !quoted => stages.last_mut().unwrap().extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err(ShellErr::Usage("A quote is not closed".into()));
+    }
+    stages.last_mut().unwrap().extend(arg.take());
+    Ok(stages)
+}
+
+/// Collect the paths of every file in a directory and its subdirectories
+fn file_paths(dir: &entry::Dir, path: &Path, paths: &mut Vec<path::PathBuf>) {
+    for entry in dir.entries_sorted() {
+        let path = path.join(entry.name());
+        match entry {
+            Entry::File(_) => paths.push(path),
+            Entry::Dir(dir) => file_paths(dir, &path, paths),
+        }
+    }
+}
+
+/// An interactive shell session over an open archive, which runs commands to browse the archive and edit it.
+/// Edits are kept in memory until the `save` command writes them to the archive file
+struct Shell {
+    /// The archive that commands run on
+    bar: Bar<fs::File>,
+
+    /// The current directory in the archive that relative paths are resolved from
+    cwd: path::PathBuf,
+
+    /// Files to add to the archive when it is saved, as pairs of the path in the archive and the file's data
+    added: Vec<(path::PathBuf, Vec<u8>)>,
+
+    /// If the archive has been edited since it was last saved
+    dirty: bool,
+}
+
+impl Shell {
+    /// The help text printed by the `help` command
+    const HELP: &'static str = "\
+ls [dir]                 List the entries of a directory
+cd <dir>                 Change the current directory
+cat [files..]            Print the contents of files
+search <glob>            Select every file in the current directory matching a glob pattern
+extract <dir> [paths..]  Extract files or directories to a directory on the filesystem
+rm [paths..]             Remove files or directories
+add <file> [path]        Add a file from the filesystem, it is stored when the archive is saved
+note <path> [text..]     Set the note of an entry, or remove it if no text is given
+save                     Write all changes to the archive
+exit                     Leave the shell
+
+Commands can be joined with | to pass the paths selected by search to cat, extract, or rm,
+like search \"*.txt\" | extract out";
+
+    /// Create a new shell in the root directory of an archive
+    fn new(bar: Bar<fs::File>) -> Self {
+        Self {
+            bar,
+            cwd: path::PathBuf::new(),
+            added: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    /// Get the prompt showing the archive's name and the current directory
+    fn prompt(&self) -> String {
+        format!("{}:/{}> ", self.bar.meta().name, self.cwd.display())
+    }
+
+    /// Resolve a path argument to a path from the root of the archive. Paths starting with `/` are from the
+    /// root and other paths are from the current directory
+    fn resolve(&self, arg: &str) -> path::PathBuf {
+        let mut resolved = match arg.starts_with('/') {
+            true => path::PathBuf::new(),
+            false => self.cwd.clone(),
+        };
+        for component in Path::new(arg).components() {
+            match component {
+                path::Component::Normal(name) => resolved.push(name),
+                path::Component::ParentDir => {
+                    resolved.pop();
+                }
+                _ => (),
+            }
+        }
+        resolved
+    }
+
+    /// Get the directory at a resolved path, where an empty path is the root directory
+    fn dir(&self, path: &Path) -> BarResult<&entry::Dir> {
+        match path.components().next() {
+            None => Ok(self.bar.root()),
+            Some(_) => self
+                .bar
+                .dir(path)
+                .ok_or_else(|| BarErr::NoEntry(path.display().to_string())),
+        }
+    }
+
+    /// Run one line of input, writing the output of commands to `out`. Commands separated by `|` form a
+    /// pipeline where each command receives the paths selected by the command before it, and paths selected
+    /// by the last command are printed. Returns `false` if the shell should exit
+    fn run_line(&mut self, line: &str, out: &mut impl std::io::Write) -> Result<bool, ShellErr> {
+        let stages = split_pipeline(line)?;
+        if let [stage] = stages.as_slice() {
+            if stage.is_empty() {
+                return Ok(true);
+            }
+        }
+
+        let mut piped = Piped::Nothing;
+        for stage in stages.iter() {
+            let (cmd, args) = stage
+                .split_first()
+                .ok_or_else(|| ShellErr::Usage("A command in the pipeline is empty".into()))?;
+            if cmd == "exit" || cmd == "quit" {
+                return Ok(false);
+            }
+            piped = self.run_command(cmd, args, piped, out)?;
+        }
+        if let Piped::Paths(paths) = piped {
+            for path in paths {
+                writeln!(out, "{}", path.display())?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Run one command with the paths piped into it, returning the paths that it selects
+    fn run_command(
+        &mut self,
+        cmd: &str,
+        args: &[String],
+        input: Piped,
+        out: &mut impl std::io::Write,
+    ) -> Result<Piped, ShellErr> {
+        let arg = |idx: usize| {
+            args.get(idx)
+                .map(String::as_str)
+                .ok_or_else(|| ShellErr::Usage(format!("{} is missing an argument", cmd)))
+        };
+        //Commands that take a list of paths use the piped paths, or their arguments if nothing was piped
+        let targets = |args: &[String]| match input {
+            Piped::Paths(paths) => Ok(paths),
+            Piped::Nothing if args.is_empty() => Err(ShellErr::Usage(format!(
+                "{} needs paths as arguments or from a pipeline",
+                cmd
+            ))),
+            Piped::Nothing => Ok(args.iter().map(|arg| self.resolve(arg)).collect()),
+        };
+
+        match cmd {
+            "ls" => {
+                let path = self.resolve(args.first().map_or("", String::as_str));
+                for entry in self.dir(&path)?.entries_sorted() {
+                    match entry {
+                        Entry::Dir(_) => writeln!(out, "{}/", entry.name())?,
+                        Entry::File(_) => writeln!(out, "{}", entry.name())?,
+                    }
+                }
+            }
+            "cd" => {
+                let path = self.resolve(arg(0)?);
+                self.dir(&path)?;
+                self.cwd = path;
+            }
+            "cat" => {
+                for path in targets(args)? {
+                    let mut data = Vec::new();
+                    std::io::copy(&mut self.bar.open(path)?, &mut data)?;
+                    writeln!(out, "{}", String::from_utf8_lossy(&data))?;
+                }
+            }
+            "search" => {
+                let glob = globset::Glob::new(arg(0)?)
+                    .map_err(|e| ShellErr::Usage(e.to_string()))?
+                    .compile_matcher();
+                let mut files = vec![];
+                file_paths(self.dir(&self.cwd)?, Path::new(""), &mut files);
+                return Ok(Piped::Paths(
+                    files
+                        .into_iter()
+                        .filter(|path| glob.is_match(path))
+                        .map(|path| self.cwd.join(path))
+                        .collect(),
+                ));
+            }
+            "extract" => {
+                let dir = Path::new(arg(0)?);
+                fs::create_dir_all(dir)?;
+                for path in targets(&args[1..])? {
+                    let entry = self
+                        .bar
+                        .entry(&path)
+                        .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))?
+                        .clone();
+                    self.bar.entry_data(dir, entry, true, false, true)?;
+                }
+            }
+            "rm" => {
+                for path in targets(args)? {
+                    self.bar.remove_entry(path)?;
+                    self.dirty = true;
+                }
+            }
+            "add" => {
+                let local = Path::new(arg(0)?);
+                let dest = match args.get(1) {
+                    Some(dest) => self.resolve(dest),
+                    None => self.cwd.join(local.file_name().ok_or_else(|| {
+                        ShellErr::Usage(format!("{} is not a file", local.display()))
+                    })?),
+                };
+                if self.bar.entry(&dest).is_some() || self.added.iter().any(|(p, _)| *p == dest) {
+                    return Err(BarErr::EntryExists(dest.display().to_string()).into());
+                }
+                self.added.push((dest, fs::read(local)?));
+                self.dirty = true;
+            }
+            "note" => {
+                let path = self.resolve(arg(0)?);
+                let entry = self
+                    .bar
+                    .entry_mut(&path)
+                    .ok_or_else(|| BarErr::NoEntry(path.display().to_string()))?;
+                entry.meta_mut().note = match args[1..].join(" ") {
+                    note if note.is_empty() => None,
+                    note => Some(note),
+                };
+                self.dirty = true;
+            }
+            "save" => self.save()?,
+            "help" => writeln!(out, "{}", Self::HELP)?,
+            other => {
+                return Err(ShellErr::Usage(format!(
+                    "Unknown command {}, enter help to list commands",
+                    other
+                )))
+            }
+        }
+        Ok(Piped::Nothing)
+    }
+
+    /// Write every edit to the archive file, compressing added files with gzip. Added files are kept until
+    /// they are saved, so a failed save can be retried
+    fn save(&mut self) -> BarResult<()> {
+        let mut transaction = self.bar.begin();
+        for (path, data) in self.added.iter().cloned() {
+            transaction.add_file(path, data, "medium-gzip".parse().unwrap());
+        }
+        transaction.commit(false)?;
+        self.added.clear();
+        self.dirty = false;
+        Ok(())
+    }
+}
+
 /// Get an entry using a string name, or if the entry doesn't exist, search for it
-fn get_entry_or_search<'a>(dir: &'a entry::Dir, item: &str) -> &'a Entry {
-    match dir.entry(item) {
-        Some(ref mut entry) => entry,
+fn get_entry_or_search<'a>(dir: &'a entry::Dir, item: &str, ignore_case: bool) -> &'a Entry {
+    let entry = match ignore_case {
+        true => dir.entry_ci(item),
+        false => dir.entry(item),
+    };
+    match entry {
+        Some(entry) => entry,
         None => {
             let mut items: Vec<(&'a Entry, isize, path::PathBuf)> = vec![];
             let mut loaded = 3; //The number of loaded entries
@@ -734,7 +1993,7 @@ fn get_entry_or_search<'a>(dir: &'a entry::Dir, item: &str) -> &'a Entry {
                     ..Default::default()
                 })
                 .items(
-                    &items
+                    items
                         .iter()
                         .map(|(entry, score, path)| {
                             format!(
@@ -847,3 +2106,711 @@ fn search_dir<'a>(
     scores.sort_by(|(_, item, _), (_, next, _)| item.cmp(next).reverse());
     scores.truncate(max_len);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_verbosity_flags() {
+        let matches = app()
+            .try_get_matches_from(["bar", "tree", "--quiet", "Cargo.toml"])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        assert_eq!(Verbosity::from_args(args), Verbosity::Quiet);
+        assert!(!show_prog(args));
+
+        let matches = app()
+            .try_get_matches_from(["bar", "-v", "tree", "Cargo.toml"])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        assert_eq!(Verbosity::from_args(args), Verbosity::Verbose);
+        assert!(show_prog(args));
+
+        let matches = app()
+            .try_get_matches_from(["bar", "tree", "Cargo.toml"])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        assert_eq!(Verbosity::from_args(args), Verbosity::Normal);
+
+        let err = app()
+            .try_get_matches_from(["bar", "tree", "--quiet", "--verbose", "Cargo.toml"])
+            .unwrap_err();
+        assert_eq!(err.kind, clap::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    pub fn test_pack_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in [
+            ".git/objects/a",
+            "src/main.rs",
+            "src/old.tmp",
+            "notes.txt",
+            "lib.rs",
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"contents").unwrap();
+        }
+
+        let matches = app()
+            .try_get_matches_from([
+                "bar",
+                "pack",
+                "--exclude",
+                "*.tmp",
+                "--exclude",
+                ".git/**",
+                "--include",
+                "*.rs",
+                "Cargo.toml",
+                "out.bar",
+            ])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        let back = std::io::Cursor::new(Vec::new());
+        let bar = Bar::pack_filtered(
+            dir.path(),
+            back,
+            "none".parse().unwrap(),
+            false,
+            false,
+            pack_filter(args),
+        )
+        .unwrap();
+
+        assert!(bar.file("src/main.rs").is_some());
+        assert!(bar.file("lib.rs").is_some());
+        assert!(bar.file("src/old.tmp").is_none());
+        assert!(bar.file("notes.txt").is_none());
+        assert!(bar.entry(".git").is_none());
+    }
+
+    #[test]
+    pub fn test_temp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        let temp = dir.path().join("temp");
+        fs::create_dir(&input).unwrap();
+        fs::create_dir(&temp).unwrap();
+        fs::write(
+            input.join("a.txt"),
+            "Packed through a chosen temporary directory",
+        )
+        .unwrap();
+        let archive = dir.path().join("out.bar");
+        let (input, temp, archive_str) = (
+            input.to_str().unwrap(),
+            temp.to_str().unwrap(),
+            archive.to_str().unwrap(),
+        );
+
+        let matches = app()
+            .try_get_matches_from([
+                "bar",
+                "pack",
+                "-q",
+                "--redundant-header",
+                "--temp-dir",
+                temp,
+                input,
+                archive_str,
+            ])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        pack(args).unwrap();
+        assert!(Bar::unpack(&archive).unwrap().file("a.txt").is_some());
+
+        //The backing file is created in the chosen directory, so packing fails once it is removed
+        fs::remove_dir(temp).unwrap();
+        assert!(matches!(backing_file(args), Err(e) if e.kind() == std::io::ErrorKind::NotFound));
+        assert!(matches!(pack(args), Err(BarErr::Io(_))));
+        assert!(app()
+            .try_get_matches_from(["bar", "pack", "--temp-dir", temp, input, archive_str])
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_compression_level() {
+        let matches = app()
+            .try_get_matches_from([
+                "bar",
+                "pack",
+                "--method",
+                "gzip",
+                "--level",
+                "7",
+                "Cargo.toml",
+                "out.bar",
+            ])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        let compression = compression(args);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), b"Compressed at level 7").unwrap();
+        let back = std::io::Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, compression, false).unwrap();
+        let mut saved = std::io::Cursor::new(Vec::new());
+        bar.save(&mut saved, false).unwrap();
+
+        let bar = Bar::unpack_reader(saved).unwrap();
+        let compression = bar.file("a.txt").unwrap().compression();
+        assert_eq!(compression.0.level(), 7);
+        assert_eq!(compression.1, entry::CompressMethod::Gzip);
+
+        let err = app()
+            .try_get_matches_from(["bar", "pack", "--level", "7", "Cargo.toml", "out.bar"])
+            .unwrap_err();
+        assert_eq!(err.kind, clap::ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    pub fn test_pack_auto() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("input")).unwrap();
+        fs::write(dir.path().join("input/a.txt"), "Automatic ".repeat(500)).unwrap();
+        let input = dir.path().join("input");
+        let archive = dir.path().join("archive.bar");
+        let archive_str = archive.to_str().unwrap();
+        let matches = app()
+            .try_get_matches_from([
+                "bar",
+                "pack",
+                "-q",
+                "-c",
+                "auto",
+                input.to_str().unwrap(),
+                archive_str,
+            ])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        pack(args).unwrap();
+
+        let bar = Bar::unpack(&archive).unwrap();
+        assert_ne!(
+            bar.file("a.txt").unwrap().compression().1,
+            entry::CompressMethod::None
+        );
+        //Repacking compresses every file the same way, so it can't choose automatically
+        assert!(app()
+            .try_get_matches_from(["bar", "repack", archive_str, archive_str, "-c", "auto"])
+            .is_err());
+        assert!(app()
+            .try_get_matches_from(["bar", "repack", archive_str, archive_str, "-c", "fast-gzip"])
+            .is_ok());
+    }
+
+    #[test]
+    pub fn test_read_file_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("list.txt");
+        fs::write(&list, "# Comment\na.txt\n\nsub/b.txt\trenamed/b.txt\n").unwrap();
+
+        let files = read_file_list(Path::new("base"), list.to_str().unwrap()).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                (
+                    path::PathBuf::from("base/a.txt"),
+                    path::PathBuf::from("a.txt")
+                ),
+                (
+                    path::PathBuf::from("base/sub/b.txt"),
+                    path::PathBuf::from("renamed/b.txt")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_file_totals() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("a.txt"), "a".repeat(1000)).unwrap();
+        fs::write(dir.path().join("sub/b.txt"), "b".repeat(500)).unwrap();
+        fs::write(dir.path().join("sub/c.txt"), "").unwrap();
+
+        let back = tempfile::tempfile().unwrap();
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        let totals = FileTotals::of(bar.root());
+        assert_eq!(
+            totals,
+            FileTotals {
+                files: 3,
+                bytes: 1500
+            }
+        );
+        assert!((totals.ratio(750) - 0.5).abs() < f64::EPSILON);
+        assert!((FileTotals::default().ratio(0) - 1.).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    pub fn test_repack() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("input/sub")).unwrap();
+        fs::write(dir.path().join("input/a.txt"), "Repacked text ".repeat(500)).unwrap();
+        fs::write(dir.path().join("input/sub/b.txt"), "Nested file").unwrap();
+        let archive = dir.path().join("archive.bar");
+        let input = dir.path().join("input");
+        let run = |args: &[&str]| {
+            let matches = app().try_get_matches_from(args).unwrap();
+            match matches.subcommand().unwrap() {
+                ("pack", args) => pack(args),
+                ("repack", args) => repack(args),
+                _ => unreachable!(),
+            }
+            .unwrap();
+        };
+        let archive_str = archive.to_str().unwrap();
+        run(&["bar", "pack", "-q", input.to_str().unwrap(), archive_str]);
+        {
+            let mut bar = Bar::unpack(&archive).unwrap();
+            bar.file_mut("a.txt").unwrap().meta.borrow_mut().note = Some("Kept".into());
+            bar.save_updated(false).unwrap();
+        }
+
+        //Repack the archive over itself
+        run(&[
+            "bar",
+            "repack",
+            "-q",
+            archive_str,
+            archive_str,
+            "-c",
+            "high-gzip",
+        ]);
+        let mut bar = Bar::unpack(&archive).unwrap();
+        let file = bar.file("a.txt").unwrap();
+        assert_eq!(file.compression().to_string(), "high-gzip");
+        assert!(file.size() < 7000);
+        assert_eq!(file.meta.borrow().note.as_deref(), Some("Kept"));
+        let mut data = String::new();
+        bar.open("a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Repacked text ".repeat(500));
+        data.clear();
+        bar.open("sub/b.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Nested file");
+
+        let encrypted = dir.path().join("encrypted.bar");
+        run(&[
+            "bar",
+            "repack",
+            "-q",
+            archive_str,
+            encrypted.to_str().unwrap(),
+            "--method",
+            "deflate",
+            "--encrypt",
+            "password",
+        ]);
+        let mut decrypted = Vec::new();
+        let mut password = b"password".to_vec();
+        password.resize(16, 0);
+        enc::decrypt(
+            &mut std::io::BufReader::new(fs::File::open(&encrypted).unwrap()),
+            &mut decrypted,
+            &password,
+            false,
+        )
+        .unwrap();
+        let bar = Bar::unpack_reader(std::io::Cursor::new(decrypted)).unwrap();
+        assert_eq!(
+            bar.file("a.txt").unwrap().compression().to_string(),
+            "6-deflate"
+        );
+    }
+
+    #[test]
+    pub fn test_keyfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        let (plain, locked, unlocked, keyfile) = (
+            path("plain.txt"),
+            path("locked"),
+            path("unlocked.txt"),
+            path("key"),
+        );
+        fs::write(&plain, "Secret data ".repeat(100)).unwrap();
+        fs::write(&keyfile, (0..=255u8).cycle().take(1000).collect::<Vec<_>>()).unwrap();
+        let run = |args: &[&str]| {
+            let matches = app().try_get_matches_from(args).unwrap();
+            match matches.subcommand().unwrap() {
+                ("enc", args) => enc(args),
+                ("dec", args) => dec(args),
+                _ => unreachable!(),
+            }
+            .unwrap()
+        };
+
+        run(&["bar", "enc", &plain, &locked, "--keyfile", &keyfile]);
+        assert!(!fs::read(&locked)
+            .unwrap()
+            .windows(12)
+            .any(|w| w == b"Secret data "));
+        run(&["bar", "dec", &locked, &unlocked, "--keyfile", &keyfile]);
+        assert_eq!(
+            fs::read_to_string(&unlocked).unwrap(),
+            "Secret data ".repeat(100)
+        );
+        assert_eq!(
+            enc::file_key(&fs::read(&keyfile).unwrap()),
+            enc::file_key(&fs::read(&keyfile).unwrap())
+        );
+        assert_ne!(enc::file_key(b"key"), enc::password_key("key"));
+        assert_eq!(&enc::password_key("key"), b"key\0\0\0\0\0\0\0\0\0\0\0\0\0");
+
+        //A key file replaces the password, so exactly one of them must be given
+        assert!(app()
+            .try_get_matches_from([
+                "bar",
+                "dec",
+                &locked,
+                &unlocked,
+                "password",
+                "--keyfile",
+                &keyfile
+            ])
+            .is_err());
+        assert!(app()
+            .try_get_matches_from(["bar", "dec", &locked, &unlocked])
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_file_category() {
+        for (name, category) in [
+            ("photo.JPG", FileCategory::Media),
+            ("song.mp3", FileCategory::Media),
+            ("clip.mp4", FileCategory::Media),
+            ("notes.txt", FileCategory::Text),
+            ("main.rs", FileCategory::Text),
+            ("data.json", FileCategory::Text),
+            ("backup.tar.gz", FileCategory::Archive),
+            ("files.zip", FileCategory::Archive),
+            ("saved.bar", FileCategory::Archive),
+            ("setup.exe", FileCategory::Executable),
+            ("build.sh", FileCategory::Executable),
+            ("unknown.qqq", FileCategory::Other),
+            ("Makefile", FileCategory::Other),
+        ] {
+            assert_eq!(FileCategory::of(name), category, "{}", name);
+        }
+        assert_ne!(FileCategory::Media.color(), FileCategory::Text.color());
+    }
+
+    #[test]
+    pub fn test_max_file_size() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("2k"), Ok(2048));
+        assert_eq!(parse_size("3M"), Ok(3 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("12X").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        fs::create_dir(&input).unwrap();
+        fs::write(input.join("a.txt"), "Large file ".repeat(1000)).unwrap();
+        let archive = dir.path().join("archive.bar");
+        let out = dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        let (input, archive, out) = (
+            input.to_str().unwrap(),
+            archive.to_str().unwrap(),
+            out.to_str().unwrap(),
+        );
+        let run = |args: &[&str]| {
+            let matches = app().try_get_matches_from(args).unwrap();
+            match matches.subcommand().unwrap() {
+                ("pack", args) => pack(args),
+                ("unpack", args) => unpack(args),
+                _ => unreachable!(),
+            }
+        };
+        run(&["bar", "pack", "-q", "-c", "high-gzip", input, archive]).unwrap();
+
+        assert!(matches!(
+            run(&["bar", "unpack", "-q", "--max-file-size", "1K", archive, out]),
+            Err(BarErr::DecompressionLimitExceeded(..))
+        ));
+        run(&["bar", "unpack", "-q", "--max-file-size", "1M", archive, out]).unwrap();
+        run(&[
+            "bar",
+            "unpack",
+            "-q",
+            "--max-file-size",
+            "none",
+            archive,
+            out,
+        ])
+        .unwrap();
+        assert!(app()
+            .try_get_matches_from(["bar", "unpack", "--max-file-size", "nothing", archive, out])
+            .is_err());
+
+        let matches = app()
+            .try_get_matches_from(["bar", "unpack", archive, out])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        assert_eq!(max_file_size(args), Some(DEFAULT_DECOMPRESSION_LIMIT));
+        let matches = app()
+            .try_get_matches_from(["bar", "unpack", "--max-file-size", "none", archive, out])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        assert_eq!(max_file_size(args), None);
+    }
+
+    #[test]
+    pub fn test_extract_unused() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("input/sub")).unwrap();
+        fs::write(dir.path().join("input/a.txt"), "Watched").unwrap();
+        fs::write(dir.path().join("input/sub/b.txt"), "Unwatched ".repeat(100)).unwrap();
+        fs::write(dir.path().join("input/sub/c.txt"), "Also unwatched").unwrap();
+        let archive = dir.path().join("archive.bar");
+        let archive_str = archive.to_str().unwrap();
+        let run = |args: &[&str]| {
+            let matches = app().try_get_matches_from(args).unwrap();
+            match matches.subcommand().unwrap() {
+                ("pack", args) => pack(args),
+                ("extract-unused", args) => extract_unused(args),
+                _ => unreachable!(),
+            }
+            .unwrap();
+        };
+        let input = dir.path().join("input");
+        run(&["bar", "pack", "-q", input.to_str().unwrap(), archive_str]);
+        {
+            let bar = Bar::unpack(&archive).unwrap();
+            bar.file("a.txt").unwrap().meta.borrow_mut().touch();
+            bar.save_updated(false).unwrap();
+        }
+
+        let out = dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        let out_str = out.to_str().unwrap();
+        run(&["bar", "extract-unused", "-q", archive_str, out_str]);
+        assert!(!out.join("a.txt").exists());
+        assert_eq!(
+            fs::read_to_string(out.join("sub/b.txt")).unwrap(),
+            "Unwatched ".repeat(100)
+        );
+        assert_eq!(
+            fs::read_to_string(out.join("sub/c.txt")).unwrap(),
+            "Also unwatched"
+        );
+        assert_eq!(Bar::unpack(&archive).unwrap().unused_files().len(), 2);
+
+        //Consuming the files marks them as used, so there is nothing left to extract afterwards
+        run(&[
+            "bar",
+            "extract-unused",
+            "-q",
+            "--consume",
+            archive_str,
+            out_str,
+        ]);
+        assert!(Bar::unpack(&archive).unwrap().unused_files().is_empty());
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        run(&[
+            "bar",
+            "extract-unused",
+            "-q",
+            archive_str,
+            empty.to_str().unwrap(),
+        ]);
+        assert_eq!(fs::read_dir(&empty).unwrap().count(), 0);
+    }
+
+    #[test]
+    pub fn test_shell_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("input/sub")).unwrap();
+        fs::write(dir.path().join("input/a.txt"), "First file").unwrap();
+        fs::write(dir.path().join("input/sub/b.txt"), "Second file").unwrap();
+        fs::write(dir.path().join("added.txt"), "Added file").unwrap();
+        let archive = dir.path().join("archive.bar");
+        let back = tempfile::tempfile().unwrap();
+        let mut bar = Bar::pack(
+            dir.path().join("input"),
+            back,
+            "none".parse().unwrap(),
+            false,
+        )
+        .unwrap();
+        bar.save(&mut fs::File::create(&archive).unwrap(), false)
+            .unwrap();
+
+        let mut shell = Shell::new(Bar::unpack(&archive).unwrap());
+        let mut run = |line: &str| {
+            let mut out = Vec::new();
+            shell
+                .run_line(line, &mut out)
+                .map(|_| String::from_utf8(out).unwrap())
+        };
+        assert_eq!(run("ls").unwrap(), "a.txt\nsub/\n");
+        run("cd sub").unwrap();
+        assert_eq!(run("cat b.txt").unwrap(), "Second file\n");
+        assert_eq!(run("cat /a.txt").unwrap(), "First file\n");
+        assert!(matches!(
+            run("cd missing"),
+            Err(ShellErr::Bar(BarErr::NoEntry(_)))
+        ));
+        assert!(matches!(run("frobnicate"), Err(ShellErr::Usage(_))));
+        run(&format!("add {}", dir.path().join("added.txt").display())).unwrap();
+        run("note ../a.txt A note with spaces").unwrap();
+        run("cd ..").unwrap();
+        run("rm sub/b.txt").unwrap();
+        assert_eq!(run("ls sub").unwrap(), "");
+        run("save").unwrap();
+        assert!(!shell.run_line("exit", &mut Vec::new()).unwrap());
+        assert!(!shell.dirty);
+        drop(shell);
+
+        let mut bar = Bar::unpack(&archive).unwrap();
+        assert!(bar.entry("sub/b.txt").is_none());
+        assert_eq!(
+            bar.file("a.txt").unwrap().meta.borrow().note.as_deref(),
+            Some("A note with spaces")
+        );
+        let mut data = Vec::new();
+        std::io::copy(&mut bar.open("sub/added.txt").unwrap(), &mut data).unwrap();
+        assert_eq!(data, b"Added file");
+    }
+
+    #[test]
+    pub fn test_shell_failed_save() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("input")).unwrap();
+        fs::write(dir.path().join("input/a.txt"), "First file").unwrap();
+        fs::write(dir.path().join("added.txt"), "Added file").unwrap();
+        let archive = dir.path().join("archive.bar");
+        let back = tempfile::tempfile().unwrap();
+        let mut bar = Bar::pack(
+            dir.path().join("input"),
+            back,
+            "none".parse().unwrap(),
+            false,
+        )
+        .unwrap();
+        bar.save(&mut fs::File::create(&archive).unwrap(), false)
+            .unwrap();
+
+        //Adding a file inside a file fails when the archive is saved, and the add stays pending
+        let mut shell = Shell::new(Bar::unpack(&archive).unwrap());
+        let added = dir.path().join("added.txt");
+        shell
+            .run_line(
+                &format!("add {} a.txt/added.txt", added.display()),
+                &mut Vec::new(),
+            )
+            .unwrap();
+        assert!(shell.run_line("save", &mut Vec::new()).is_err());
+        assert!(shell.dirty);
+        assert_eq!(shell.added.len(), 1);
+        assert_eq!(shell.added[0].0, Path::new("a.txt/added.txt"));
+        assert_eq!(shell.added[0].1, b"Added file");
+        drop(shell);
+        assert!(Bar::unpack(&archive).unwrap().entry("added.txt").is_none());
+    }
+
+    #[test]
+    pub fn test_shell_pipeline() {
+        assert_eq!(
+            split_pipeline(r#"search "*.txt" | rm"#).unwrap(),
+            vec![vec!["search", "*.txt"], vec!["rm"]]
+        );
+        assert_eq!(
+            split_pipeline(r#"note a "with | pipe""#).unwrap(),
+            vec![vec!["note", "a", "with | pipe"]]
+        );
+        assert!(split_pipeline(r#"cat "a.txt"#).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("input/sub")).unwrap();
+        fs::write(dir.path().join("input/a.txt"), "First file").unwrap();
+        fs::write(dir.path().join("input/sub/b.txt"), "Second file").unwrap();
+        fs::write(dir.path().join("input/sub/c.md"), "Third file").unwrap();
+        let archive = dir.path().join("archive.bar");
+        let back = tempfile::tempfile().unwrap();
+        let mut bar = Bar::pack(
+            dir.path().join("input"),
+            back,
+            "none".parse().unwrap(),
+            false,
+        )
+        .unwrap();
+        bar.save(&mut fs::File::create(&archive).unwrap(), false)
+            .unwrap();
+
+        let mut shell = Shell::new(Bar::unpack(&archive).unwrap());
+        let mut run = |line: &str| {
+            let mut out = Vec::new();
+            shell
+                .run_line(line, &mut out)
+                .map(|_| String::from_utf8(out).unwrap())
+        };
+        assert_eq!(run(r#"search "*.txt""#).unwrap(), "a.txt\nsub/b.txt\n");
+        assert_eq!(run("search *.md | cat").unwrap(), "Third file\n");
+        let out = dir.path().join("out");
+        run(&format!("search sub/* | extract {}", out.display())).unwrap();
+        assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"Second file");
+        assert_eq!(fs::read(out.join("c.md")).unwrap(), b"Third file");
+
+        assert_eq!(run("search \"*.txt\" | rm").unwrap(), "");
+        assert!(matches!(run("rm"), Err(ShellErr::Usage(_))));
+        assert!(matches!(run("ls |"), Err(ShellErr::Usage(_))));
+        assert_eq!(run("ls").unwrap(), "sub/\n");
+        assert_eq!(run("ls sub").unwrap(), "c.md\n");
+    }
+
+    #[test]
+    pub fn test_format_time() {
+        assert_eq!(format_time(0, true), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(1_000_000_000, true), "2001-09-09T01:46:40Z");
+        assert_eq!(format_time(-86_400, true), "1969-12-31T00:00:00Z");
+    }
+
+    #[test]
+    pub fn test_search_json() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("music")).unwrap();
+        std::fs::write(dir.path().join("music/song.mp3"), "Song data").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "Unrelated").unwrap();
+
+        let back = std::io::Cursor::new(Vec::new());
+        let bar = Bar::pack(dir.path(), back, "none".parse().unwrap(), false).unwrap();
+        bar.file("music/song.mp3").unwrap().meta.borrow_mut().note = Some("Favorite".to_owned());
+
+        let mut scores = vec![];
+        search_dir(
+            bar.root(),
+            &mut scores,
+            "song.mp3",
+            1,
+            0,
+            path::PathBuf::from("/"),
+        );
+        let score = scores[0].1;
+        assert_eq!(
+            search_results_json(&scores),
+            serde_json::json!([{
+                "path": "/music/song.mp3",
+                "score": score,
+                "size": 9,
+                "note": "Favorite",
+            }])
+        );
+    }
+}
@@ -5,10 +5,27 @@ use aes::{
 };
 use indicatif::{ProgressBar, ProgressStyle};
 
-use crate::ar::BarResult;
+use crate::ar::{Bar, BarErr, BarResult};
 
 use std::io::{BufRead, BufWriter, Read, Seek, SeekFrom, Write};
 
+/// Get the key for a password by padding it with zero bytes or trimming it to 16 bytes
+pub fn password_key(password: &str) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    let len = password.len().min(key.len());
+    key[..len].copy_from_slice(&password.as_bytes()[..len]);
+    key
+}
+
+/// Get the key for the contents of a key file, which can be any length, from the first 16 bytes of their
+/// SHA-256 hash
+pub fn file_key(data: &[u8]) -> [u8; 16] {
+    use sha2::{Digest, Sha256};
+    let mut key = [0u8; 16];
+    key.copy_from_slice(&Sha256::digest(data)[..16]);
+    key
+}
+
 /// Encrypt a reader, writing the encrypted bytes to a writer
 pub fn encrypt(
     reader: &mut impl BufRead,
@@ -35,7 +52,7 @@ pub fn encrypt(
         for i in 0..10 {
             //Attempt to fill all buffers
             let read = unsafe {
-                reader.read(&mut std::slice::from_raw_parts_mut(
+                reader.read(std::slice::from_raw_parts_mut(
                     buf[i].as_mut_ptr() as *mut u8,
                     128,
                 ))?
@@ -90,7 +107,7 @@ pub fn decrypt(
         for i in 0..10 {
             //Attempt to fill all buffers
             let read = unsafe {
-                reader.read(&mut std::slice::from_raw_parts_mut(
+                reader.read(std::slice::from_raw_parts_mut(
                     buf[i].as_mut_ptr() as *mut u8,
                     128,
                 ))?
@@ -160,3 +177,64 @@ pub fn decrypt_in_place(ciphertxt: &mut (impl Read + Write + Seek), key: &[u8])
     }
     Ok(())
 }
+
+/// Decrypt an encrypted archive from a reader with `old_key` and write it to a writer encrypted with `new_key`.
+/// The decrypted archive is checked before it is encrypted again, returning [WrongKey](BarErr::WrongKey) if it
+/// can't be read or its hash doesn't match because `old_key` is not the key it was encrypted with. The decrypted
+/// archive is only held in memory and never written to disk, so memory use grows with the archive's size
+pub fn rotate_key(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    old_key: &[u8],
+    new_key: &[u8],
+    prog: bool,
+) -> BarResult<()> {
+    let mut plaintxt = Vec::new();
+    decrypt(reader, &mut plaintxt, old_key, prog)?;
+
+    match Bar::unpack_bytes(plaintxt.as_slice()).and_then(|mut bar| bar.verify_archive_hash()) {
+        Ok(()) | Err(BarErr::NoArchiveHash) => (),
+        Err(_) => return Err(BarErr::WrongKey),
+    }
+
+    encrypt(&mut plaintxt.as_slice(), writer, new_key, prog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    pub fn test_rotate_key() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "Secret file ".repeat(100)).unwrap();
+        let back = Cursor::new(Vec::new());
+        let mut bar = Bar::pack(dir.path(), back, "fast-gzip".parse().unwrap(), false).unwrap();
+        let mut archive = Vec::new();
+        bar.save(&mut Cursor::new(&mut archive), false).unwrap();
+
+        let (a, b) = (password_key("key A"), password_key("key B"));
+        let mut encrypted = Vec::new();
+        encrypt(&mut archive.as_slice(), &mut encrypted, &a, false).unwrap();
+        let mut rotated = Vec::new();
+        rotate_key(&mut encrypted.as_slice(), &mut rotated, &a, &b, false).unwrap();
+        assert_ne!(rotated, encrypted);
+
+        //The rotated archive only decrypts with the new key
+        assert!(matches!(
+            rotate_key(&mut rotated.as_slice(), &mut Vec::new(), &a, &b, false),
+            Err(BarErr::WrongKey)
+        ));
+        let mut decrypted = Vec::new();
+        decrypt(&mut rotated.as_slice(), &mut decrypted, &b, false).unwrap();
+        assert_eq!(decrypted, archive);
+        let mut bar = Bar::unpack_bytes(decrypted.as_slice()).unwrap();
+        let mut data = String::new();
+        bar.open("a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "Secret file ".repeat(100));
+    }
+}